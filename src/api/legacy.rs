@@ -0,0 +1,16 @@
+//! The original verb-in-path routes, kept for clients that predate `/v1`.
+
+use axum::{
+    routing::{delete, get, post, put},
+    Router,
+};
+
+use crate::{notes, AppState};
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/get/:id", get(notes::read_note))
+        .route("/create", post(notes::create_note))
+        .route("/update/:id", put(notes::update_note))
+        .route("/delete/:id", delete(notes::delete_note))
+}
@@ -0,0 +1,4 @@
+//! HTTP routers, one module per API version.
+
+pub mod legacy;
+pub mod v1;
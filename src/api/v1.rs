@@ -0,0 +1,328 @@
+use axum::{
+    routing::{delete, get, post, put},
+    Router,
+};
+
+use crate::{
+    auth, config::Features, events, history, keys, notebooks, notes, search, sharing, tags, trash,
+    webhooks, workspaces, AppState,
+};
+
+/// The `/v1` routes, leaving out those of the features not enabled.
+pub fn router(features: Features) -> Router<AppState> {
+    let mut router = Router::new()
+        .route("/auth/register", post(auth::register))
+        .route("/auth/login", post(auth::login))
+        .route("/auth/me", get(auth::me))
+        .route("/auth/keys", get(keys::list_keys).post(keys::create_key))
+        .route("/auth/keys/:id", delete(keys::revoke_key))
+        .route(
+            "/workspaces",
+            get(workspaces::list_workspaces).post(workspaces::create_workspace),
+        )
+        .route("/users", get(workspaces::list_users))
+        .route("/users/:id/admin", put(workspaces::set_admin))
+        .route("/notes", get(notes::list_notes).post(notes::create_note))
+        .route(
+            "/notes/:id",
+            get(notes::read_note)
+                .put(notes::update_note)
+                .patch(notes::patch_note)
+                .delete(notes::delete_note),
+        )
+        .route("/notes/:id/revisions", get(history::list_revisions))
+        .route(
+            "/notes/:id/revisions/:revision",
+            get(history::read_revision),
+        )
+        .route(
+            "/notes/:id/revisions/:revision/restore",
+            post(history::restore_revision),
+        )
+        .route("/notes/:id/diff", get(history::diff_revisions))
+        .route("/notes/:id/notebook", put(notebooks::move_note))
+        .route("/notes/:id/acl", get(sharing::list_grants))
+        .route(
+            "/notes/:id/acl/:username",
+            put(sharing::put_grant).delete(sharing::delete_grant),
+        )
+        .route(
+            "/notes/:id/links",
+            get(sharing::list_links).post(sharing::create_link),
+        )
+        .route("/notes/:id/links/:link_id", delete(sharing::delete_link))
+        .route("/shared", get(sharing::list_shared))
+        .route("/public/:token", get(sharing::read_public))
+        .route(
+            "/notebooks",
+            get(notebooks::list_notebooks).post(notebooks::create_notebook),
+        )
+        .route(
+            "/notebooks/:id",
+            get(notebooks::read_notebook)
+                .put(notebooks::update_notebook)
+                .delete(notebooks::delete_notebook),
+        )
+        .route("/notebooks/:id/contents", get(notebooks::notebook_contents))
+        .route("/notes/:id/tags", post(tags::add_tags))
+        .route("/notes/:id/tags/:tag", delete(tags::remove_tag))
+        .route("/tags", get(tags::list_tags))
+        .route("/tags/:tag/rename", post(tags::rename_tag))
+        .route("/search", get(search::search_notes))
+        .route("/trash", get(trash::list_trash))
+        .route("/trash/:id", delete(trash::purge_note))
+        .route("/trash/:id/restore", post(trash::restore_note));
+    if features.events {
+        router = router
+            .route("/events", get(events::stream_events))
+            .route("/events/ws", get(events::events_socket));
+    }
+    if features.webhooks {
+        router = router
+            .route(
+                "/webhooks",
+                get(webhooks::list_webhooks).post(webhooks::create_webhook),
+            )
+            .route("/webhooks/:id", delete(webhooks::delete_webhook))
+            .route("/webhooks/:id/deliveries", get(webhooks::list_deliveries))
+            .route(
+                "/webhooks/:id/deliveries/:delivery_id/redeliver",
+                post(webhooks::redeliver),
+            );
+    }
+    router
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{header, StatusCode};
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    use crate::{
+        patch::JSON_PATCH,
+        store::NoteStore,
+        test_util::{json_body, request, seeded_store, test_app, with_header},
+        NoteInput,
+    };
+
+    #[tokio::test]
+    async fn create() {
+        let store = seeded_store().await;
+        let response = test_app(store.clone())
+            .oneshot(request(
+                "POST",
+                "/v1/notes",
+                Some(json!({"title": "second", "note": "body"})),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(response.headers()[header::LOCATION], "/v1/notes/2");
+        let body = json_body(response).await;
+        assert_eq!(body["id"], 2);
+        assert_eq!(body["title"], "second");
+        assert_eq!(body["created_at"], body["updated_at"]);
+    }
+
+    #[tokio::test]
+    async fn read_update_delete() {
+        let store = seeded_store().await;
+        let app = test_app(store.clone());
+
+        let response = app
+            .clone()
+            .oneshot(request("GET", "/v1/notes/1", None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(json_body(response).await["title"], "test_title");
+
+        let response = app
+            .clone()
+            .oneshot(request(
+                "PUT",
+                "/v1/notes/1",
+                Some(json!({"title": "new", "note": "replaced"})),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let note = store.get(1).await.unwrap().unwrap();
+        assert_eq!(
+            NoteInput::from(&note),
+            NoteInput::new("new".into(), "replaced".into())
+        );
+
+        let response = app
+            .clone()
+            .oneshot(request("DELETE", "/v1/notes/1", None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        let response = app
+            .oneshot(request("GET", "/v1/notes/1", None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn list() {
+        let store = seeded_store().await;
+        store
+            .create(1, NoteInput::new("second".into(), "body".into()))
+            .await
+            .unwrap();
+        let app = test_app(store);
+
+        let response = app
+            .clone()
+            .oneshot(request("GET", "/v1/notes?limit=1&order=desc", None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let page = json_body(response).await;
+        assert_eq!(page["items"][0]["id"], 2);
+        let cursor = page["next_cursor"].as_str().unwrap();
+
+        let uri = format!("/v1/notes?limit=1&order=desc&cursor={}", cursor);
+        let page = json_body(app.oneshot(request("GET", &uri, None)).await.unwrap()).await;
+        assert_eq!(page["items"][0]["id"], 1);
+        assert_eq!(page["next_cursor"], serde_json::Value::Null);
+    }
+
+    #[tokio::test]
+    async fn list_rejects_bad_cursor() {
+        let response = test_app(seeded_store().await)
+            .oneshot(request("GET", "/v1/notes?cursor=garbage", None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn patch() {
+        let store = seeded_store().await;
+        let response = test_app(store.clone())
+            .oneshot(request(
+                "PATCH",
+                "/v1/notes/1",
+                Some(json!({"note": "patched"})),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = json_body(response).await;
+        assert_eq!(body["title"], "test_title");
+        assert_eq!(body["note"], "patched");
+    }
+
+    #[tokio::test]
+    async fn json_patch() {
+        let store = seeded_store().await;
+        let req = with_header(
+            request(
+                "PATCH",
+                "/v1/notes/1",
+                Some(json!([{"op": "replace", "path": "/title", "value": "patched"}])),
+            ),
+            header::CONTENT_TYPE,
+            JSON_PATCH,
+        );
+        let response = test_app(store.clone()).oneshot(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(store.get(1).await.unwrap().unwrap().title, "patched");
+
+        let req = with_header(
+            request(
+                "PATCH",
+                "/v1/notes/1",
+                Some(json!([{"op": "remove", "path": "/created_at"}])),
+            ),
+            header::CONTENT_TYPE,
+            JSON_PATCH,
+        );
+        let response = test_app(store).oneshot(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn conditional_requests() {
+        let app = test_app(seeded_store().await);
+        let response = app
+            .clone()
+            .oneshot(request("GET", "/v1/notes/1", None))
+            .await
+            .unwrap();
+        assert_eq!(response.headers()[header::ETAG], "\"1\"");
+
+        let req = with_header(
+            request("GET", "/v1/notes/1", None),
+            header::IF_NONE_MATCH,
+            "\"1\"",
+        );
+        let response = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+        let body = json!({"title": "new", "note": "body"});
+        let req = with_header(
+            request("PUT", "/v1/notes/1", Some(body.clone())),
+            header::IF_MATCH,
+            "\"0\"",
+        );
+        let response = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+
+        let req = with_header(
+            request("PUT", "/v1/notes/1", Some(body)),
+            header::IF_MATCH,
+            "\"1\"",
+        );
+        let response = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::ETAG], "\"2\"");
+
+        let req = with_header(
+            request("DELETE", "/v1/notes/1", None),
+            header::IF_MATCH,
+            "\"1\"",
+        );
+        let response = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+
+        let req = with_header(
+            request("DELETE", "/v1/notes/1", None),
+            header::IF_MATCH,
+            "*",
+        );
+        let response = app.oneshot(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    }
+
+    #[tokio::test]
+    async fn server_managed_fields_are_ignored() {
+        let store = seeded_store().await;
+        let original = store.get(1).await.unwrap().unwrap();
+        let response = test_app(store.clone())
+            .oneshot(request(
+                "PUT",
+                "/v1/notes/1",
+                Some(json!({
+                    "id": 7,
+                    "title": "new",
+                    "note": "body",
+                    "created_at": "2000-01-01T00:00:00Z",
+                })),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let note = store.get(1).await.unwrap().unwrap();
+        assert_eq!(note.id, 1);
+        assert_eq!(note.created_at, original.created_at);
+        assert!(note.updated_at >= original.updated_at);
+        assert_eq!(store.get(7).await.unwrap(), None);
+    }
+}
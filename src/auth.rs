@@ -0,0 +1,553 @@
+//! User accounts and bearer token authentication.
+//!
+//! Passwords are stored as argon2 hashes. Logging in returns a signed JWT
+//! that [`AuthUser`] checks on every request to a protected route, along
+//! with the API keys of [`crate::keys`].
+
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use async_trait::async_trait;
+use std::{collections::BTreeSet, sync::OnceLock};
+
+use axum::{
+    extract::{FromRequestParts, State},
+    http::{header, request::Parts, HeaderName, StatusCode},
+    response::IntoResponse,
+};
+use chrono::{DateTime, Duration, Utc};
+use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{
+    error::ApiError,
+    extract::Json,
+    keys::{hash_key, Scope, KEY_PREFIX},
+    store::{NoteStore, StoreError},
+    workspaces::DEFAULT_WORKSPACE,
+    AppState,
+};
+
+pub const MIN_PASSWORD_LEN: usize = 8;
+pub const DEFAULT_TOKEN_TTL: Duration = Duration::hours(24);
+pub const API_KEY_HEADER: HeaderName = HeaderName::from_static("x-api-key");
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, ToSchema)]
+pub struct User {
+    pub id: u32,
+    pub username: String,
+    pub created_at: DateTime<Utc>,
+    /// Admins manage the users of their workspace.
+    pub is_admin: bool,
+}
+
+/// Signing keys and lifetime of issued tokens. Tokens are only accepted by
+/// the workspace they were issued for.
+#[derive(Clone)]
+pub struct Auth {
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+    token_ttl: Duration,
+    workspace: String,
+}
+
+impl Auth {
+    /// Tokens are signed with HMAC-SHA256 using `secret`.
+    pub fn new(secret: &[u8]) -> Self {
+        Self {
+            encoding: EncodingKey::from_secret(secret),
+            decoding: DecodingKey::from_secret(secret),
+            token_ttl: DEFAULT_TOKEN_TTL,
+            workspace: DEFAULT_WORKSPACE.into(),
+        }
+    }
+
+    /// The same keys, issuing and accepting tokens for `workspace`.
+    pub fn for_workspace(&self, workspace: &str) -> Self {
+        Self {
+            workspace: workspace.into(),
+            ..self.clone()
+        }
+    }
+
+    pub fn with_token_ttl(mut self, token_ttl: Duration) -> Self {
+        self.token_ttl = token_ttl;
+        self
+    }
+
+    pub fn issue(&self, user: &User) -> Result<Token, ApiError> {
+        let now = Utc::now();
+        let expires_at = now + self.token_ttl;
+        let claims = Claims {
+            sub: user.id,
+            username: user.username.clone(),
+            workspace: self.workspace.clone(),
+            iat: now.timestamp(),
+            exp: expires_at.timestamp(),
+        };
+        let token = jsonwebtoken::encode(&Header::default(), &claims, &self.encoding)
+            .map_err(|e| ApiError::Internal(format!("failed to sign token: {}", e)))?;
+        Ok(Token {
+            access_token: token,
+            token_type: "Bearer".into(),
+            expires_at,
+        })
+    }
+
+    pub fn verify(&self, token: &str) -> Result<Claims, ApiError> {
+        jsonwebtoken::decode::<Claims>(token, &self.decoding, &Validation::default())
+            .ok()
+            .map(|data| data.claims)
+            .filter(|claims| claims.workspace == self.workspace)
+            .ok_or_else(|| ApiError::Unauthorized("Invalid or expired token".into()))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Claims {
+    /// The user id.
+    pub sub: u32,
+    pub username: String,
+    pub workspace: String,
+    pub iat: i64,
+    pub exp: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, ToSchema)]
+pub struct Token {
+    pub access_token: String,
+    pub token_type: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// The user a request is authenticated as, taken from its
+/// `Authorization: Bearer` header or, for API keys, from `X-Api-Key`.
+/// Bearer tokens starting with [`KEY_PREFIX`] are API keys too. Rejects with
+/// 401 when there are no credentials or they are invalid.
+///
+/// Users that logged in may do anything; API keys only what their scopes
+/// allow, which handlers check with [`AuthUser::require`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuthUser {
+    pub id: u32,
+    pub username: String,
+    pub scopes: BTreeSet<Scope>,
+}
+
+impl AuthUser {
+    /// Rejects with 403 unless the request may act within `scope`.
+    pub fn require(&self, scope: Scope) -> Result<(), ApiError> {
+        if self.scopes.contains(&scope) || self.scopes.contains(&Scope::Admin) {
+            Ok(())
+        } else {
+            Err(ApiError::Forbidden(format!(
+                "This API key lacks the `{}` scope",
+                scope
+            )))
+        }
+    }
+
+    async fn from_api_key(state: &AppState, key: &str) -> Result<Self, ApiError> {
+        let invalid = || ApiError::Unauthorized("Invalid API key".into());
+        let key = state
+            .store
+            .use_api_key(&hash_key(key))
+            .await?
+            .ok_or_else(invalid)?;
+        let user = state
+            .store
+            .get_user(key.user_id)
+            .await?
+            .ok_or_else(invalid)?;
+        Ok(AuthUser {
+            id: user.id,
+            username: user.username,
+            scopes: key.scopes,
+        })
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for AuthUser {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let header = |name| {
+            parts
+                .headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::trim)
+        };
+        if let Some(key) = header(API_KEY_HEADER) {
+            return Self::from_api_key(state, key).await;
+        }
+        let token = header(header::AUTHORIZATION)
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(str::trim)
+            .ok_or_else(|| ApiError::Unauthorized("Missing bearer token".into()))?;
+        if token.starts_with(KEY_PREFIX) {
+            return Self::from_api_key(state, token).await;
+        }
+        let claims = state.auth.verify(token)?;
+        Ok(AuthUser {
+            id: claims.sub,
+            username: claims.username,
+            scopes: Scope::ALL.into(),
+        })
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+/// Usernames are 3 to 32 lowercase letters, digits, `-` or `_`.
+pub(crate) fn normalize_username(username: &str) -> Result<String, ApiError> {
+    let username = username.trim().to_lowercase();
+    let valid = (3..=32).contains(&username.len())
+        && username
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        return Err(ApiError::Unprocessable(
+            "Username must be 3 to 32 letters, digits, `-` or `_`".into(),
+        ));
+    }
+    Ok(username)
+}
+
+/// Hashing is deliberately slow, so it runs off the async workers.
+async fn hash_password(password: String) -> Result<String, ApiError> {
+    tokio::task::spawn_blocking(move || {
+        let salt = SaltString::generate(&mut OsRng);
+        Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+    })
+    .await
+    .map_err(|e| ApiError::Internal(e.to_string()))?
+    .map_err(|e| ApiError::Internal(format!("failed to hash password: {}", e)))
+}
+
+async fn verify_password(password: String, hash: String) -> Result<bool, ApiError> {
+    tokio::task::spawn_blocking(move || {
+        PasswordHash::new(&hash)
+            .map(|hash| {
+                Argon2::default()
+                    .verify_password(password.as_bytes(), &hash)
+                    .is_ok()
+            })
+            .unwrap_or(false)
+    })
+    .await
+    .map_err(|e| ApiError::Internal(e.to_string()))
+}
+
+/// A hash of no one's password, verified against when logging in as a user
+/// that does not exist so that it takes as long as a wrong password.
+async fn dummy_hash() -> Result<String, ApiError> {
+    static HASH: OnceLock<String> = OnceLock::new();
+    if let Some(hash) = HASH.get() {
+        return Ok(hash.clone());
+    }
+    let hash = hash_password("not anyone's password".into()).await?;
+    Ok(HASH.get_or_init(|| hash).clone())
+}
+
+/// Validates `credentials` for a new user, returning the normalized
+/// username and the password hash to store.
+pub(crate) async fn hash_credentials(
+    credentials: Credentials,
+) -> Result<(String, String), ApiError> {
+    let username = normalize_username(&credentials.username)?;
+    if credentials.password.chars().count() < MIN_PASSWORD_LEN {
+        return Err(ApiError::Unprocessable(format!(
+            "Password must be at least {} characters",
+            MIN_PASSWORD_LEN
+        )));
+    }
+    let hash = hash_password(credentials.password).await?;
+    Ok((username, hash))
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/auth/register",
+    tag = "auth",
+    request_body = Credentials,
+    responses(
+        (status = 201, description = "The new user", body = User),
+        (status = 409, description = "Username taken", body = ErrorBody),
+        (status = 422, description = "Invalid input", body = ErrorBody),
+    ),
+    security(()),
+)]
+pub async fn register(
+    State(state): State<AppState>,
+    Json(payload): Json<Credentials>,
+) -> Result<impl IntoResponse, ApiError> {
+    let (username, hash) = hash_credentials(payload).await?;
+    let user = state.store.create_user(&username, &hash, false).await?;
+    Ok((StatusCode::CREATED, Json(user)))
+}
+
+/// What [`bootstrap_admin`] found.
+#[derive(Debug, PartialEq)]
+pub enum Bootstrap {
+    /// The admin was created and took over `claimed` notes.
+    Created { user: User, claimed: usize },
+    /// A user of that name was there already, and was left as it is.
+    Existing(User),
+}
+
+/// Creates the admin of the default workspace `store` at startup, who takes
+/// over the notes written before there were users. This is not left to
+/// registration, where anyone taking the name first would become admin; a
+/// user already holding the name is not promoted.
+pub async fn bootstrap_admin(
+    store: &dyn NoteStore,
+    credentials: Credentials,
+) -> Result<Bootstrap, ApiError> {
+    let (username, hash) = hash_credentials(credentials).await?;
+    match store.create_user(&username, &hash, true).await {
+        Ok(user) => {
+            let claimed = store.claim_unowned(user.id).await?;
+            Ok(Bootstrap::Created { user, claimed })
+        }
+        Err(StoreError::UsernameTaken) => {
+            let (user, _) = store
+                .user_credentials(&username)
+                .await?
+                .ok_or_else(|| ApiError::Internal(format!("user {username} vanished")))?;
+            Ok(Bootstrap::Existing(user))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/auth/login",
+    tag = "auth",
+    request_body = Credentials,
+    responses(
+        (status = 200, description = "A bearer token for the user", body = Token),
+        (status = 401, description = "Wrong username or password", body = ErrorBody),
+    ),
+    security(()),
+)]
+pub async fn login(
+    State(state): State<AppState>,
+    Json(payload): Json<Credentials>,
+) -> Result<Json<Token>, ApiError> {
+    let invalid = || ApiError::Unauthorized("Invalid username or password".into());
+    let username = payload.username.trim().to_lowercase();
+    let Some((user, hash)) = state.store.user_credentials(&username).await? else {
+        // Otherwise unknown usernames would be told apart by how fast
+        // they are turned down.
+        verify_password(payload.password, dummy_hash().await?).await?;
+        return Err(invalid());
+    };
+    if !verify_password(payload.password, hash).await? {
+        return Err(invalid());
+    }
+    Ok(Json(state.auth.issue(&user)?))
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/auth/me",
+    tag = "auth",
+    responses(
+        (status = 200, description = "The authenticated user", body = User),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+    ),
+)]
+pub async fn me(State(state): State<AppState>, user: AuthUser) -> Result<Json<User>, ApiError> {
+    let user = state
+        .store
+        .get_user(user.id)
+        .await?
+        .ok_or_else(|| ApiError::Unauthorized("User no longer exists".into()))?;
+    Ok(Json(user))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{header, StatusCode};
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::test_util::{json_body, request, seeded_store, test_app, token, with_header};
+
+    fn user() -> User {
+        User {
+            id: 7,
+            username: "alice".into(),
+            created_at: Utc::now(),
+            is_admin: false,
+        }
+    }
+
+    #[test]
+    fn token_round_trip() {
+        let auth = Auth::new(b"secret");
+        let token = auth.issue(&user()).unwrap();
+        let claims = auth.verify(&token.access_token).unwrap();
+        assert_eq!(claims.sub, 7);
+        assert_eq!(claims.username, "alice");
+
+        let other = Auth::new(b"other secret");
+        assert!(other.verify(&token.access_token).is_err());
+        let expired = auth
+            .clone()
+            .with_token_ttl(Duration::hours(-1))
+            .issue(&user())
+            .unwrap();
+        assert!(auth.verify(&expired.access_token).is_err());
+        let team = auth.for_workspace("team");
+        assert!(team.verify(&token.access_token).is_err());
+        let token = team.issue(&user()).unwrap();
+        assert_eq!(team.verify(&token.access_token).unwrap().workspace, "team");
+    }
+
+    #[test]
+    fn usernames() {
+        assert_eq!(normalize_username(" Alice_1 ").unwrap(), "alice_1");
+        assert!(normalize_username("al").is_err());
+        assert!(normalize_username("has space").is_err());
+    }
+
+    #[tokio::test]
+    async fn register_and_login() {
+        let app = test_app(seeded_store().await);
+        let credentials = json!({"username": "Bob", "password": "correct horse"});
+        let response = app
+            .clone()
+            .oneshot(request(
+                "POST",
+                "/v1/auth/register",
+                Some(credentials.clone()),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(json_body(response).await["username"], "bob");
+
+        let response = app
+            .clone()
+            .oneshot(request(
+                "POST",
+                "/v1/auth/register",
+                Some(credentials.clone()),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        let response = app
+            .clone()
+            .oneshot(request(
+                "POST",
+                "/v1/auth/login",
+                Some(json!({"username": "bob", "password": "wrong password"})),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = app
+            .clone()
+            .oneshot(request("POST", "/v1/auth/login", Some(credentials)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let token = json_body(response).await["access_token"]
+            .as_str()
+            .unwrap()
+            .to_owned();
+
+        let req = with_header(
+            request("GET", "/v1/auth/me", None),
+            header::AUTHORIZATION,
+            &format!("Bearer {}", token),
+        );
+        let response = app.oneshot(req).await.unwrap();
+        assert_eq!(json_body(response).await["id"], 2);
+    }
+
+    #[tokio::test]
+    async fn bootstrap_admin() {
+        use crate::auth::{bootstrap_admin, Bootstrap, Credentials};
+
+        let store = crate::store::MemoryStore::new();
+        let credentials = |username: &str| Credentials {
+            username: username.into(),
+            password: "correct horse".into(),
+        };
+        store.create_user("bob", "hash", false).await.unwrap();
+        match bootstrap_admin(&store, credentials(" Carol "))
+            .await
+            .unwrap()
+        {
+            Bootstrap::Created { user, .. } => {
+                assert_eq!(user.username, "carol");
+                assert!(user.is_admin);
+            }
+            other => panic!("unexpected {other:?}"),
+        }
+        match bootstrap_admin(&store, credentials("carol")).await.unwrap() {
+            Bootstrap::Existing(user) => assert!(user.is_admin),
+            other => panic!("unexpected {other:?}"),
+        }
+        // Registering the name first does not make anyone an admin.
+        match bootstrap_admin(&store, credentials("bob")).await.unwrap() {
+            Bootstrap::Existing(user) => assert!(!user.is_admin),
+            other => panic!("unexpected {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn notes_are_private() {
+        let app = test_app(seeded_store().await);
+        let mut anonymous = request("GET", "/v1/notes/1", None);
+        anonymous.headers_mut().remove(header::AUTHORIZATION);
+        let response = app.clone().oneshot(anonymous).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.headers()[header::WWW_AUTHENTICATE], "Bearer");
+
+        let as_bob = |req| {
+            with_header(
+                req,
+                header::AUTHORIZATION,
+                &format!("Bearer {}", token(2, "bob")),
+            )
+        };
+        let response = app
+            .clone()
+            .oneshot(as_bob(request("GET", "/v1/notes/1", None)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = app
+            .clone()
+            .oneshot(as_bob(request("DELETE", "/v1/notes/1", None)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = app
+            .oneshot(as_bob(request("GET", "/v1/notes", None)))
+            .await
+            .unwrap();
+        assert_eq!(json_body(response).await["items"], json!([]));
+    }
+}
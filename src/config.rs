@@ -0,0 +1,826 @@
+//! Server configuration.
+//!
+//! Settings are layered: the defaults are overridden by a TOML file, which
+//! is overridden by `NOTES_*` environment variables, which are overridden by
+//! command-line flags. The file is the one named by `--config` or
+//! `NOTES_CONFIG`, or `notes.toml` in the working directory if there is one.
+//! Every setting and its variable and flag is listed in [`SETTINGS`].
+
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use serde::Deserialize;
+
+pub const CONFIG_ENV: &str = "NOTES_CONFIG";
+pub const CONFIG_FLAG: &str = "--config";
+const DEFAULT_CONFIG_FILE: &str = "notes.toml";
+/// A hundred years. Far larger values overflow `chrono::Duration`, or the
+/// date when subtracted from the current time.
+const MAX_RETENTION_DAYS: i64 = 36500;
+
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub listen: Listen,
+    pub storage: Storage,
+    pub log: Log,
+    pub limits: Limits,
+    pub features: Features,
+    pub auth: AuthConfig,
+    pub trash: Trash,
+    pub shutdown: Shutdown,
+    pub webhooks: Webhooks,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Listen {
+    pub address: IpAddr,
+    pub port: u16,
+}
+
+impl Default for Listen {
+    fn default() -> Self {
+        Self {
+            address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port: 3000,
+        }
+    }
+}
+
+impl Listen {
+    pub fn socket_addr(&self) -> SocketAddr {
+        SocketAddr::new(self.address, self.port)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Storage {
+    pub backend: Backend,
+    /// Database of the default workspace.
+    pub path: PathBuf,
+    /// Directory holding the databases of the other workspaces.
+    pub workspaces_dir: PathBuf,
+}
+
+impl Default for Storage {
+    fn default() -> Self {
+        Self {
+            backend: Backend::Sqlite,
+            path: "notes.db".into(),
+            workspaces_dir: "workspaces".into(),
+        }
+    }
+}
+
+/// Where notes are kept. `Memory` loses everything on restart and is meant
+/// for trying the API out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    Sqlite,
+    Memory,
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sqlite" => Ok(Backend::Sqlite),
+            "memory" => Ok(Backend::Memory),
+            _ => Err("expected `sqlite` or `memory`".into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Log {
+    pub level: LogLevel,
+    pub format: LogFormat,
+}
+
+/// `Pretty` is for reading in a terminal, `Json` is one object per line for
+/// log collectors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Pretty,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pretty" => Ok(LogFormat::Pretty),
+            "json" => Ok(LogFormat::Json),
+            _ => Err("expected `pretty` or `json`".into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LogLevel::Error => "error",
+            LogLevel::Warn => "warn",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+            LogLevel::Trace => "trace",
+        }
+    }
+}
+
+impl FromStr for LogLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(LogLevel::Error),
+            "warn" => Ok(LogLevel::Warn),
+            "info" => Ok(LogLevel::Info),
+            "debug" => Ok(LogLevel::Debug),
+            "trace" => Ok(LogLevel::Trace),
+            _ => Err("expected `error`, `warn`, `info`, `debug` or `trace`".into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    /// Largest request body accepted, in bytes.
+    pub body_bytes: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            body_bytes: 2 * 1024 * 1024,
+        }
+    }
+}
+
+/// Parts of the API that can be switched off. Routes of a disabled feature
+/// are not served at all.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Features {
+    /// `/openapi.json` and `/docs`.
+    pub docs: bool,
+    /// The change feed under `/v1/events`.
+    pub events: bool,
+    /// `/v1/webhooks` and the sending of webhooks.
+    pub webhooks: bool,
+    /// The unversioned `/get`, `/create`, `/update` and `/delete` routes.
+    pub legacy_routes: bool,
+    /// Prometheus metrics at `/metrics`.
+    pub metrics: bool,
+}
+
+impl Default for Features {
+    fn default() -> Self {
+        Self {
+            docs: true,
+            events: true,
+            webhooks: true,
+            legacy_routes: true,
+            metrics: true,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// Key signing tokens. Without one a random key is used, so tokens do
+    /// not survive a restart.
+    pub jwt_secret: Option<String>,
+    /// Admin of the default workspace created at startup, who takes over the
+    /// notes written before there were users.
+    pub bootstrap_admin: Option<String>,
+    /// Password the bootstrap admin is created with.
+    pub bootstrap_password: Option<String>,
+}
+
+impl fmt::Debug for AuthConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuthConfig")
+            .field(
+                "jwt_secret",
+                &self.jwt_secret.as_ref().map(|_| "<redacted>"),
+            )
+            .field("bootstrap_admin", &self.bootstrap_admin)
+            .field(
+                "bootstrap_password",
+                &self.bootstrap_password.as_ref().map(|_| "<redacted>"),
+            )
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Trash {
+    /// Days a deleted note stays in the trash before it is purged.
+    pub retention_days: i64,
+}
+
+impl Default for Trash {
+    fn default() -> Self {
+        Self { retention_days: 30 }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Shutdown {
+    /// Seconds requests in flight are given to finish once a shutdown is
+    /// asked for.
+    pub timeout_secs: u64,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self { timeout_secs: 10 }
+    }
+}
+
+impl Shutdown {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout_secs)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Webhooks {
+    /// Lets webhooks target loopback and private addresses, which are
+    /// otherwise turned down so that users cannot reach the server's own
+    /// network through them.
+    pub allow_private_targets: bool,
+}
+
+/// A setting that can be given as an environment variable or a flag.
+pub struct Setting {
+    /// Name of the setting in the file, as `section.key`.
+    pub key: &'static str,
+    pub env: &'static str,
+    pub flag: &'static str,
+    pub help: &'static str,
+    set: fn(&mut Config, &str) -> Result<(), String>,
+}
+
+pub const SETTINGS: &[Setting] = &[
+    Setting {
+        key: "listen.address",
+        env: "NOTES_LISTEN_ADDRESS",
+        flag: "--address",
+        help: "IP address to listen on",
+        set: |config, value| {
+            config.listen.address = parse(value)?;
+            Ok(())
+        },
+    },
+    Setting {
+        key: "listen.port",
+        env: "NOTES_LISTEN_PORT",
+        flag: "--port",
+        help: "port to listen on",
+        set: |config, value| {
+            config.listen.port = parse(value)?;
+            Ok(())
+        },
+    },
+    Setting {
+        key: "storage.backend",
+        env: "NOTES_STORAGE_BACKEND",
+        flag: "--storage",
+        help: "`sqlite` or `memory`",
+        set: |config, value| {
+            config.storage.backend = parse(value)?;
+            Ok(())
+        },
+    },
+    Setting {
+        key: "storage.path",
+        env: "NOTES_DB_PATH",
+        flag: "--db-path",
+        help: "database of the default workspace",
+        set: |config, value| {
+            config.storage.path = value.into();
+            Ok(())
+        },
+    },
+    Setting {
+        key: "storage.workspaces_dir",
+        env: "NOTES_WORKSPACES_DIR",
+        flag: "--workspaces-dir",
+        help: "directory of the other workspaces' databases",
+        set: |config, value| {
+            config.storage.workspaces_dir = value.into();
+            Ok(())
+        },
+    },
+    Setting {
+        key: "log.level",
+        env: "NOTES_LOG_LEVEL",
+        flag: "--log-level",
+        help: "`error`, `warn`, `info`, `debug` or `trace`",
+        set: |config, value| {
+            config.log.level = parse(value)?;
+            Ok(())
+        },
+    },
+    Setting {
+        key: "log.format",
+        env: "NOTES_LOG_FORMAT",
+        flag: "--log-format",
+        help: "`pretty` or `json`",
+        set: |config, value| {
+            config.log.format = parse(value)?;
+            Ok(())
+        },
+    },
+    Setting {
+        key: "limits.body_bytes",
+        env: "NOTES_BODY_LIMIT",
+        flag: "--body-limit",
+        help: "largest request body accepted, in bytes",
+        set: |config, value| {
+            config.limits.body_bytes = parse(value)?;
+            Ok(())
+        },
+    },
+    Setting {
+        key: "features.docs",
+        env: "NOTES_FEATURE_DOCS",
+        flag: "--docs",
+        help: "serve /openapi.json and /docs",
+        set: |config, value| {
+            config.features.docs = parse(value)?;
+            Ok(())
+        },
+    },
+    Setting {
+        key: "features.events",
+        env: "NOTES_FEATURE_EVENTS",
+        flag: "--events",
+        help: "serve the change feed",
+        set: |config, value| {
+            config.features.events = parse(value)?;
+            Ok(())
+        },
+    },
+    Setting {
+        key: "features.webhooks",
+        env: "NOTES_FEATURE_WEBHOOKS",
+        flag: "--webhooks",
+        help: "serve and send webhooks",
+        set: |config, value| {
+            config.features.webhooks = parse(value)?;
+            Ok(())
+        },
+    },
+    Setting {
+        key: "features.legacy_routes",
+        env: "NOTES_FEATURE_LEGACY_ROUTES",
+        flag: "--legacy-routes",
+        help: "serve the unversioned routes",
+        set: |config, value| {
+            config.features.legacy_routes = parse(value)?;
+            Ok(())
+        },
+    },
+    Setting {
+        key: "features.metrics",
+        env: "NOTES_FEATURE_METRICS",
+        flag: "--metrics",
+        help: "serve /metrics",
+        set: |config, value| {
+            config.features.metrics = parse(value)?;
+            Ok(())
+        },
+    },
+    Setting {
+        key: "auth.jwt_secret",
+        env: "NOTES_JWT_SECRET",
+        flag: "--jwt-secret",
+        help: "key signing tokens",
+        set: |config, value| {
+            config.auth.jwt_secret = Some(value.into());
+            Ok(())
+        },
+    },
+    Setting {
+        key: "auth.bootstrap_admin",
+        env: "NOTES_BOOTSTRAP_ADMIN",
+        flag: "--bootstrap-admin",
+        help: "admin of the default workspace created at startup",
+        set: |config, value| {
+            config.auth.bootstrap_admin = Some(value.into());
+            Ok(())
+        },
+    },
+    Setting {
+        key: "auth.bootstrap_password",
+        env: "NOTES_BOOTSTRAP_PASSWORD",
+        flag: "--bootstrap-password",
+        help: "password the bootstrap admin is created with",
+        set: |config, value| {
+            config.auth.bootstrap_password = Some(value.into());
+            Ok(())
+        },
+    },
+    Setting {
+        key: "trash.retention_days",
+        env: "NOTES_TRASH_RETENTION_DAYS",
+        flag: "--trash-retention-days",
+        help: "days before deleted notes are purged",
+        set: |config, value| {
+            config.trash.retention_days = parse(value)?;
+            Ok(())
+        },
+    },
+    Setting {
+        key: "shutdown.timeout_secs",
+        env: "NOTES_SHUTDOWN_TIMEOUT",
+        flag: "--shutdown-timeout",
+        help: "seconds to let requests finish on shutdown",
+        set: |config, value| {
+            config.shutdown.timeout_secs = parse(value)?;
+            Ok(())
+        },
+    },
+    Setting {
+        key: "webhooks.allow_private_targets",
+        env: "NOTES_WEBHOOKS_ALLOW_PRIVATE_TARGETS",
+        flag: "--webhooks-allow-private-targets",
+        help: "let webhooks target loopback and private addresses",
+        set: |config, value| {
+            config.webhooks.allow_private_targets = parse(value)?;
+            Ok(())
+        },
+    },
+];
+
+fn parse<T>(value: &str) -> Result<T, String>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    value.parse().map_err(|e| format!("`{value}`: {e}"))
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+    /// A variable or flag, named by `origin`, with a value `key` does not
+    /// take.
+    Value {
+        origin: String,
+        key: &'static str,
+        message: String,
+    },
+    /// An unknown flag or one missing its value.
+    Flag(String),
+    /// A setting that parsed but is out of range.
+    Invalid {
+        key: &'static str,
+        message: String,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, e) => write!(f, "cannot read {}: {}", path.display(), e),
+            ConfigError::Parse(path, e) => write!(f, "{}: {}", path.display(), e),
+            ConfigError::Value {
+                origin,
+                key,
+                message,
+            } => write!(f, "{origin} ({key}): invalid value {message}"),
+            ConfigError::Flag(message) => write!(f, "{message}, see --help"),
+            ConfigError::Invalid { key, message } => write!(f, "{key}: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    /// Loads the configuration from the command-line arguments `args`, the
+    /// program name excluded, and the environment variables `env` returns.
+    pub fn load<I>(args: I, env: impl Fn(&str) -> Option<String>) -> Result<Self, ConfigError>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let flags = flags(args)?;
+        let file = flags
+            .iter()
+            .rev()
+            .find(|(flag, _)| flag == CONFIG_FLAG)
+            .map(|(_, path)| PathBuf::from(path))
+            .or_else(|| env(CONFIG_ENV).map(PathBuf::from));
+        let mut config = match file {
+            Some(path) => Self::from_file(&path)?,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                Self::from_file(Path::new(DEFAULT_CONFIG_FILE))?
+            }
+            None => Self::default(),
+        };
+        for setting in SETTINGS {
+            if let Some(value) = env(setting.env) {
+                config.set(setting, setting.env, &value)?;
+            }
+        }
+        for (flag, value) in &flags {
+            if let Some(setting) = SETTINGS.iter().find(|s| s.flag == flag) {
+                config.set(setting, flag, value)?;
+            }
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let text =
+            std::fs::read_to_string(path).map_err(|e| ConfigError::Read(path.to_owned(), e))?;
+        toml::from_str(&text).map_err(|e| ConfigError::Parse(path.to_owned(), e))
+    }
+
+    fn set(&mut self, setting: &Setting, origin: &str, value: &str) -> Result<(), ConfigError> {
+        (setting.set)(self, value).map_err(|message| ConfigError::Value {
+            origin: origin.to_owned(),
+            key: setting.key,
+            message,
+        })
+    }
+
+    /// Checks what the types of the settings alone do not.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |key, message: &str| {
+            Err(ConfigError::Invalid {
+                key,
+                message: message.to_owned(),
+            })
+        };
+        if self.storage.backend == Backend::Sqlite {
+            if self.storage.path.as_os_str().is_empty() {
+                return invalid("storage.path", "must not be empty");
+            }
+            if self.storage.workspaces_dir.as_os_str().is_empty() {
+                return invalid("storage.workspaces_dir", "must not be empty");
+            }
+        }
+        if self.limits.body_bytes == 0 {
+            return invalid("limits.body_bytes", "must be at least 1");
+        }
+        if self.auth.jwt_secret.as_deref() == Some("") {
+            return invalid("auth.jwt_secret", "must not be empty");
+        }
+        if let Some(username) = &self.auth.bootstrap_admin {
+            if crate::auth::normalize_username(username).is_err() {
+                return invalid(
+                    "auth.bootstrap_admin",
+                    "must be 3 to 32 letters, digits, `-` or `_`",
+                );
+            }
+        }
+        match (&self.auth.bootstrap_admin, &self.auth.bootstrap_password) {
+            (Some(_), None) => {
+                return invalid(
+                    "auth.bootstrap_password",
+                    "must be set with a bootstrap admin",
+                )
+            }
+            (None, Some(_)) => {
+                return invalid(
+                    "auth.bootstrap_admin",
+                    "must be set with a bootstrap password",
+                )
+            }
+            (Some(_), Some(password))
+                if password.chars().count() < crate::auth::MIN_PASSWORD_LEN =>
+            {
+                return invalid(
+                    "auth.bootstrap_password",
+                    &format!(
+                        "must be at least {} characters",
+                        crate::auth::MIN_PASSWORD_LEN
+                    ),
+                );
+            }
+            _ => {}
+        }
+        if self.trash.retention_days < 1 {
+            return invalid("trash.retention_days", "must be at least 1");
+        }
+        if self.trash.retention_days > MAX_RETENTION_DAYS {
+            return invalid(
+                "trash.retention_days",
+                &format!("must be at most {MAX_RETENTION_DAYS}"),
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Splits `args` into flags and their values, given as `--flag value` or
+/// `--flag=value`.
+fn flags(args: impl IntoIterator<Item = String>) -> Result<Vec<(String, String)>, ConfigError> {
+    let mut args = args.into_iter();
+    let mut flags = Vec::new();
+    while let Some(arg) = args.next() {
+        let (flag, value) = match arg.split_once('=') {
+            Some((flag, value)) => (flag.to_owned(), Some(value.to_owned())),
+            None => (arg, None),
+        };
+        if flag != CONFIG_FLAG && !SETTINGS.iter().any(|s| s.flag == flag) {
+            return Err(ConfigError::Flag(format!("unknown flag `{flag}`")));
+        }
+        let value = match value.or_else(|| args.next()) {
+            Some(value) => value,
+            None => return Err(ConfigError::Flag(format!("missing value for `{flag}`"))),
+        };
+        flags.push((flag, value));
+    }
+    Ok(flags)
+}
+
+/// The `--help` text.
+pub fn usage() -> String {
+    let mut usage = format!(
+        "Usage: axum-notes [{CONFIG_FLAG} FILE] [FLAG VALUE]...\n\n\
+         Settings come from FILE ({CONFIG_ENV}, or {DEFAULT_CONFIG_FILE} if present), then \
+         environment variables, then flags.\n\n"
+    );
+    for setting in SETTINGS {
+        usage.push_str(&format!(
+            "  {:<24} {:<28} {:<24} {}\n",
+            setting.flag, setting.env, setting.key, setting.help
+        ));
+    }
+    usage
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn load(args: &[&str], env: &[(&str, &str)]) -> Result<Config, ConfigError> {
+        let env: HashMap<String, String> = env
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        Config::load(args.iter().map(|a| a.to_string()), |name| {
+            env.get(name).cloned()
+        })
+    }
+
+    fn config_file(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("notes-{}-{name}.toml", std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn layers() {
+        let path = config_file(
+            "layers",
+            "[listen]\nport = 4000\naddress = \"0.0.0.0\"\n\
+             [storage]\nbackend = \"memory\"\n\
+             [log]\nformat = \"json\"\n\
+             [features]\nwebhooks = false\n",
+        );
+        let file = path.to_str().unwrap();
+
+        let config = load(&["--config", file], &[]).unwrap();
+        assert_eq!(config.listen.socket_addr(), "0.0.0.0:4000".parse().unwrap());
+        assert_eq!(config.storage.backend, Backend::Memory);
+        assert!(!config.features.webhooks);
+        assert!(config.features.events);
+        assert_eq!(config.log.format, LogFormat::Json);
+        assert_eq!(config.trash, Trash::default());
+
+        // Variables override the file, and flags override both.
+        let env = [
+            (CONFIG_ENV, file),
+            ("NOTES_LISTEN_PORT", "5000"),
+            ("NOTES_SHUTDOWN_TIMEOUT", "3"),
+            ("NOTES_LOG_LEVEL", "debug"),
+            ("NOTES_FEATURE_WEBHOOKS", "true"),
+        ];
+        let config = load(&[], &env).unwrap();
+        assert_eq!(config.listen.port, 5000);
+        assert_eq!(config.log.level, LogLevel::Debug);
+        assert_eq!(config.shutdown.timeout(), Duration::from_secs(3));
+        assert!(config.features.webhooks);
+        let config = load(
+            &[
+                "--port",
+                "6000",
+                "--log-level=warn",
+                "--log-format",
+                "pretty",
+            ],
+            &env,
+        )
+        .unwrap();
+        assert_eq!(config.listen.port, 6000);
+        assert_eq!(config.log.level, LogLevel::Warn);
+        assert_eq!(config.log.format, LogFormat::Pretty);
+        assert_eq!(config.listen.address, "0.0.0.0".parse::<IpAddr>().unwrap());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn errors() {
+        let message = |result: Result<Config, ConfigError>| result.unwrap_err().to_string();
+        assert_eq!(
+            message(load(&[], &[("NOTES_LISTEN_PORT", "http")])),
+            "NOTES_LISTEN_PORT (listen.port): invalid value `http`: invalid digit found in string"
+        );
+        assert_eq!(
+            message(load(&["--storage", "redis"], &[])),
+            "--storage (storage.backend): invalid value `redis`: expected `sqlite` or `memory`"
+        );
+        assert_eq!(
+            message(load(&["--prot", "1"], &[])),
+            "unknown flag `--prot`, see --help"
+        );
+        assert_eq!(
+            message(load(&["--port"], &[])),
+            "missing value for `--port`, see --help"
+        );
+        assert_eq!(
+            message(load(&["--body-limit", "0"], &[])),
+            "limits.body_bytes: must be at least 1"
+        );
+        assert_eq!(
+            message(load(&[], &[("NOTES_TRASH_RETENTION_DAYS", "0")])),
+            "trash.retention_days: must be at least 1"
+        );
+        assert_eq!(
+            message(load(
+                &["--trash-retention-days", "9223372036854775807"],
+                &[]
+            )),
+            "trash.retention_days: must be at most 36500"
+        );
+        assert_eq!(
+            message(load(&["--bootstrap-admin", "a b"], &[])),
+            "auth.bootstrap_admin: must be 3 to 32 letters, digits, `-` or `_`"
+        );
+        assert_eq!(
+            message(load(&["--bootstrap-admin", "carol"], &[])),
+            "auth.bootstrap_password: must be set with a bootstrap admin"
+        );
+        assert_eq!(
+            message(load(
+                &["--bootstrap-admin", "carol"],
+                &[("NOTES_BOOTSTRAP_PASSWORD", "short")]
+            )),
+            "auth.bootstrap_password: must be at least 8 characters"
+        );
+
+        let path = config_file("typo", "[listen]\nprot = 4000\n");
+        let error = message(load(&["--config", path.to_str().unwrap()], &[]));
+        assert!(error.contains("unknown field `prot`"), "{error}");
+        std::fs::remove_file(path).unwrap();
+        let error = message(load(&["--config", "/nonexistent/notes.toml"], &[]));
+        assert!(error.starts_with("cannot read /nonexistent/notes.toml"));
+    }
+
+    #[test]
+    fn secret_is_redacted() {
+        let config = load(&["--jwt-secret", "hunter2"], &[]).unwrap();
+        assert_eq!(config.auth.jwt_secret.as_deref(), Some("hunter2"));
+        assert!(!format!("{config:?}").contains("hunter2"));
+    }
+}
@@ -0,0 +1,154 @@
+//! Entity tags derived from note revisions and the `If-Match` /
+//! `If-None-Match` preconditions that use them.
+
+use async_trait::async_trait;
+use axum::{
+    extract::FromRequestParts,
+    http::{header, request::Parts, HeaderName, HeaderValue},
+};
+
+use crate::error::ApiError;
+
+/// Strong entity tag for a note revision.
+pub fn etag(revision: u64) -> HeaderValue {
+    HeaderValue::from_str(&format!("\"{}\"", revision)).expect("etag is a valid header value")
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntityTag {
+    pub weak: bool,
+    pub opaque: String,
+}
+
+/// Parsed value of an `If-Match` or `If-None-Match` header.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ETagCondition {
+    Any,
+    Tags(Vec<EntityTag>),
+}
+
+impl ETagCondition {
+    fn parse(value: &HeaderValue) -> Result<Self, ApiError> {
+        let invalid = || ApiError::BadRequest("Malformed entity tag".into());
+        let value = value.to_str().map_err(|_| invalid())?.trim();
+        if value == "*" {
+            return Ok(ETagCondition::Any);
+        }
+        value
+            .split(',')
+            .map(|tag| {
+                let tag = tag.trim();
+                let (weak, tag) = match tag.strip_prefix("W/") {
+                    Some(rest) => (true, rest),
+                    None => (false, tag),
+                };
+                let opaque = tag
+                    .strip_prefix('"')
+                    .and_then(|tag| tag.strip_suffix('"'))
+                    .ok_or_else(invalid)?;
+                Ok(EntityTag {
+                    weak,
+                    opaque: opaque.to_owned(),
+                })
+            })
+            .collect::<Result<_, _>>()
+            .map(ETagCondition::Tags)
+    }
+
+    /// Strong comparison, as required for `If-Match`.
+    pub fn matches_strong(&self, revision: u64) -> bool {
+        let current = revision.to_string();
+        match self {
+            ETagCondition::Any => true,
+            ETagCondition::Tags(tags) => tags.iter().any(|t| !t.weak && t.opaque == current),
+        }
+    }
+
+    /// Weak comparison, as required for `If-None-Match`.
+    pub fn matches_weak(&self, revision: u64) -> bool {
+        let current = revision.to_string();
+        match self {
+            ETagCondition::Any => true,
+            ETagCondition::Tags(tags) => tags.iter().any(|t| t.opaque == current),
+        }
+    }
+}
+
+fn condition(parts: &Parts, name: HeaderName) -> Result<Option<ETagCondition>, ApiError> {
+    parts
+        .headers
+        .get(name)
+        .map(ETagCondition::parse)
+        .transpose()
+}
+
+/// The request's `If-Match` header, if any.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IfMatch(pub Option<ETagCondition>);
+
+impl IfMatch {
+    /// Fails with 412 unless the header is absent or matches `revision`.
+    pub fn check(&self, revision: u64) -> Result<(), ApiError> {
+        match &self.0 {
+            Some(condition) if !condition.matches_strong(revision) => Err(
+                ApiError::PreconditionFailed("If-Match does not match the current revision".into()),
+            ),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for IfMatch {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(IfMatch(condition(parts, header::IF_MATCH)?))
+    }
+}
+
+/// The request's `If-None-Match` header, if any.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IfNoneMatch(pub Option<ETagCondition>);
+
+impl IfNoneMatch {
+    /// Whether the client's cached copy of `revision` is still current.
+    pub fn is_fresh(&self, revision: u64) -> bool {
+        self.0
+            .as_ref()
+            .is_some_and(|condition| condition.matches_weak(revision))
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for IfNoneMatch {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(IfNoneMatch(condition(parts, header::IF_NONE_MATCH)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(value: &str) -> ETagCondition {
+        ETagCondition::parse(&HeaderValue::from_str(value).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn strong_and_weak_comparison() {
+        let condition = parse(r#"W/"1", "2""#);
+        assert!(!condition.matches_strong(1));
+        assert!(condition.matches_weak(1));
+        assert!(condition.matches_strong(2));
+        assert!(!condition.matches_weak(3));
+        assert!(parse("*").matches_strong(7));
+    }
+
+    #[test]
+    fn rejects_unquoted_tags() {
+        assert!(ETagCondition::parse(&HeaderValue::from_static("1")).is_err());
+    }
+}
@@ -0,0 +1,670 @@
+//! Change feed: an event for every note created, updated, deleted or
+//! restored from the trash through the API, streamed over Server-Sent
+//! Events and WebSocket.
+//!
+//! Every workspace has a feed of its own. Subscribers only receive events
+//! for notes they own or have been granted a role on, optionally narrowed
+//! down to some notes or tags.
+
+use std::{
+    collections::{BTreeSet, VecDeque},
+    str::FromStr,
+    sync::Mutex,
+};
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        State,
+    },
+    http::HeaderMap,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        Response,
+    },
+};
+use chrono::{DateTime, Utc};
+use futures_util::{stream, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_stream::wrappers::BroadcastStream;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    auth::AuthUser,
+    error::ApiError,
+    extract::Query,
+    keys::Scope,
+    metrics,
+    notes::role,
+    tags::{normalize, parse_list},
+    AppState, Note,
+};
+
+/// How many of the latest events are kept for clients resuming with
+/// `Last-Event-ID`.
+pub const HISTORY_LEN: usize = 1000;
+/// How many events a slow subscriber may fall behind before it misses some.
+const CHANNEL_CAPACITY: usize = 256;
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, ToSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum EventKind {
+    Created,
+    Updated,
+    Deleted,
+    /// Brought back from the trash.
+    Restored,
+}
+
+impl EventKind {
+    pub const ALL: [EventKind; 4] = [
+        EventKind::Created,
+        EventKind::Updated,
+        EventKind::Deleted,
+        EventKind::Restored,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EventKind::Created => "created",
+            EventKind::Updated => "updated",
+            EventKind::Deleted => "deleted",
+            EventKind::Restored => "restored",
+        }
+    }
+}
+
+impl FromStr for EventKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        EventKind::ALL
+            .into_iter()
+            .find(|kind| kind.as_str() == s)
+            .ok_or_else(|| format!("unknown event `{}`", s))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct NoteEvent {
+    /// Increases by one with every event of the workspace.
+    pub id: u64,
+    pub kind: EventKind,
+    pub at: DateTime<Utc>,
+    /// The note after the change; for deletions, as it was before.
+    pub note: Note,
+}
+
+#[derive(Default)]
+struct History {
+    last_id: u64,
+    events: VecDeque<NoteEvent>,
+}
+
+/// Broadcasts the events of one workspace and remembers the latest ones.
+pub struct EventFeed {
+    sender: broadcast::Sender<NoteEvent>,
+    history: Mutex<History>,
+    /// Held by the write whose event is next, see [`EventFeed::publisher`].
+    /// `None` when the order of the events does not matter.
+    writing: Option<tokio::sync::Mutex<()>>,
+}
+
+/// Publishes the event of one write. Taken before the write and dropped
+/// after publishing, so that events come out in the order the writes were
+/// made.
+pub struct Publisher<'a> {
+    feed: &'a EventFeed,
+    _writing: Option<tokio::sync::MutexGuard<'a, ()>>,
+}
+
+impl Publisher<'_> {
+    pub fn publish(&self, kind: EventKind, note: &Note) {
+        self.feed.publish(kind, note);
+    }
+}
+
+impl Default for EventFeed {
+    fn default() -> Self {
+        Self {
+            sender: broadcast::channel(CHANNEL_CAPACITY).0,
+            history: Mutex::default(),
+            writing: Some(tokio::sync::Mutex::default()),
+        }
+    }
+}
+
+impl EventFeed {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A feed whose events may come out of the order of their writes, so
+    /// that writes do not wait on each other. For a workspace whose feed is
+    /// not served, where only webhooks hear of the events.
+    pub fn unordered() -> Self {
+        Self {
+            writing: None,
+            ..Self::default()
+        }
+    }
+
+    /// Waits for the writes in progress to publish their events, unless the
+    /// feed is unordered.
+    pub async fn publisher(&self) -> Publisher<'_> {
+        let writing = match &self.writing {
+            Some(writing) => Some(metrics::lock("events", writing).await),
+            None => None,
+        };
+        Publisher {
+            feed: self,
+            _writing: writing,
+        }
+    }
+
+    fn publish(&self, kind: EventKind, note: &Note) {
+        let mut history = self.history.lock().expect("event history poisoned");
+        history.last_id += 1;
+        let event = NoteEvent {
+            id: history.last_id,
+            kind,
+            at: Utc::now(),
+            note: note.clone(),
+        };
+        if history.events.len() == HISTORY_LEN {
+            history.events.pop_front();
+        }
+        history.events.push_back(event.clone());
+        // Nobody listening is fine.
+        let _ = self.sender.send(event);
+    }
+
+    /// Subscribes to events published from now on, returning along with the
+    /// receiver the remembered events after `last_id`, if given.
+    pub fn subscribe(
+        &self,
+        last_id: Option<u64>,
+    ) -> (Vec<NoteEvent>, broadcast::Receiver<NoteEvent>) {
+        // Holding the lock keeps events from slipping in between the two.
+        let history = self.history.lock().expect("event history poisoned");
+        let backlog = match last_id {
+            Some(last_id) => history
+                .events
+                .iter()
+                .filter(|event| event.id > last_id)
+                .cloned()
+                .collect(),
+            None => Vec::new(),
+        };
+        (backlog, self.sender.subscribe())
+    }
+}
+
+/// The notes and tags a subscriber is interested in. Without any, every
+/// event matches.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct Subscriptions {
+    pub notes: BTreeSet<u32>,
+    pub tags: BTreeSet<String>,
+}
+
+impl Subscriptions {
+    pub fn matches(&self, event: &NoteEvent) -> bool {
+        (self.notes.is_empty() && self.tags.is_empty())
+            || self.notes.contains(&event.note.id)
+            || !self.tags.is_disjoint(&event.note.tags)
+    }
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[serde(deny_unknown_fields)]
+pub struct EventParams {
+    /// Comma separated note ids.
+    pub note_id: Option<String>,
+    /// Comma separated tags.
+    pub tag: Option<String>,
+}
+
+impl EventParams {
+    fn subscriptions(&self) -> Result<Subscriptions, ApiError> {
+        let notes = match &self.note_id {
+            Some(ids) => ids
+                .split(',')
+                .map(|id| {
+                    id.trim()
+                        .parse()
+                        .map_err(|_| ApiError::BadRequest(format!("Invalid note id `{}`", id)))
+                })
+                .collect::<Result<_, _>>()?,
+            None => BTreeSet::new(),
+        };
+        let tags = match &self.tag {
+            Some(tags) => parse_list(tags)?,
+            None => BTreeSet::new(),
+        };
+        Ok(Subscriptions { notes, tags })
+    }
+}
+
+/// Whether `user` may see `event`, which is the case if they have any role
+/// on its note.
+async fn visible(state: &AppState, user: &AuthUser, event: &NoteEvent) -> bool {
+    matches!(role(state, user, &event.note).await, Ok(Some(_)))
+}
+
+fn sse_event(event: &NoteEvent) -> Result<Event, axum::Error> {
+    Event::default()
+        .id(event.id.to_string())
+        .event(event.kind.as_str())
+        .json_data(event)
+}
+
+/// Streams events as Server-Sent Events. A client reconnecting with
+/// `Last-Event-ID` first gets the events it missed, as far as they are still
+/// remembered. The stream ends when the client falls too far behind, so that
+/// it reconnects and catches up that way.
+#[utoipa::path(
+    get,
+    path = "/v1/events",
+    tag = "events",
+    params(EventParams, ("Last-Event-ID" = Option<u64>, Header, description = "Replay the remembered events after this one first")),
+    responses(
+        (status = 200, description = "Server-Sent Events, one per change", content_type = "text/event-stream", body = NoteEvent),
+        (status = 400, description = "Malformed request", body = ErrorBody),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Not allowed for the caller", body = ErrorBody),
+    ),
+)]
+pub async fn stream_events(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Query(params): Query<EventParams>,
+    headers: HeaderMap,
+) -> Result<Sse<impl Stream<Item = Result<Event, axum::Error>>>, ApiError> {
+    user.require(Scope::Read)?;
+    let subscriptions = params.subscriptions()?;
+    let last_id = headers
+        .get("last-event-id")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse().ok());
+    let (backlog, receiver) = state.events.subscribe(last_id);
+    let live = BroadcastStream::new(receiver)
+        .take_while(|event| std::future::ready(event.is_ok()))
+        .filter_map(|event| std::future::ready(event.ok()));
+    let events = stream::iter(backlog).chain(live).filter_map(move |event| {
+        let state = state.clone();
+        let user = user.clone();
+        let wanted = subscriptions.matches(&event);
+        async move { (wanted && visible(&state, &user, &event).await).then(|| sse_event(&event)) }
+    });
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+/// What a WebSocket client sends to change its subscriptions.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "action", rename_all = "lowercase", deny_unknown_fields)]
+enum Command {
+    Subscribe {
+        note_id: Option<u32>,
+        tag: Option<String>,
+    },
+    Unsubscribe {
+        note_id: Option<u32>,
+        tag: Option<String>,
+    },
+}
+
+impl Subscriptions {
+    fn apply(&mut self, command: Command) -> Result<(), String> {
+        let (subscribe, note_id, tag) = match command {
+            Command::Subscribe { note_id, tag } => (true, note_id, tag),
+            Command::Unsubscribe { note_id, tag } => (false, note_id, tag),
+        };
+        let tag = match tag {
+            Some(tag) => Some(normalize(&tag).ok_or_else(|| format!("Invalid tag `{}`", tag))?),
+            None => None,
+        };
+        if subscribe {
+            self.notes.extend(note_id);
+            self.tags.extend(tag);
+        } else {
+            if let Some(id) = note_id {
+                self.notes.remove(&id);
+            }
+            if let Some(tag) = tag {
+                self.tags.remove(&tag);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Streams events over a WebSocket. Clients start out with the
+/// subscriptions given in the query string and change them by sending
+/// `{"action": "subscribe", "note_id": 1}` or `{"action": "unsubscribe",
+/// "tag": "work"}`; every change is acknowledged with the resulting
+/// subscriptions.
+#[utoipa::path(
+    get,
+    path = "/v1/events/ws",
+    tag = "events",
+    params(EventParams),
+    responses(
+        (status = 101, description = "Upgraded to a WebSocket carrying `NoteEvent`s"),
+        (status = 400, description = "Malformed request", body = ErrorBody),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Not allowed for the caller", body = ErrorBody),
+    ),
+)]
+pub async fn events_socket(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Query(params): Query<EventParams>,
+    upgrade: WebSocketUpgrade,
+) -> Result<Response, ApiError> {
+    user.require(Scope::Read)?;
+    let subscriptions = params.subscriptions()?;
+    Ok(upgrade.on_upgrade(move |socket| serve_socket(socket, state, user, subscriptions)))
+}
+
+async fn serve_socket(
+    mut socket: WebSocket,
+    state: AppState,
+    user: AuthUser,
+    mut subscriptions: Subscriptions,
+) {
+    let (_, mut receiver) = state.events.subscribe(None);
+    loop {
+        let reply = tokio::select! {
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    let applied = serde_json::from_str(&text)
+                        .map_err(|e| e.to_string())
+                        .and_then(|command| subscriptions.apply(command));
+                    match applied {
+                        Ok(()) => json!({ "subscriptions": subscriptions }),
+                        Err(e) => json!({ "error": e }),
+                    }
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => continue,
+            },
+            event = receiver.recv() => match event {
+                Ok(event) => {
+                    if !subscriptions.matches(&event) || !visible(&state, &user, &event).await {
+                        continue;
+                    }
+                    json!(event)
+                }
+                Err(RecvError::Lagged(missed)) => json!({ "error": format!("Missed {} events", missed) }),
+                Err(RecvError::Closed) => break,
+            },
+        };
+        if socket.send(Message::Text(reply.to_string())).await.is_err() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use axum::http::{header, HeaderName};
+    use futures_util::SinkExt;
+    use serde_json::json;
+    use tokio_tungstenite::tungstenite::{client::IntoClientRequest, Message};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        store::NoteStore,
+        test_util::{request, seeded_store, test_app, token, with_header, ALICE},
+        NoteInput,
+    };
+
+    fn note(id: u32, tags: &[&str]) -> Note {
+        let now = Utc::now();
+        Note {
+            id,
+            owner_id: 1,
+            title: "t".into(),
+            note: "n".into(),
+            notebook_id: None,
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            revision: 1,
+            created_at: now,
+            updated_at: now,
+            deleted_at: None,
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn publishes_in_write_order() {
+        let feed = Arc::new(EventFeed::new());
+        let store = seeded_store().await;
+        let (_, mut receiver) = feed.subscribe(None);
+        let writes: Vec<_> = (0..20)
+            .map(|i| {
+                let (feed, store) = (feed.clone(), store.clone());
+                tokio::spawn(async move {
+                    let events = feed.publisher().await;
+                    let input = NoteInput::new("t".into(), i.to_string());
+                    let note = store.update(1, input, None).await.unwrap().unwrap();
+                    tokio::task::yield_now().await;
+                    events.publish(EventKind::Updated, &note);
+                })
+            })
+            .collect();
+        for write in writes {
+            write.await.unwrap();
+        }
+        for revision in 2..22 {
+            assert_eq!(receiver.recv().await.unwrap().note.revision, revision);
+        }
+    }
+
+    #[tokio::test]
+    async fn unordered_feeds_do_not_wait() {
+        let feed = EventFeed::unordered();
+        let first = feed.publisher().await;
+        let second = feed.publisher().await;
+        second.publish(EventKind::Updated, &note(1, &[]));
+        first.publish(EventKind::Created, &note(1, &[]));
+        let (backlog, _) = feed.subscribe(Some(0));
+        assert_eq!(backlog[0].kind, EventKind::Updated);
+    }
+
+    #[test]
+    fn feed_resumes_after_last_id() {
+        let feed = EventFeed::new();
+        feed.publish(EventKind::Created, &note(1, &[]));
+        feed.publish(EventKind::Updated, &note(1, &[]));
+        let (backlog, mut receiver) = feed.subscribe(Some(1));
+        assert_eq!(backlog.len(), 1);
+        assert_eq!(backlog[0].id, 2);
+        assert_eq!(backlog[0].kind, EventKind::Updated);
+        assert!(feed.subscribe(None).0.is_empty());
+
+        feed.publish(EventKind::Deleted, &note(1, &[]));
+        assert_eq!(receiver.try_recv().unwrap().id, 3);
+
+        for _ in 0..HISTORY_LEN {
+            feed.publish(EventKind::Updated, &note(1, &[]));
+        }
+        let (backlog, _) = feed.subscribe(Some(0));
+        assert_eq!(backlog.len(), HISTORY_LEN);
+        assert_eq!(backlog[0].id, 4);
+    }
+
+    #[test]
+    fn subscriptions() {
+        let event = |id, tags| NoteEvent {
+            id: 1,
+            kind: EventKind::Updated,
+            at: Utc::now(),
+            note: note(id, tags),
+        };
+        let mut subscriptions = Subscriptions::default();
+        assert!(subscriptions.matches(&event(1, &[])));
+
+        let subscribe = r#"{"action": "subscribe", "tag": "Work"}"#;
+        subscriptions
+            .apply(serde_json::from_str(subscribe).unwrap())
+            .unwrap();
+        assert!(subscriptions.matches(&event(1, &["work", "home"])));
+        assert!(!subscriptions.matches(&event(1, &["home"])));
+
+        let subscribe = r#"{"action": "subscribe", "note_id": 2}"#;
+        subscriptions
+            .apply(serde_json::from_str(subscribe).unwrap())
+            .unwrap();
+        assert!(subscriptions.matches(&event(2, &[])));
+        let unsubscribe = r#"{"action": "unsubscribe", "tag": "work"}"#;
+        subscriptions
+            .apply(serde_json::from_str(unsubscribe).unwrap())
+            .unwrap();
+        assert!(!subscriptions.matches(&event(1, &["work"])));
+
+        let invalid = r#"{"action": "subscribe", "tag": "no spaces"}"#;
+        assert!(subscriptions
+            .apply(serde_json::from_str(invalid).unwrap())
+            .is_err());
+    }
+
+    async fn next_event<S>(events: &mut S) -> String
+    where
+        S: futures_util::Stream<Item = Result<axum::body::Bytes, axum::Error>> + Unpin,
+    {
+        let event = events.next().await.unwrap().unwrap();
+        String::from_utf8(event.to_vec()).unwrap()
+    }
+
+    async fn next_message<S>(socket: &mut S) -> serde_json::Value
+    where
+        S: futures_util::Stream<Item = Result<Message, tokio_tungstenite::tungstenite::Error>>
+            + Unpin,
+    {
+        let text = socket.next().await.unwrap().unwrap().into_text().unwrap();
+        serde_json::from_str(&text).unwrap()
+    }
+
+    #[tokio::test]
+    async fn event_stream() {
+        let store = seeded_store().await;
+        store.create_user("bob", "not a hash", false).await.unwrap();
+        let app = test_app(store);
+        for title in ["one", "two"] {
+            app.clone()
+                .oneshot(request(
+                    "POST",
+                    "/v1/notes",
+                    Some(json!({"title": title, "note": "n"})),
+                ))
+                .await
+                .unwrap();
+        }
+        let last_event_id = HeaderName::from_static("last-event-id");
+        let response = app
+            .clone()
+            .oneshot(with_header(
+                request("GET", "/v1/events", None),
+                last_event_id.clone(),
+                "1",
+            ))
+            .await
+            .unwrap();
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/event-stream"
+        );
+        let mut events = response.into_body().into_data_stream();
+        let event = next_event(&mut events).await;
+        assert!(event.contains("id: 2\n"), "{event}");
+        assert!(event.contains("event: created\n"), "{event}");
+        assert!(event.contains(r#""title":"two""#), "{event}");
+
+        app.clone()
+            .oneshot(request("DELETE", "/v1/notes/1", None))
+            .await
+            .unwrap();
+        let event = next_event(&mut events).await;
+        assert!(event.contains("id: 3\nevent: deleted\n"), "{event}");
+
+        // Bob sees none of Alice's notes.
+        let response = app
+            .oneshot(with_header(
+                with_header(
+                    request("GET", "/v1/events", None),
+                    header::AUTHORIZATION,
+                    &format!("Bearer {}", token(2, "bob")),
+                ),
+                last_event_id,
+                "0",
+            ))
+            .await
+            .unwrap();
+        let mut events = response.into_body().into_data_stream();
+        let next = tokio::time::timeout(Duration::from_millis(100), events.next()).await;
+        assert!(next.is_err());
+    }
+
+    #[tokio::test]
+    async fn event_socket() {
+        let app = test_app(seeded_store().await);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = app.clone();
+        tokio::spawn(async move { axum::serve(listener, server).await.unwrap() });
+
+        let mut req = format!("ws://{addr}/v1/events/ws?note_id=7")
+            .into_client_request()
+            .unwrap();
+        let bearer = format!("Bearer {}", token(ALICE, "alice"));
+        req.headers_mut()
+            .insert(header::AUTHORIZATION, bearer.parse().unwrap());
+        let (mut socket, _) = tokio_tungstenite::connect_async(req).await.unwrap();
+        socket
+            .send(Message::Text(
+                r#"{"action": "subscribe", "tag": "work"}"#.into(),
+            ))
+            .await
+            .unwrap();
+        let ack = next_message(&mut socket).await;
+        assert_eq!(
+            ack["subscriptions"],
+            json!({"notes": [7], "tags": ["work"]})
+        );
+
+        app.clone()
+            .oneshot(request(
+                "PUT",
+                "/v1/notes/1",
+                Some(json!({"title": "untagged", "note": "n"})),
+            ))
+            .await
+            .unwrap();
+        app.clone()
+            .oneshot(request(
+                "POST",
+                "/v1/notes/1/tags",
+                Some(json!({"tags": ["work"]})),
+            ))
+            .await
+            .unwrap();
+        let event = next_message(&mut socket).await;
+        assert_eq!(event["id"], 2);
+        assert_eq!(event["kind"], "updated");
+        assert_eq!(event["note"]["tags"], json!(["work"]));
+
+        socket
+            .send(Message::Text(r#"{"action": "subscribe"#.into()))
+            .await
+            .unwrap();
+        assert!(next_message(&mut socket).await["error"].is_string());
+    }
+}
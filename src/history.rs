@@ -0,0 +1,319 @@
+//! Immutable note revisions: listing, diffing and restoring them.
+
+use axum::{extract::State, response::Response};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use similar::{ChangeTag, TextDiff};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    auth::AuthUser,
+    error::ApiError,
+    etag::IfMatch,
+    events::EventKind,
+    extract::{Json, Path, Query},
+    keys::Scope,
+    notes::{expected_revision, find_note, precondition, tagged},
+    sharing::Role,
+    AppState, Note, NoteInput,
+};
+
+/// The content of a note as written by one create, update or restore.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, ToSchema)]
+pub struct Revision {
+    pub note_id: u32,
+    pub revision: u64,
+    pub title: String,
+    pub note: String,
+    /// When this revision was written.
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<&Note> for Revision {
+    fn from(note: &Note) -> Self {
+        Self {
+            note_id: note.id,
+            revision: note.revision,
+            title: note.title.clone(),
+            note: note.note.clone(),
+            created_at: note.updated_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LineOp {
+    Equal,
+    Insert,
+    Delete,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, ToSchema)]
+pub struct DiffLine {
+    pub op: LineOp,
+    pub text: String,
+}
+
+/// Line level difference between two revisions of a note.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, ToSchema)]
+pub struct RevisionDiff {
+    pub from: u64,
+    pub to: u64,
+    pub title_changed: bool,
+    pub lines: Vec<DiffLine>,
+    /// The same changes in unified diff format.
+    pub unified: String,
+}
+
+impl RevisionDiff {
+    pub fn between(from: &Revision, to: &Revision) -> Self {
+        let diff = TextDiff::from_lines(&from.note, &to.note);
+        let lines = diff
+            .iter_all_changes()
+            .map(|change| DiffLine {
+                op: match change.tag() {
+                    ChangeTag::Equal => LineOp::Equal,
+                    ChangeTag::Insert => LineOp::Insert,
+                    ChangeTag::Delete => LineOp::Delete,
+                },
+                text: change.value().trim_end_matches('\n').to_owned(),
+            })
+            .collect();
+        let unified = diff
+            .unified_diff()
+            .header(
+                &format!("revision {}", from.revision),
+                &format!("revision {}", to.revision),
+            )
+            .to_string();
+        Self {
+            from: from.revision,
+            to: to.revision,
+            title_changed: from.title != to.title,
+            lines,
+            unified,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[serde(deny_unknown_fields)]
+pub struct DiffParams {
+    pub from: u64,
+    /// Defaults to the current revision.
+    pub to: Option<u64>,
+}
+
+async fn find_revision(state: &AppState, id: u32, revision: u64) -> Result<Revision, ApiError> {
+    state.store.revision(id, revision).await?.ok_or_else(|| {
+        ApiError::NotFound(format!("Revision {} of note {} not found", revision, id))
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/notes/{id}/revisions",
+    tag = "history",
+    params(("id" = u32, Path, description = "Note id")),
+    responses(
+        (status = 200, description = "Every revision of the note, oldest first", body = [Revision]),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Not allowed for the caller", body = ErrorBody),
+        (status = 404, description = "Note not found", body = ErrorBody),
+    ),
+)]
+pub async fn list_revisions(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Path(id): Path<u32>,
+) -> Result<Json<Vec<Revision>>, ApiError> {
+    user.require(Scope::Read)?;
+    find_note(&state, &user, id, Role::Viewer).await?;
+    let revisions = state.store.revisions(id).await?;
+    if revisions.is_empty() {
+        return Err(ApiError::note_not_found(id));
+    }
+    Ok(Json(revisions))
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/notes/{id}/revisions/{revision}",
+    tag = "history",
+    params(("id" = u32, Path, description = "Note id"), ("revision" = u64, Path, description = "Revision number")),
+    responses(
+        (status = 200, description = "The revision", body = Revision),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Not allowed for the caller", body = ErrorBody),
+        (status = 404, description = "Note or revision not found", body = ErrorBody),
+    ),
+)]
+pub async fn read_revision(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Path((id, revision)): Path<(u32, u64)>,
+) -> Result<Json<Revision>, ApiError> {
+    user.require(Scope::Read)?;
+    find_note(&state, &user, id, Role::Viewer).await?;
+    Ok(Json(find_revision(&state, id, revision).await?))
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/notes/{id}/diff",
+    tag = "history",
+    params(("id" = u32, Path, description = "Note id"), DiffParams),
+    responses(
+        (status = 200, description = "Differences between the two revisions", body = RevisionDiff),
+        (status = 400, description = "Malformed request", body = ErrorBody),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Not allowed for the caller", body = ErrorBody),
+        (status = 404, description = "Note or revision not found", body = ErrorBody),
+    ),
+)]
+pub async fn diff_revisions(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Path(id): Path<u32>,
+    Query(params): Query<DiffParams>,
+) -> Result<Json<RevisionDiff>, ApiError> {
+    user.require(Scope::Read)?;
+    let head = find_note(&state, &user, id, Role::Viewer).await?.revision;
+    let to = params.to.unwrap_or(head);
+    let from = find_revision(&state, id, params.from).await?;
+    let to = find_revision(&state, id, to).await?;
+    Ok(Json(RevisionDiff::between(&from, &to)))
+}
+
+/// Writes the content of an old revision as the new head of the note.
+#[utoipa::path(
+    post,
+    path = "/v1/notes/{id}/revisions/{revision}/restore",
+    tag = "history",
+    params(("id" = u32, Path, description = "Note id"), ("revision" = u64, Path, description = "Revision number"), ("If-Match" = Option<String>, Header, description = "Only write if the note is still at this `ETag`")),
+    responses(
+        (status = 200, description = "The note with the old content as a new revision", body = Note, headers(("ETag" = String))),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Not allowed for the caller", body = ErrorBody),
+        (status = 404, description = "Note or revision not found", body = ErrorBody),
+        (status = 412, description = "`If-Match` does not match the current revision", body = ErrorBody),
+    ),
+)]
+pub async fn restore_revision(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Path((id, revision)): Path<(u32, u64)>,
+    if_match: IfMatch,
+) -> Result<Response, ApiError> {
+    user.require(Scope::Write)?;
+    let expected = expected_revision(&state, &user, id, Role::Editor, &if_match).await?;
+    let old = find_revision(&state, id, revision).await?;
+    let events = state.events.publisher().await;
+    let note = state
+        .store
+        .update(id, NoteInput::new(old.title, old.note), expected)
+        .await
+        .map_err(precondition)?
+        .ok_or_else(|| ApiError::note_not_found(id))?;
+    tracing::info!(
+        user_id = user.id,
+        note_id = id,
+        restored = revision,
+        revision = note.revision,
+        "revision restored"
+    );
+    events.publish(EventKind::Updated, &note);
+    Ok(tagged(note))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        store::NoteStore,
+        test_util::{json_body, request, seeded_store, test_app},
+    };
+
+    fn revision(revision: u64, title: &str, note: &str) -> Revision {
+        Revision {
+            note_id: 1,
+            revision,
+            title: title.into(),
+            note: note.into(),
+            created_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn line_diff() {
+        let diff = RevisionDiff::between(
+            &revision(1, "t", "one\ntwo\nthree\n"),
+            &revision(2, "t", "one\n2\nthree\n"),
+        );
+        let ops: Vec<_> = diff
+            .lines
+            .iter()
+            .map(|line| (line.op, line.text.as_str()))
+            .collect();
+        assert_eq!(
+            ops,
+            vec![
+                (LineOp::Equal, "one"),
+                (LineOp::Delete, "two"),
+                (LineOp::Insert, "2"),
+                (LineOp::Equal, "three"),
+            ]
+        );
+        assert!(!diff.title_changed);
+        assert!(diff.unified.contains("-two\n+2\n"));
+    }
+
+    #[tokio::test]
+    async fn revision_history() {
+        let store = seeded_store().await;
+        let app = test_app(store.clone());
+        let body = json!({"title": "test_title", "note": "changed"});
+        app.clone()
+            .oneshot(request("PUT", "/v1/notes/1", Some(body)))
+            .await
+            .unwrap();
+
+        let response = app
+            .clone()
+            .oneshot(request("GET", "/v1/notes/1/revisions", None))
+            .await
+            .unwrap();
+        let revisions = json_body(response).await;
+        assert_eq!(revisions.as_array().unwrap().len(), 2);
+        assert_eq!(revisions[0]["note"], "test");
+
+        let response = app
+            .clone()
+            .oneshot(request("GET", "/v1/notes/1/diff?from=1", None))
+            .await
+            .unwrap();
+        let diff = json_body(response).await;
+        assert_eq!(diff["to"], 2);
+        assert_eq!(diff["lines"][0], json!({"op": "delete", "text": "test"}));
+
+        let response = app
+            .clone()
+            .oneshot(request("POST", "/v1/notes/1/revisions/1/restore", None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let note = store.get(1).await.unwrap().unwrap();
+        assert_eq!((note.revision, note.note.as_str()), (3, "test"));
+
+        let response = app
+            .oneshot(request("GET", "/v1/notes/1/revisions/9", None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,330 @@
+//! API keys, for scripts that call the API without a user's password.
+//!
+//! A key is a random secret shown once when it is issued. Only its SHA-256
+//! hash is stored: keys carry enough entropy that a slow password hash would
+//! add nothing but latency to every request. Each key is limited to a set of
+//! [`Scope`]s, which handlers check through [`AuthUser::require`].
+
+use std::{collections::BTreeSet, fmt, str::FromStr};
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use axum::{extract::State, http::StatusCode, response::IntoResponse};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use utoipa::ToSchema;
+
+use crate::{
+    auth::AuthUser,
+    error::ApiError,
+    extract::{Json, Path},
+    AppState,
+};
+
+/// Every key starts with this, which is how a bearer token is told apart
+/// from a JWT.
+pub const KEY_PREFIX: &str = "nk_";
+/// Characters of a key kept in the clear to tell keys apart in listings.
+const SHOWN_LEN: usize = 10;
+pub const MAX_NAME_LEN: usize = 100;
+
+/// What a key may do. `Admin` allows everything, including managing keys.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, ToSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    Read,
+    Write,
+    Delete,
+    Admin,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 4] = [Scope::Read, Scope::Write, Scope::Delete, Scope::Admin];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Scope::Read => "read",
+            Scope::Write => "write",
+            Scope::Delete => "delete",
+            Scope::Admin => "admin",
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Scope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Scope::ALL
+            .into_iter()
+            .find(|scope| scope.as_str() == s)
+            .ok_or_else(|| format!("unknown scope `{}`", s))
+    }
+}
+
+/// Joins scopes into the comma separated form they are stored in.
+pub fn join_scopes(scopes: &BTreeSet<Scope>) -> String {
+    scopes
+        .iter()
+        .map(|scope| scope.as_str())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// The inverse of [`join_scopes`]. Unknown scopes are skipped.
+pub fn split_scopes(scopes: &str) -> BTreeSet<Scope> {
+    scopes.split(',').filter_map(|s| s.parse().ok()).collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct ApiKey {
+    pub id: u32,
+    pub user_id: u32,
+    pub name: String,
+    /// The first characters of the key.
+    pub prefix: String,
+    pub scopes: BTreeSet<Scope>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ApiKeyInput {
+    pub name: String,
+    pub scopes: BTreeSet<Scope>,
+}
+
+/// A freshly issued key. `key` is not stored and cannot be shown again.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct IssuedKey {
+    #[serde(flatten)]
+    pub api_key: ApiKey,
+    pub key: String,
+}
+
+/// Generates a new key, returning it with its hash.
+fn generate() -> (String, String) {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    let key = format!("{}{}", KEY_PREFIX, URL_SAFE_NO_PAD.encode(bytes));
+    let hash = hash_key(&key);
+    (key, hash)
+}
+
+/// The hash a key is stored and looked up under.
+pub fn hash_key(key: &str) -> String {
+    format!("{:x}", Sha256::digest(key.as_bytes()))
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/auth/keys",
+    tag = "auth",
+    request_body = ApiKeyInput,
+    responses(
+        (status = 201, description = "The new key; `key` is only shown here", body = IssuedKey),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Not allowed for the caller", body = ErrorBody),
+        (status = 422, description = "Invalid input", body = ErrorBody),
+    ),
+)]
+pub async fn create_key(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Json(mut payload): Json<ApiKeyInput>,
+) -> Result<impl IntoResponse, ApiError> {
+    user.require(Scope::Admin)?;
+    payload.name = payload.name.trim().to_owned();
+    if payload.name.is_empty() || payload.name.chars().count() > MAX_NAME_LEN {
+        return Err(ApiError::Unprocessable(format!(
+            "Key name must be 1 to {} characters",
+            MAX_NAME_LEN
+        )));
+    }
+    if payload.scopes.is_empty() {
+        return Err(ApiError::Unprocessable(
+            "A key needs at least one scope".into(),
+        ));
+    }
+    let (key, hash) = generate();
+    let api_key = state
+        .store
+        .create_api_key(user.id, payload, &key[..SHOWN_LEN], &hash)
+        .await?;
+    tracing::info!(user_id = user.id, key_id = api_key.id, "API key created");
+    Ok((StatusCode::CREATED, Json(IssuedKey { api_key, key })))
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/auth/keys",
+    tag = "auth",
+    responses(
+        (status = 200, description = "The caller's API keys", body = [ApiKey]),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Not allowed for the caller", body = ErrorBody),
+    ),
+)]
+pub async fn list_keys(
+    State(state): State<AppState>,
+    user: AuthUser,
+) -> Result<Json<Vec<ApiKey>>, ApiError> {
+    user.require(Scope::Admin)?;
+    Ok(Json(state.store.list_api_keys(user.id).await?))
+}
+
+/// Revoked keys are deleted and stop working immediately.
+#[utoipa::path(
+    delete,
+    path = "/v1/auth/keys/{id}",
+    tag = "auth",
+    params(("id" = u32, Path, description = "API key id")),
+    responses(
+        (status = 204, description = "Key revoked"),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Not allowed for the caller", body = ErrorBody),
+        (status = 404, description = "API key not found", body = ErrorBody),
+    ),
+)]
+pub async fn revoke_key(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Path(id): Path<u32>,
+) -> Result<StatusCode, ApiError> {
+    user.require(Scope::Admin)?;
+    let not_found = || ApiError::NotFound(format!("API key {} not found", id));
+    let keys = state.store.list_api_keys(user.id).await?;
+    if !keys.iter().any(|key| key.id == id) {
+        return Err(not_found());
+    }
+    if !state.store.revoke_api_key(id).await? {
+        return Err(not_found());
+    }
+    tracing::info!(user_id = user.id, key_id = id, "API key revoked");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{header, StatusCode};
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        auth::API_KEY_HEADER,
+        test_util::{json_body, request, seeded_store, test_app, with_header},
+    };
+
+    #[test]
+    fn scopes_round_trip() {
+        let scopes = BTreeSet::from([Scope::Delete, Scope::Read]);
+        assert_eq!(join_scopes(&scopes), "read,delete");
+        assert_eq!(split_scopes("read,delete"), scopes);
+        assert_eq!(split_scopes(""), BTreeSet::new());
+        assert!("owner".parse::<Scope>().is_err());
+    }
+
+    #[test]
+    fn generated_keys() {
+        let (key, hash) = generate();
+        assert!(key.starts_with(KEY_PREFIX));
+        assert_eq!(key.len(), KEY_PREFIX.len() + 43);
+        assert_eq!(hash, hash_key(&key));
+        assert_eq!(hash.len(), 64);
+        assert_ne!(generate().0, key);
+    }
+
+    #[tokio::test]
+    async fn api_keys() {
+        let app = test_app(seeded_store().await);
+        let response = app
+            .clone()
+            .oneshot(request(
+                "POST",
+                "/v1/auth/keys",
+                Some(json!({"name": "reader", "scopes": ["read"]})),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let issued = json_body(response).await;
+        let key = issued["key"].as_str().unwrap().to_owned();
+        assert_eq!(issued["scopes"], json!(["read"]));
+        assert!(key.starts_with(issued["prefix"].as_str().unwrap()));
+
+        let with_key = |req| {
+            let mut req = with_header(req, API_KEY_HEADER, &key);
+            req.headers_mut().remove(header::AUTHORIZATION);
+            req
+        };
+        let response = app
+            .clone()
+            .oneshot(with_key(request("GET", "/v1/notes/1", None)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = app
+            .clone()
+            .oneshot(with_header(
+                request("GET", "/v1/notes/1", None),
+                header::AUTHORIZATION,
+                &format!("Bearer {}", key),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = app
+            .clone()
+            .oneshot(with_key(request(
+                "POST",
+                "/v1/notes",
+                Some(json!({"title": "t", "note": "n"})),
+            )))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = app
+            .clone()
+            .oneshot(with_key(request("GET", "/v1/auth/keys", None)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = app
+            .clone()
+            .oneshot(request("GET", "/v1/auth/keys", None))
+            .await
+            .unwrap();
+        let keys = json_body(response).await;
+        assert_eq!(keys.as_array().unwrap().len(), 1);
+        assert!(keys[0]["last_used_at"].is_string());
+        assert!(keys[0].get("key").is_none());
+
+        let uri = format!("/v1/auth/keys/{}", issued["id"]);
+        let response = app
+            .clone()
+            .oneshot(request("DELETE", &uri, None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let response = app
+            .clone()
+            .oneshot(with_key(request("GET", "/v1/notes/1", None)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = app.oneshot(request("DELETE", &uri, None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
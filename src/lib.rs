@@ -0,0 +1,387 @@
+pub mod api;
+pub mod auth;
+pub mod config;
+pub mod error;
+pub mod etag;
+pub mod events;
+pub mod extract;
+pub mod history;
+pub mod keys;
+pub mod list;
+pub mod logging;
+pub mod metrics;
+pub mod notebooks;
+pub mod notes;
+pub mod openapi;
+pub mod patch;
+pub mod search;
+pub mod sharing;
+pub mod shutdown;
+pub mod store;
+pub mod tags;
+#[cfg(test)]
+mod test_util;
+pub mod trash;
+pub mod webhooks;
+pub mod workspaces;
+
+use std::sync::Arc;
+
+use auth::Auth;
+use axum::{extract::DefaultBodyLimit, middleware, routing::get, Json, Router};
+use config::{Config, Features, Limits};
+use events::EventFeed;
+use search::IndexedStore;
+use store::NoteStore;
+use webhooks::WebhookClient;
+use workspaces::Workspaces;
+
+pub use notes::{Note, NoteInput};
+
+/// State of the router of one workspace.
+#[derive(Clone)]
+pub struct AppState {
+    store: Arc<dyn NoteStore>,
+    /// The same store as `store`, for searching it.
+    search: Arc<IndexedStore>,
+    auth: Arc<Auth>,
+    events: Arc<EventFeed>,
+    webhooks: Arc<WebhookClient>,
+    /// Name of the workspace.
+    workspace: String,
+    workspaces: Arc<Workspaces>,
+}
+
+/// Builds the full application router, dispatching each request to the
+/// workspace it is for and logging it. Only the features and limits of
+/// `config` are used here; the rest is for setting up what is passed in.
+pub fn app(
+    workspaces: Arc<Workspaces>,
+    auth: Auth,
+    webhooks: WebhookClient,
+    config: &Config,
+) -> Router {
+    let mut router = workspaces::dispatcher(
+        workspaces.clone(),
+        auth,
+        Arc::new(webhooks),
+        config.features,
+        config.limits,
+    );
+    if config.features.metrics {
+        router = router.merge(
+            Router::new()
+                .route("/metrics", get(metrics::export))
+                .with_state(workspaces),
+        );
+    }
+    logging::trace(router)
+}
+
+/// Builds the router of a single workspace.
+///
+/// Every API version is nested under its own `/vN` prefix so that a new
+/// version can be added next to the existing ones. The unversioned
+/// verb-in-path routes are kept for existing clients. `/openapi.json`
+/// describes the API and `/docs` renders that description. Disabled
+/// features are left out. `/metrics` is not here but in [`app`], since it
+/// covers every workspace.
+fn router(state: AppState, features: Features, limits: Limits) -> Router {
+    let mut router = Router::new().route("/", get(root_handler));
+    if features.docs {
+        router = router
+            .route("/openapi.json", get(openapi::openapi_json))
+            .route("/docs", get(openapi::docs));
+    }
+    router = router.nest("/v1", api::v1::router(features));
+    if features.legacy_routes {
+        router = router.merge(api::legacy::router());
+    }
+    router
+        .layer(DefaultBodyLimit::max(limits.body_bytes))
+        .layer(middleware::from_fn(metrics::track))
+        .with_state(state)
+}
+
+async fn root_handler() -> Json<String> {
+    Json("Notes are available under /v1/notes, described at /openapi.json and /docs".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        body::Body,
+        extract::Request,
+        http::{header, StatusCode},
+    };
+    use serde_json::json;
+    use store::{MemoryStore, MemoryWorkspaces};
+    use test_util::{
+        auth, authorized, json_body, request, retry_policy, seeded_store, test_app, with_header,
+    };
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn create() {
+        let store = Arc::new(MemoryStore::new());
+        let app = test_app(store.clone());
+        let note = NoteInput::new("test_title".into(), "test".into());
+        let response = app
+            .oneshot(authorized(
+                Request::builder()
+                    .method("POST")
+                    .uri("/create")
+                    .header("content-type", "application/json")
+                    .body(Body::from(serde_json::to_string(&note).unwrap()))
+                    .unwrap(),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(response.headers()[header::LOCATION], "/v1/notes/1");
+        let body = json_body(response).await;
+        assert_eq!(body["id"], 1);
+        assert_eq!(body["title"], "test_title");
+        let received_note = store.get(1).await.unwrap().unwrap();
+        assert_eq!(NoteInput::from(&received_note), note);
+    }
+
+    #[tokio::test]
+    async fn get() {
+        let store = seeded_store().await;
+        let app = test_app(store.clone());
+        let response = app
+            .oneshot(authorized(
+                Request::builder()
+                    .method("GET")
+                    .uri("/get/1")
+                    .body(Body::empty())
+                    .unwrap(),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = json_body(response).await;
+        assert_eq!(
+            serde_json::from_value::<Note>(body).unwrap(),
+            store.get(1).await.unwrap().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn update() {
+        let store = seeded_store().await;
+        let app = test_app(store.clone());
+        let note = NoteInput::new("test_title".into(), "test_updated".into());
+        let response = app
+            .oneshot(authorized(
+                Request::builder()
+                    .method("PUT")
+                    .uri("/update/1")
+                    .header("content-type", "application/json")
+                    .body(Body::from(serde_json::to_string(&note).unwrap()))
+                    .unwrap(),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = json_body(response).await;
+        assert_eq!(body["id"], 1);
+        assert_eq!(body["note"], "test_updated");
+        let received_note = store.get(1).await.unwrap().unwrap();
+        assert_eq!(NoteInput::from(&received_note), note);
+    }
+
+    #[tokio::test]
+    async fn delete() {
+        let store = seeded_store().await;
+        let app = test_app(store.clone());
+        let response = app
+            .oneshot(authorized(
+                Request::builder()
+                    .method("DELETE")
+                    .uri("/delete/1")
+                    .body(Body::empty())
+                    .unwrap(),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(store.get(1).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn get_missing() {
+        let app = test_app(seeded_store().await);
+        let response = app
+            .oneshot(authorized(
+                Request::builder()
+                    .method("GET")
+                    .uri("/get/42")
+                    .body(Body::empty())
+                    .unwrap(),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let body = json_body(response).await;
+        assert_eq!(body["error"]["code"], "not_found");
+        assert_eq!(body["error"]["message"], "Note 42 not found");
+    }
+
+    #[tokio::test]
+    async fn get_invalid_id() {
+        let app = test_app(seeded_store().await);
+        let response = app
+            .oneshot(authorized(
+                Request::builder()
+                    .method("GET")
+                    .uri("/get/abc")
+                    .body(Body::empty())
+                    .unwrap(),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(json_body(response).await["error"]["code"], "bad_request");
+    }
+
+    #[tokio::test]
+    async fn create_invalid_body() {
+        let app = test_app(seeded_store().await);
+        let response = app
+            .oneshot(authorized(
+                Request::builder()
+                    .method("POST")
+                    .uri("/create")
+                    .header("content-type", "application/json")
+                    .body(Body::from(r#"{"title":"missing body"}"#))
+                    .unwrap(),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(
+            json_body(response).await["error"]["code"],
+            "unprocessable_entity"
+        );
+    }
+
+    #[tokio::test]
+    async fn update_missing() {
+        let store = seeded_store().await;
+        let app = test_app(store.clone());
+        let note = NoteInput::new("test_title".into(), "test_updated".into());
+        let response = app
+            .oneshot(authorized(
+                Request::builder()
+                    .method("PUT")
+                    .uri("/update/42")
+                    .header("content-type", "application/json")
+                    .body(Body::from(serde_json::to_string(&note).unwrap()))
+                    .unwrap(),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(store.get(42).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn request_ids() {
+        let app = test_app(seeded_store().await);
+        let response = app
+            .clone()
+            .oneshot(request("GET", "/v1/notes/1", None))
+            .await
+            .unwrap();
+        let id = response.headers()[logging::REQUEST_ID_HEADER]
+            .to_str()
+            .unwrap();
+        assert_eq!(id.len(), 36);
+
+        // A request that comes with an id keeps it.
+        let request = with_header(
+            request("GET", "/w/default/v1/notes/42", None),
+            logging::REQUEST_ID_HEADER,
+            "abc-123",
+        );
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.headers()[logging::REQUEST_ID_HEADER], "abc-123");
+    }
+
+    #[tokio::test]
+    async fn body_limit() {
+        let mut config = Config::default();
+        config.limits.body_bytes = 64;
+        let workspaces = Workspaces::new(seeded_store().await, Arc::new(MemoryWorkspaces));
+        let app = app(
+            Arc::new(workspaces),
+            auth(),
+            WebhookClient::new(retry_policy()),
+            &config,
+        );
+        let note = NoteInput::new("title".into(), "x".repeat(64));
+        let response = app
+            .oneshot(request("POST", "/v1/notes", Some(json!(note))))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(
+            json_body(response).await["error"]["code"],
+            "payload_too_large"
+        );
+    }
+
+    #[tokio::test]
+    async fn disabled_features() {
+        let config = Config {
+            features: Features {
+                docs: false,
+                events: false,
+                webhooks: false,
+                legacy_routes: false,
+                metrics: false,
+            },
+            ..Config::default()
+        };
+        let workspaces = Workspaces::new(seeded_store().await, Arc::new(MemoryWorkspaces));
+        let app = app(
+            Arc::new(workspaces),
+            auth(),
+            WebhookClient::new(retry_policy()),
+            &config,
+        );
+        for uri in ["/docs", "/v1/events", "/v1/webhooks", "/get/1", "/metrics"] {
+            let response = app
+                .clone()
+                .oneshot(request("GET", uri, None))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "{uri}");
+        }
+        let response = app
+            .oneshot(request("GET", "/v1/notes/1", None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn delete_missing() {
+        let app = test_app(seeded_store().await);
+        let response = app
+            .oneshot(authorized(
+                Request::builder()
+                    .method("DELETE")
+                    .uri("/delete/42")
+                    .body(Body::empty())
+                    .unwrap(),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
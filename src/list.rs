@@ -0,0 +1,333 @@
+//! Filtering, sorting and cursor pagination for note listings.
+
+use std::{cmp::Ordering, collections::BTreeSet};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{error::ApiError, tags, Note};
+
+pub const DEFAULT_LIMIT: usize = 20;
+pub const MAX_LIMIT: usize = 100;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SortField {
+    #[default]
+    Id,
+    Title,
+    Created,
+    Updated,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// Query string accepted by the listing endpoint.
+#[derive(Debug, Default, Clone, Deserialize, IntoParams)]
+#[serde(deny_unknown_fields)]
+pub struct ListParams {
+    #[serde(default)]
+    pub sort: SortField,
+    #[serde(default)]
+    pub order: SortOrder,
+    pub limit: Option<usize>,
+    pub cursor: Option<String>,
+    /// Only notes whose title starts with this string.
+    pub title_prefix: Option<String>,
+    /// Only notes whose body contains this string.
+    pub note_contains: Option<String>,
+    /// Only notes created at or after this instant.
+    pub created_after: Option<DateTime<Utc>>,
+    /// Only notes updated at or after this instant.
+    pub updated_after: Option<DateTime<Utc>>,
+    /// Only notes filed directly in this notebook.
+    pub notebook_id: Option<u32>,
+    /// Comma separated tags; only notes carrying at least one of them.
+    pub tags_any: Option<String>,
+    /// Comma separated tags; only notes carrying every one of them.
+    pub tags_all: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct TagFilter {
+    any: Option<BTreeSet<String>>,
+    all: BTreeSet<String>,
+}
+
+impl TagFilter {
+    fn matches(&self, note: &Note) -> bool {
+        self.any
+            .as_ref()
+            .is_none_or(|any| !any.is_disjoint(&note.tags))
+            && self.all.is_subset(&note.tags)
+    }
+}
+
+/// One page of results.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[aliases(NotePage = Page<Note>)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Pass back as `cursor` to fetch the following page. `None` on the last
+    /// page.
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+enum SortKey {
+    Id,
+    Text(String),
+    Time(DateTime<Utc>),
+}
+
+/// Position of the last item of a page. Carries the sort it was produced
+/// with so that it cannot be replayed against a different ordering.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Cursor {
+    sort: SortField,
+    order: SortOrder,
+    key: SortKey,
+    id: u32,
+}
+
+impl Cursor {
+    fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).expect("cursor serializes"))
+    }
+
+    fn decode(raw: &str) -> Result<Self, ApiError> {
+        URL_SAFE_NO_PAD
+            .decode(raw)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .ok_or_else(|| ApiError::BadRequest("Invalid cursor".into()))
+    }
+}
+
+fn sort_key(field: SortField, note: &Note) -> SortKey {
+    match field {
+        SortField::Id => SortKey::Id,
+        SortField::Title => SortKey::Text(note.title.clone()),
+        SortField::Created => SortKey::Time(note.created_at),
+        SortField::Updated => SortKey::Time(note.updated_at),
+    }
+}
+
+impl ListParams {
+    fn tag_filter(&self) -> Result<TagFilter, ApiError> {
+        Ok(TagFilter {
+            any: self.tags_any.as_deref().map(tags::parse_list).transpose()?,
+            all: match &self.tags_all {
+                Some(all) => tags::parse_list(all)?,
+                None => BTreeSet::new(),
+            },
+        })
+    }
+
+    fn matches(&self, note: &Note) -> bool {
+        if let Some(prefix) = &self.title_prefix {
+            if !note.title.starts_with(prefix.as_str()) {
+                return false;
+            }
+        }
+        if let Some(needle) = &self.note_contains {
+            if !note.note.contains(needle.as_str()) {
+                return false;
+            }
+        }
+        if self
+            .notebook_id
+            .is_some_and(|notebook| note.notebook_id != Some(notebook))
+        {
+            return false;
+        }
+        if self
+            .created_after
+            .is_some_and(|after| note.created_at < after)
+        {
+            return false;
+        }
+        if self
+            .updated_after
+            .is_some_and(|after| note.updated_at < after)
+        {
+            return false;
+        }
+        true
+    }
+
+    fn limit(&self) -> Result<usize, ApiError> {
+        match self.limit {
+            Some(0) => Err(ApiError::BadRequest("limit must be at least 1".into())),
+            Some(limit) => Ok(limit.min(MAX_LIMIT)),
+            None => Ok(DEFAULT_LIMIT),
+        }
+    }
+}
+
+/// Filters, sorts and slices `notes` according to `params`.
+pub fn paginate(notes: Vec<Note>, params: &ListParams) -> Result<Page<Note>, ApiError> {
+    let limit = params.limit()?;
+    let tag_filter = params.tag_filter()?;
+    let cursor = params.cursor.as_deref().map(Cursor::decode).transpose()?;
+    if let Some(cursor) = &cursor {
+        if cursor.sort != params.sort || cursor.order != params.order {
+            return Err(ApiError::BadRequest(
+                "Cursor does not match the requested sort".into(),
+            ));
+        }
+    }
+
+    let compare = |a: &(SortKey, u32), b: &(SortKey, u32)| match params.order {
+        SortOrder::Asc => a.cmp(b),
+        SortOrder::Desc => b.cmp(a),
+    };
+
+    let mut keyed: Vec<_> = notes
+        .into_iter()
+        .filter(|note| params.matches(note) && tag_filter.matches(note))
+        .map(|note| ((sort_key(params.sort, &note), note.id), note))
+        .filter(|(key, _)| match &cursor {
+            Some(cursor) => compare(key, &(cursor.key.clone(), cursor.id)) == Ordering::Greater,
+            None => true,
+        })
+        .collect();
+    keyed.sort_by(|(a, _), (b, _)| compare(a, b));
+
+    let has_more = keyed.len() > limit;
+    keyed.truncate(limit);
+    let next_cursor = match keyed.last() {
+        Some(((key, id), _)) if has_more => Some(
+            Cursor {
+                sort: params.sort,
+                order: params.order,
+                key: key.clone(),
+                id: *id,
+            }
+            .encode(),
+        ),
+        _ => None,
+    };
+
+    Ok(Page {
+        items: keyed.into_iter().map(|(_, note)| note).collect(),
+        next_cursor,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+
+    /// Notes created a day apart, updated in reverse order of creation.
+    fn notes() -> Vec<Note> {
+        let epoch = Utc.timestamp_opt(0, 0).unwrap();
+        ["banana", "apple", "cherry", "apricot"]
+            .iter()
+            .enumerate()
+            .map(|(i, title)| Note {
+                notebook_id: None,
+                tags: ["all".to_string(), format!("tag{}", i % 2)].into(),
+                id: i as u32 + 1,
+                owner_id: 1,
+                title: title.to_string(),
+                note: "body".into(),
+                revision: 1,
+                created_at: epoch + Duration::days(i as i64),
+                updated_at: epoch + Duration::days(10 - i as i64),
+                deleted_at: None,
+            })
+            .collect()
+    }
+
+    fn ids(page: &Page<Note>) -> Vec<u32> {
+        page.items.iter().map(|note| note.id).collect()
+    }
+
+    #[test]
+    fn walks_pages_with_cursor() {
+        let mut params = ListParams {
+            sort: SortField::Title,
+            limit: Some(3),
+            ..Default::default()
+        };
+        let first = paginate(notes(), &params).unwrap();
+        assert_eq!(ids(&first), vec![2, 4, 1]);
+
+        params.cursor = first.next_cursor;
+        let second = paginate(notes(), &params).unwrap();
+        assert_eq!(ids(&second), vec![3]);
+        assert_eq!(second.next_cursor, None);
+    }
+
+    #[test]
+    fn descending_with_filter() {
+        let params = ListParams {
+            order: SortOrder::Desc,
+            title_prefix: Some("ap".into()),
+            ..Default::default()
+        };
+        assert_eq!(ids(&paginate(notes(), &params).unwrap()), vec![4, 2]);
+    }
+
+    #[test]
+    fn sorts_and_filters_by_timestamps() {
+        let params = ListParams {
+            sort: SortField::Updated,
+            created_after: Some(Utc.timestamp_opt(0, 0).unwrap() + Duration::days(1)),
+            ..Default::default()
+        };
+        assert_eq!(ids(&paginate(notes(), &params).unwrap()), vec![4, 3, 2]);
+    }
+
+    #[test]
+    fn filters_by_tags() {
+        let params = ListParams {
+            tags_any: Some("tag0,missing".into()),
+            ..Default::default()
+        };
+        assert_eq!(ids(&paginate(notes(), &params).unwrap()), vec![1, 3]);
+
+        let params = ListParams {
+            tags_all: Some("all,TAG1".into()),
+            ..Default::default()
+        };
+        assert_eq!(ids(&paginate(notes(), &params).unwrap()), vec![2, 4]);
+
+        let params = ListParams {
+            tags_all: Some("all,".into()),
+            ..Default::default()
+        };
+        assert!(matches!(
+            paginate(notes(), &params),
+            Err(ApiError::BadRequest(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_mismatched_cursor() {
+        let params = ListParams {
+            limit: Some(1),
+            ..Default::default()
+        };
+        let cursor = paginate(notes(), &params).unwrap().next_cursor;
+        let params = ListParams {
+            sort: SortField::Title,
+            cursor,
+            ..Default::default()
+        };
+        assert!(matches!(
+            paginate(notes(), &params),
+            Err(ApiError::BadRequest(_))
+        ));
+    }
+}
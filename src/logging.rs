@@ -0,0 +1,117 @@
+//! Structured logs, written with `tracing`.
+//!
+//! Every request runs in a `request` span holding its method, path, id and,
+//! once it is known, workspace. Share link tokens are left out of the path. A `response` event closes the span with the
+//! status and latency. The request id is taken from an `X-Request-Id` header
+//! or made up, and is sent back on the response.
+
+use std::{borrow::Cow, time::Duration};
+
+use axum::{
+    extract::Request,
+    http::{HeaderName, Response},
+    Router,
+};
+use tower::ServiceBuilder;
+use tower_http::{
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+    trace::TraceLayer,
+};
+use tracing::{field, Level, Span};
+
+use crate::config::{Log, LogFormat, LogLevel};
+
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Installs the global subscriber writing to stderr. Fails if one is
+/// already installed.
+pub fn init(log: &Log) -> Result<(), String> {
+    let level = match log.level {
+        LogLevel::Error => Level::ERROR,
+        LogLevel::Warn => Level::WARN,
+        LogLevel::Info => Level::INFO,
+        LogLevel::Debug => Level::DEBUG,
+        LogLevel::Trace => Level::TRACE,
+    };
+    let builder = tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_writer(std::io::stderr);
+    match log.format {
+        LogFormat::Pretty => builder.try_init(),
+        LogFormat::Json => builder
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_span_list(false)
+            .try_init(),
+    }
+    .map_err(|e| e.to_string())
+}
+
+/// Gives every request a request id and a span.
+pub(crate) fn trace(router: Router) -> Router {
+    router.layer(
+        ServiceBuilder::new()
+            .layer(SetRequestIdLayer::new(
+                REQUEST_ID_HEADER.clone(),
+                MakeRequestUuid,
+            ))
+            .layer(
+                TraceLayer::new_for_http()
+                    .make_span_with(request_span)
+                    .on_request(())
+                    .on_response(log_response)
+                    .on_failure(()),
+            )
+            .layer(PropagateRequestIdLayer::new(REQUEST_ID_HEADER.clone())),
+    )
+}
+
+fn request_span(request: &Request) -> Span {
+    let id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|id| id.to_str().ok())
+        .unwrap_or_default();
+    tracing::info_span!(
+        "request",
+        method = %request.method(),
+        path = %loggable_path(request.uri().path()),
+        request_id = %id,
+        workspace = field::Empty,
+    )
+}
+
+/// `path` with the token of a share link blanked out, since anyone holding
+/// it can read the note.
+fn loggable_path(path: &str) -> Cow<'_, str> {
+    match path.split_once("/public/") {
+        Some((prefix, _)) => format!("{prefix}/public/:token").into(),
+        None => path.into(),
+    }
+}
+
+fn log_response<B>(response: &Response<B>, latency: Duration, _span: &Span) {
+    let status = response.status().as_u16();
+    let latency_ms = latency.as_secs_f64() * 1000.0;
+    if response.status().is_server_error() {
+        tracing::error!(status, latency_ms, "response");
+    } else {
+        tracing::info!(status, latency_ms, "response");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn share_tokens_are_not_logged() {
+        assert_eq!(loggable_path("/v1/notes/1"), "/v1/notes/1");
+        assert_eq!(loggable_path("/v1/public/secret"), "/v1/public/:token");
+        assert_eq!(
+            loggable_path("/w/team/v1/public/secret"),
+            "/w/team/v1/public/:token"
+        );
+    }
+}
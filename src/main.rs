@@ -1,182 +1,140 @@
-use std::{collections::HashMap, sync::Arc};
-
-use axum::{
-    extract::{Path, State},
-    routing::{delete, get, post, put},
-    Json, Router,
+use std::{process::ExitCode, sync::Arc};
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use axum_notes::{
+    app,
+    auth::{self, Auth, Bootstrap, Credentials},
+    config::{self, Backend, Config},
+    logging,
+    shutdown::{self, Stopped},
+    store::{MemoryStore, MemoryWorkspaces, NoteStore, SqliteStore, SqliteWorkspaces},
+    trash,
+    webhooks::WebhookClient,
+    workspaces::Workspaces,
 };
-use serde::{Deserialize, Serialize};
-use tokio::sync::Mutex;
-
-#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq, PartialOrd)]
-pub struct Note {
-    title: String,
-    note: String,
-}
-
-impl Note {
-    pub fn new(title: String, note: String) -> Self {
-        Self { title, note }
-    }
-}
-
-#[derive(Default)]
-pub struct AppState {
-    id: u32,
-    data: HashMap<u32, Note>,
-}
 
 #[tokio::main]
-async fn main() {
-    let app_state: Arc<Mutex<AppState>> = Arc::new(Mutex::new(AppState::default()));
-
-    let listener = tokio::net::TcpListener::bind("127.0.0.1:3000")
-        .await
-        .unwrap();
-    axum::serve(listener, app(app_state)).await.unwrap();
-}
-
-fn app(app_state: Arc<Mutex<AppState>>) -> Router {
-    Router::new()
-        .route("/", get(root_handler))
-        .route("/get/:id", get(read_note))
-        .route("/create", post(create_note))
-        .route("/update/:id", put(update_note))
-        .route("/delete/:id", delete(delete_note))
-        .with_state(app_state)
-}
-
-async fn root_handler() -> Json<String> {
-    Json(format!("Available methods are create, get, update, delete"))
-}
-
-pub async fn create_note(
-    state: State<Arc<Mutex<AppState>>>,
-    Json(payload): Json<Note>,
-) -> Json<String> {
-    let mut state = state.lock().await;
-    let new_id = state.id + 1;
-    state.data.insert(new_id, payload);
-    state.id = new_id;
-    Json(format!("Note created with id: {}", new_id))
-}
-
-async fn delete_note(state: State<Arc<Mutex<AppState>>>, Path(id): Path<u32>) -> Json<String> {
-    let mut state = state.lock().await;
-    state.data.remove(&id);
-    Json(format!("User deleted with id: {}", id))
-}
-
-async fn update_note(
-    state: State<Arc<Mutex<AppState>>>,
-    Path(id): Path<u32>,
-    Json(payload): Json<Note>,
-) -> Json<String> {
-    let mut state = state.lock().await;
-    state.data.insert(id, payload);
-    Json("Updated note".into())
-}
-
-async fn read_note(
-    state: State<Arc<Mutex<AppState>>>,
-    Path(id): Path<u32>,
-) -> Result<Json<Note>, String> {
-    let state = state.lock().await;
-    let note = state.data.get(&id).ok_or("Note not found")?.clone();
-    Ok(Json(note))
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use axum::{body::Body, extract::Request, http::StatusCode};
-    use lazy_static::lazy_static;
-    use sequential_test::sequential;
-    use tower::ServiceExt;
-
-    lazy_static! {
-        static ref GLOBAL_STATE: Arc<Mutex<AppState>> = Arc::new(Mutex::new(AppState::default()));
+async fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "--help" || arg == "-h") {
+        print!("{}", config::usage());
+        return ExitCode::SUCCESS;
     }
-
-    #[tokio::test]
-    #[sequential]
-    async fn create() {
-        let app_state: Arc<Mutex<AppState>> = GLOBAL_STATE.clone();
-        let app = app(app_state.clone());
-        let note = Note::new("test_title".into(), "test".into());
-        let response = app
-            .oneshot(
-                Request::builder()
-                    .method("POST")
-                    .uri("/create")
-                    .header("content-type", "application/json")
-                    .body(Body::from(serde_json::to_string(&note).unwrap()))
-                    .unwrap(),
-            )
-            .await
-            .unwrap();
-        assert_eq!(response.status(), StatusCode::OK);
-        let received_note = app_state.lock().await.data.get(&1).unwrap().clone();
-        assert_eq!(received_note, note);
+    let config = match Config::load(args, |name| std::env::var(name).ok()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("invalid configuration: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    if let Err(e) = logging::init(&config.log) {
+        eprintln!("failed to set up logging: {e}");
+        return ExitCode::FAILURE;
     }
-
-    #[tokio::test]
-    #[sequential]
-    async fn get() {
-        let app_state: Arc<Mutex<AppState>> = GLOBAL_STATE.clone();
-        let app = app(app_state.clone());
-        let note = Note::new("test_title".into(), "test".into());
-        let response = app
-            .oneshot(
-                Request::builder()
-                    .method("GET")
-                    .uri("/get/1")
-                    .body(Body::empty())
-                    .unwrap(),
-            )
-            .await
-            .unwrap();
-        assert_eq!(response.status(), StatusCode::OK);
+    match serve(config).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            tracing::error!("{e}");
+            ExitCode::FAILURE
+        }
     }
+}
 
-    #[tokio::test]
-    #[sequential]
-    async fn update() {
-        let app_state: Arc<Mutex<AppState>> = GLOBAL_STATE.clone();
-        let app = app(app_state.clone());
-        let note = Note::new("test_title".into(), "test_updated".into());
-        let response = app
-            .oneshot(
-                Request::builder()
-                    .method("PUT")
-                    .uri("/update/1")
-                    .header("content-type", "application/json")
-                    .body(Body::from(serde_json::to_string(&note).unwrap()))
-                    .unwrap(),
-            )
-            .await
-            .unwrap();
-        assert_eq!(response.status(), StatusCode::OK);
-        let received_note = app_state.lock().await.data.get(&1).unwrap().clone();
-        assert_eq!(received_note, note);
+async fn serve(config: Config) -> Result<(), String> {
+    let secret = match &config.auth.jwt_secret {
+        Some(secret) => secret.clone().into_bytes(),
+        None => {
+            tracing::warn!("auth.jwt_secret is not set; tokens will not survive a restart");
+            let mut secret = vec![0; 32];
+            OsRng.fill_bytes(&mut secret);
+            secret
+        }
+    };
+    let workspaces = match config.storage.backend {
+        Backend::Sqlite => {
+            let path = &config.storage.path;
+            let store: Arc<dyn NoteStore> = Arc::new(
+                SqliteStore::open(path)
+                    .map_err(|e| format!("cannot open {}: {}", path.display(), e))?,
+            );
+            let dir = &config.storage.workspaces_dir;
+            let stores = SqliteWorkspaces::new(dir)
+                .map_err(|e| format!("cannot open {}: {}", dir.display(), e))?;
+            Workspaces::new(store, Arc::new(stores))
+        }
+        Backend::Memory => {
+            Workspaces::new(Arc::new(MemoryStore::new()), Arc::new(MemoryWorkspaces))
+        }
+    };
+    let workspaces = Arc::new(workspaces);
+    if let (Some(username), Some(password)) = (
+        &config.auth.bootstrap_admin,
+        &config.auth.bootstrap_password,
+    ) {
+        let credentials = Credentials {
+            username: username.clone(),
+            password: password.clone(),
+        };
+        let store = workspaces.default_store().await;
+        match auth::bootstrap_admin(store.as_ref(), credentials).await {
+            Ok(Bootstrap::Created { user, claimed }) => {
+                tracing::info!(user_id = user.id, claimed, "bootstrap admin created");
+            }
+            Ok(Bootstrap::Existing(user)) if !user.is_admin => {
+                tracing::warn!(
+                    user_id = user.id,
+                    "the bootstrap admin's name is taken by a user, who was not made an admin"
+                );
+            }
+            Ok(Bootstrap::Existing(_)) => {}
+            Err(e) => {
+                return Err(format!(
+                    "cannot create the bootstrap admin: {}",
+                    e.message()
+                ))
+            }
+        }
     }
+    let purger = trash::spawn_purger(
+        workspaces.clone(),
+        chrono::Duration::days(config.trash.retention_days),
+    );
 
-    #[tokio::test]
-    #[sequential]
-    async fn delete() {
-        let app_state: Arc<Mutex<AppState>> = GLOBAL_STATE.clone();
-        let app = app(app_state.clone());
-        let response = app
-            .oneshot(
-                Request::builder()
-                    .method("DELETE")
-                    .uri("/delete/1")
-                    .body(Body::empty())
-                    .unwrap(),
-            )
+    let addr = config.listen.socket_addr();
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .map_err(|e| format!("cannot listen on {addr}: {e}"))?;
+    tracing::info!(%addr, backend = ?config.storage.backend, "listening");
+    let auth = Auth::new(&secret);
+    let webhooks =
+        WebhookClient::default().with_private_targets(config.webhooks.allow_private_targets);
+    if config.features.webhooks {
+        let resumed = webhooks
+            .resume(&workspaces)
             .await
-            .unwrap();
-        assert_eq!(response.status(), StatusCode::OK);
-        assert_eq!(app_state.lock().await.data.get(&1), None);
+            .map_err(|e| format!("cannot resume webhook deliveries: {e}"))?;
+        if resumed > 0 {
+            tracing::info!(resumed, "resuming webhook deliveries");
+        }
     }
+    let app = app(workspaces.clone(), auth, webhooks.clone(), &config);
+    let stopped = shutdown::serve(listener, app, shutdown::signal(), config.shutdown.timeout())
+        .await
+        .map_err(|e| format!("server error: {e}"))?;
+    if stopped == Stopped::TimedOut {
+        tracing::warn!(
+            timeout_secs = config.shutdown.timeout_secs,
+            "requests still running were cut off"
+        );
+    }
+    purger.abort();
+    if !webhooks.drain(config.shutdown.timeout()).await {
+        tracing::warn!("webhook deliveries still running were cut off");
+    }
+    workspaces
+        .flush()
+        .await
+        .map_err(|e| format!("failed to flush the store: {e}"))?;
+    tracing::info!("stopped");
+    Ok(())
 }
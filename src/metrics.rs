@@ -0,0 +1,228 @@
+//! Prometheus metrics, served at `/metrics` in the text exposition format.
+//!
+//! The metrics live in one registry for the whole process, since the stores
+//! timing their operations and locks are not tied to any one router. The
+//! note count is the exception: it is read from the stores on every scrape,
+//! so it is never out of date. It covers the workspaces opened so far
+//! without naming them, since the endpoint is open to anyone who can reach
+//! it.
+
+use std::{
+    future::Future,
+    sync::{Arc, OnceLock},
+    time::Instant,
+};
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::header,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use prometheus::{
+    core::Collector, exponential_buckets, histogram_opts, opts, Encoder, HistogramVec,
+    IntCounterVec, IntGauge, Registry, TextEncoder,
+};
+
+use crate::{error::ApiError, store::NoteStore, workspaces::Workspaces};
+
+/// Route label of requests that matched no route.
+const UNMATCHED: &str = "unmatched";
+
+pub struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_duration: HistogramVec,
+    store_duration: HistogramVec,
+    lock_wait: HistogramVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let http_requests = IntCounterVec::new(
+            opts!("http_requests_total", "HTTP requests served"),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let http_duration = HistogramVec::new(
+            histogram_opts!(
+                "http_request_duration_seconds",
+                "Time taken to serve HTTP requests"
+            ),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let store_duration = HistogramVec::new(
+            histogram_opts!(
+                "store_operation_duration_seconds",
+                "Time taken by store operations",
+                exponential_buckets(0.00001, 4.0, 10).unwrap()
+            ),
+            &["operation"],
+        )
+        .unwrap();
+        let lock_wait = HistogramVec::new(
+            histogram_opts!(
+                "store_lock_wait_seconds",
+                "Time spent waiting for store locks",
+                exponential_buckets(0.000001, 4.0, 12).unwrap()
+            ),
+            &["lock"],
+        )
+        .unwrap();
+        let registry = Registry::new();
+        registry.register(Box::new(http_requests.clone())).unwrap();
+        registry.register(Box::new(http_duration.clone())).unwrap();
+        registry.register(Box::new(store_duration.clone())).unwrap();
+        registry.register(Box::new(lock_wait.clone())).unwrap();
+        Self {
+            registry,
+            http_requests,
+            http_duration,
+            store_duration,
+            lock_wait,
+        }
+    }
+}
+
+pub fn metrics() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(Metrics::new)
+}
+
+/// Middleware counting and timing requests by their route, which is the
+/// path template rather than the path so that ids do not each get a series.
+pub(crate) async fn track(request: Request, next: Next) -> Response {
+    let start = Instant::now();
+    let method = request.method().clone();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_owned());
+    let response = next.run(request).await;
+    let status = response.status();
+    let labels = [
+        method.as_str(),
+        route.as_deref().unwrap_or(UNMATCHED),
+        status.as_str(),
+    ];
+    let metrics = metrics();
+    metrics.http_requests.with_label_values(&labels).inc();
+    metrics
+        .http_duration
+        .with_label_values(&labels)
+        .observe(start.elapsed().as_secs_f64());
+    response
+}
+
+/// Runs the store operation `operation`, timing it.
+pub(crate) async fn time<T>(operation: &'static str, future: impl Future<Output = T>) -> T {
+    let start = Instant::now();
+    let output = future.await;
+    metrics()
+        .store_duration
+        .with_label_values(&[operation])
+        .observe(start.elapsed().as_secs_f64());
+    output
+}
+
+/// Takes `mutex`, recording how long that took under `lock`.
+pub(crate) async fn lock<'a, T>(
+    lock: &'static str,
+    mutex: &'a tokio::sync::Mutex<T>,
+) -> tokio::sync::MutexGuard<'a, T> {
+    let start = Instant::now();
+    let guard = mutex.lock().await;
+    metrics()
+        .lock_wait
+        .with_label_values(&[lock])
+        .observe(start.elapsed().as_secs_f64());
+    guard
+}
+
+/// Like [`lock`], for a guard that outlives the borrow of `mutex`.
+pub(crate) async fn lock_owned<T>(
+    lock: &'static str,
+    mutex: Arc<tokio::sync::Mutex<T>>,
+) -> tokio::sync::OwnedMutexGuard<T> {
+    let start = Instant::now();
+    let guard = mutex.lock_owned().await;
+    metrics()
+        .lock_wait
+        .with_label_values(&[lock])
+        .observe(start.elapsed().as_secs_f64());
+    guard
+}
+
+/// Every metric, and the number of notes.
+pub async fn export(State(workspaces): State<Arc<Workspaces>>) -> Result<Response, ApiError> {
+    let mut count = 0;
+    for store in workspaces.opened().await {
+        count += store.count_notes().await?;
+    }
+    let notes = IntGauge::with_opts(opts!(
+        "notes",
+        "Notes stored in the workspaces opened so far, trashed ones left out"
+    ))
+    .unwrap();
+    notes.set(count as i64);
+    let mut families = metrics().registry.gather();
+    families.extend(notes.collect());
+    let encoder = TextEncoder::new();
+    let mut body = Vec::new();
+    encoder
+        .encode(&families, &mut body)
+        .map_err(|e| ApiError::Internal(e.to_string()))?;
+    Ok((
+        [(header::CONTENT_TYPE, encoder.format_type().to_owned())],
+        body,
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::StatusCode};
+    use tower::ServiceExt;
+
+    use crate::{
+        test_util::{request, seeded_store, test_app},
+        NoteInput,
+    };
+
+    #[tokio::test]
+    async fn exports_metrics() {
+        let app = test_app(seeded_store().await);
+        let note = serde_json::to_value(NoteInput::new("title".into(), "note".into())).unwrap();
+        for request in [
+            request("GET", "/v1/notes/1", None),
+            request("POST", "/v1/notes", Some(note)),
+            request("DELETE", "/v1/notes/1", None),
+            request("GET", "/nowhere", None),
+        ] {
+            app.clone().oneshot(request).await.unwrap();
+        }
+
+        let request = axum::extract::Request::get("/metrics")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        for expected in [
+            r#"http_requests_total{method="GET",route="/v1/notes/:id",status="200"}"#,
+            r#"http_requests_total{method="POST",route="/v1/notes",status="201"}"#,
+            r#"http_requests_total{method="GET",route="unmatched",status="404"}"#,
+            r#"http_request_duration_seconds_bucket{method="DELETE",route="/v1/notes/:id",status="204""#,
+            r#"store_operation_duration_seconds_count{operation="create"}"#,
+            r#"store_lock_wait_seconds_count{lock="memory"}"#,
+            // One note created, one trashed.
+            "\nnotes 1\n",
+        ] {
+            assert!(body.contains(expected), "{expected} missing from\n{body}");
+        }
+    }
+}
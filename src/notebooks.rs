@@ -0,0 +1,525 @@
+//! Notebooks: a tree of folders that notes are filed into.
+//!
+//! A note belongs to at most one notebook; notes without one sit at the top
+//! level.
+
+use std::collections::HashMap;
+
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    auth::AuthUser,
+    error::ApiError,
+    etag::IfMatch,
+    events::EventKind,
+    extract::{Json, Path, Query},
+    keys::Scope,
+    notes::{find_note, if_match_revision, precondition, tagged},
+    sharing::Role,
+    store::StoreError,
+    AppState, Note,
+};
+
+pub const MAX_NAME_LEN: usize = 200;
+
+/// Client supplied part of a notebook, accepted by create and update.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq, ToSchema)]
+pub struct NotebookInput {
+    pub name: String,
+    /// The notebook to nest this one in; `None` for the top level.
+    #[serde(default)]
+    pub parent_id: Option<u32>,
+}
+
+impl NotebookInput {
+    pub fn new(name: String, parent_id: Option<u32>) -> Self {
+        Self { name, parent_id }
+    }
+
+    fn validate(mut self) -> Result<Self, ApiError> {
+        self.name = self.name.trim().to_owned();
+        if self.name.is_empty() || self.name.chars().count() > MAX_NAME_LEN {
+            return Err(ApiError::Unprocessable(format!(
+                "Notebook name must be 1 to {} characters",
+                MAX_NAME_LEN
+            )));
+        }
+        Ok(self)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, ToSchema)]
+pub struct Notebook {
+    pub id: u32,
+    pub owner_id: u32,
+    pub name: String,
+    pub parent_id: Option<u32>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Notebook {
+    pub fn location(&self) -> String {
+        format!("/v1/notebooks/{}", self.id)
+    }
+}
+
+/// A notebook with the notes filed directly in it and its sub-notebooks,
+/// recursively.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, ToSchema)]
+pub struct NotebookTree {
+    pub notebook: Notebook,
+    pub notes: Vec<Note>,
+    pub children: Vec<NotebookTree>,
+}
+
+impl NotebookTree {
+    /// Builds the tree below `root` out of every notebook and note. Siblings
+    /// keep the order they are given in.
+    pub fn build(root: Notebook, notebooks: Vec<Notebook>, notes: Vec<Note>) -> Self {
+        let mut children: HashMap<u32, Vec<Notebook>> = HashMap::new();
+        for notebook in notebooks {
+            if let Some(parent) = notebook.parent_id {
+                children.entry(parent).or_default().push(notebook);
+            }
+        }
+        let mut filed: HashMap<u32, Vec<Note>> = HashMap::new();
+        for note in notes {
+            if let Some(notebook) = note.notebook_id {
+                filed.entry(notebook).or_default().push(note);
+            }
+        }
+        Self::assemble(root, &mut children, &mut filed)
+    }
+
+    /// Every note in the tree.
+    pub fn into_notes(self) -> Vec<Note> {
+        let mut notes = self.notes;
+        for child in self.children {
+            notes.extend(child.into_notes());
+        }
+        notes
+    }
+
+    fn assemble(
+        notebook: Notebook,
+        children: &mut HashMap<u32, Vec<Notebook>>,
+        filed: &mut HashMap<u32, Vec<Note>>,
+    ) -> Self {
+        let notes = filed.remove(&notebook.id).unwrap_or_default();
+        let children = children
+            .remove(&notebook.id)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|child| Self::assemble(child, children, filed))
+            .collect();
+        Self {
+            notebook,
+            notes,
+            children,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[serde(deny_unknown_fields)]
+pub struct DeleteParams {
+    /// Also delete sub-notebooks and move their notes to the trash. Without
+    /// it only empty notebooks can be deleted.
+    #[serde(default)]
+    pub cascade: bool,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct MoveInput {
+    /// `None` moves the note to the top level.
+    pub notebook_id: Option<u32>,
+}
+
+fn notebook_not_found(id: u32) -> ApiError {
+    ApiError::NotFound(format!("Notebook {} not found", id))
+}
+
+/// Fetches a notebook on behalf of `user`, hiding those of other users.
+async fn find_notebook(state: &AppState, user: &AuthUser, id: u32) -> Result<Notebook, ApiError> {
+    state
+        .store
+        .get_notebook(id)
+        .await?
+        .filter(|notebook| notebook.owner_id == user.id)
+        .ok_or_else(|| notebook_not_found(id))
+}
+
+/// Checks that a notebook a write refers to belongs to `user`.
+async fn check_target(state: &AppState, user: &AuthUser, id: Option<u32>) -> Result<(), ApiError> {
+    if let Some(id) = id {
+        find_notebook(state, user, id)
+            .await
+            .map_err(|_| StoreError::NotebookNotFound(id))?;
+    }
+    Ok(())
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/notebooks",
+    tag = "notebooks",
+    responses(
+        (status = 200, description = "The caller's notebooks", body = [Notebook]),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Not allowed for the caller", body = ErrorBody),
+    ),
+)]
+pub async fn list_notebooks(
+    State(state): State<AppState>,
+    user: AuthUser,
+) -> Result<Json<Vec<Notebook>>, ApiError> {
+    user.require(Scope::Read)?;
+    Ok(Json(state.store.list_notebooks(user.id).await?))
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/notebooks",
+    tag = "notebooks",
+    request_body = NotebookInput,
+    responses(
+        (status = 201, description = "The new notebook", body = Notebook, headers(("Location" = String))),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Not allowed for the caller", body = ErrorBody),
+        (status = 422, description = "Invalid input", body = ErrorBody),
+    ),
+)]
+pub async fn create_notebook(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Json(payload): Json<NotebookInput>,
+) -> Result<impl IntoResponse, ApiError> {
+    user.require(Scope::Write)?;
+    let input = payload.validate()?;
+    check_target(&state, &user, input.parent_id).await?;
+    let notebook = state.store.create_notebook(user.id, input).await?;
+    tracing::info!(
+        user_id = user.id,
+        notebook_id = notebook.id,
+        "notebook created"
+    );
+    Ok((
+        StatusCode::CREATED,
+        [(header::LOCATION, notebook.location())],
+        Json(notebook),
+    ))
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/notebooks/{id}",
+    tag = "notebooks",
+    params(("id" = u32, Path, description = "Notebook id")),
+    responses(
+        (status = 200, description = "The notebook", body = Notebook),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Not allowed for the caller", body = ErrorBody),
+        (status = 404, description = "Notebook not found", body = ErrorBody),
+    ),
+)]
+pub async fn read_notebook(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Path(id): Path<u32>,
+) -> Result<Json<Notebook>, ApiError> {
+    user.require(Scope::Read)?;
+    Ok(Json(find_notebook(&state, &user, id).await?))
+}
+
+/// Renames a notebook and moves it under `parent_id`.
+#[utoipa::path(
+    put,
+    path = "/v1/notebooks/{id}",
+    tag = "notebooks",
+    params(("id" = u32, Path, description = "Notebook id")),
+    request_body = NotebookInput,
+    responses(
+        (status = 200, description = "The updated notebook", body = Notebook),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Not allowed for the caller", body = ErrorBody),
+        (status = 404, description = "Notebook not found", body = ErrorBody),
+        (status = 422, description = "Invalid input", body = ErrorBody),
+    ),
+)]
+pub async fn update_notebook(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Path(id): Path<u32>,
+    Json(payload): Json<NotebookInput>,
+) -> Result<Json<Notebook>, ApiError> {
+    user.require(Scope::Write)?;
+    let input = payload.validate()?;
+    find_notebook(&state, &user, id).await?;
+    check_target(&state, &user, input.parent_id).await?;
+    let notebook = state
+        .store
+        .update_notebook(id, input)
+        .await?
+        .ok_or_else(|| notebook_not_found(id))?;
+    tracing::info!(user_id = user.id, notebook_id = id, "notebook updated");
+    Ok(Json(notebook))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/v1/notebooks/{id}",
+    tag = "notebooks",
+    params(("id" = u32, Path, description = "Notebook id"), DeleteParams),
+    responses(
+        (status = 204, description = "Notebook deleted"),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Not allowed for the caller", body = ErrorBody),
+        (status = 404, description = "Notebook not found", body = ErrorBody),
+        (status = 409, description = "Notebook not empty", body = ErrorBody),
+    ),
+)]
+pub async fn delete_notebook(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Path(id): Path<u32>,
+    Query(params): Query<DeleteParams>,
+) -> Result<StatusCode, ApiError> {
+    user.require(Scope::Delete)?;
+    let notebook = find_notebook(&state, &user, id).await?;
+    let events = state.events.publisher().await;
+    // A cascading delete trashes every note in the tree.
+    let trashed = if params.cascade {
+        let notebooks = state.store.list_notebooks(user.id).await?;
+        let notes = state.store.list(user.id).await?;
+        NotebookTree::build(notebook, notebooks, notes).into_notes()
+    } else {
+        Vec::new()
+    };
+    if !state.store.delete_notebook(id, params.cascade).await? {
+        return Err(notebook_not_found(id));
+    }
+    tracing::info!(
+        user_id = user.id,
+        notebook_id = id,
+        trashed = trashed.len(),
+        "notebook deleted"
+    );
+    for note in &trashed {
+        events.publish(EventKind::Deleted, note);
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/notebooks/{id}/contents",
+    tag = "notebooks",
+    params(("id" = u32, Path, description = "Notebook id")),
+    responses(
+        (status = 200, description = "The notebook with its notes and sub-notebooks", body = NotebookTree),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Not allowed for the caller", body = ErrorBody),
+        (status = 404, description = "Notebook not found", body = ErrorBody),
+    ),
+)]
+pub async fn notebook_contents(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Path(id): Path<u32>,
+) -> Result<Json<NotebookTree>, ApiError> {
+    user.require(Scope::Read)?;
+    let root = find_notebook(&state, &user, id).await?;
+    let notebooks = state.store.list_notebooks(user.id).await?;
+    let notes = state.store.list(user.id).await?;
+    Ok(Json(NotebookTree::build(root, notebooks, notes)))
+}
+
+#[utoipa::path(
+    put,
+    path = "/v1/notes/{id}/notebook",
+    tag = "notebooks",
+    params(("id" = u32, Path, description = "Note id"), ("If-Match" = Option<String>, Header, description = "Only write if the note is still at this `ETag`")),
+    request_body = MoveInput,
+    responses(
+        (status = 200, description = "The moved note", body = Note, headers(("ETag" = String))),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Not allowed for the caller", body = ErrorBody),
+        (status = 404, description = "Note not found", body = ErrorBody),
+        (status = 412, description = "`If-Match` does not match the current revision", body = ErrorBody),
+        (status = 422, description = "Invalid input", body = ErrorBody),
+    ),
+)]
+pub async fn move_note(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Path(id): Path<u32>,
+    if_match: IfMatch,
+    Json(payload): Json<MoveInput>,
+) -> Result<Response, ApiError> {
+    user.require(Scope::Write)?;
+    let note = find_note(&state, &user, id, Role::Owner).await?;
+    // Notebooks are private, so a note can only be filed by its owner.
+    if note.owner_id != user.id {
+        return Err(ApiError::Forbidden(format!(
+            "Only the owner of note {} can file it",
+            id
+        )));
+    }
+    let expected = if_match_revision(&note, &if_match)?;
+    check_target(&state, &user, payload.notebook_id).await?;
+    let events = state.events.publisher().await;
+    let note = state
+        .store
+        .move_note(id, payload.notebook_id, expected)
+        .await
+        .map_err(precondition)?
+        .ok_or_else(|| ApiError::note_not_found(id))?;
+    tracing::info!(
+        user_id = user.id,
+        note_id = id,
+        notebook_id = payload.notebook_id,
+        revision = note.revision,
+        "note moved"
+    );
+    events.publish(EventKind::Updated, &note);
+    Ok(tagged(note))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        store::NoteStore,
+        test_util::{json_body, request, seeded_store, test_app},
+    };
+
+    fn notebook(id: u32, parent_id: Option<u32>) -> Notebook {
+        Notebook {
+            id,
+            owner_id: 1,
+            name: format!("notebook {}", id),
+            parent_id,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    fn note(id: u32, notebook_id: Option<u32>) -> Note {
+        Note {
+            id,
+            owner_id: 1,
+            title: "title".into(),
+            note: "body".into(),
+            notebook_id,
+            tags: Default::default(),
+            revision: 1,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            deleted_at: None,
+        }
+    }
+
+    #[test]
+    fn builds_tree() {
+        let notebooks = vec![
+            notebook(1, None),
+            notebook(2, Some(1)),
+            notebook(3, Some(2)),
+            notebook(4, None),
+        ];
+        let notes = vec![note(1, Some(1)), note(2, Some(3)), note(3, Some(4))];
+        let tree = NotebookTree::build(notebook(1, None), notebooks, notes);
+
+        assert_eq!(tree.notes.len(), 1);
+        assert_eq!(tree.children.len(), 1);
+        let grandchild = &tree.children[0].children[0];
+        assert_eq!(grandchild.notebook.id, 3);
+        assert_eq!(grandchild.notes[0].id, 2);
+    }
+
+    #[test]
+    fn validates_names() {
+        let input = NotebookInput::new("  Work ".into(), None).validate();
+        assert_eq!(input.unwrap().name, "Work");
+        assert!(NotebookInput::new(" ".into(), None).validate().is_err());
+    }
+
+    #[tokio::test]
+    async fn notebooks() {
+        let store = seeded_store().await;
+        let app = test_app(store.clone());
+        let mut ids = Vec::new();
+        for (name, parent) in [("work", None), ("project", Some(1))] {
+            let response = app
+                .clone()
+                .oneshot(request(
+                    "POST",
+                    "/v1/notebooks",
+                    Some(json!({"name": name, "parent_id": parent})),
+                ))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::CREATED);
+            ids.push(json_body(response).await["id"].clone());
+        }
+        assert_eq!(ids, vec![json!(1), json!(2)]);
+
+        let response = app
+            .clone()
+            .oneshot(request(
+                "PUT",
+                "/v1/notes/1/notebook",
+                Some(json!({"notebook_id": 2})),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(json_body(response).await["notebook_id"], 2);
+
+        let response = app
+            .clone()
+            .oneshot(request(
+                "PUT",
+                "/v1/notebooks/1",
+                Some(json!({"name": "renamed", "parent_id": 2})),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let response = app
+            .clone()
+            .oneshot(request("GET", "/v1/notebooks/1/contents", None))
+            .await
+            .unwrap();
+        let tree = json_body(response).await;
+        assert_eq!(tree["notebook"]["name"], "work");
+        assert_eq!(tree["children"][0]["notes"][0]["id"], 1);
+
+        let response = app
+            .clone()
+            .oneshot(request("DELETE", "/v1/notebooks/1", None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        let response = app
+            .oneshot(request("DELETE", "/v1/notebooks/1?cascade=true", None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(store.get(1).await.unwrap(), None);
+        assert_eq!(store.list_trash(1).await.unwrap().len(), 1);
+    }
+}
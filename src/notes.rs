@@ -0,0 +1,381 @@
+use std::collections::BTreeSet;
+
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{
+    auth::AuthUser,
+    error::ApiError,
+    etag::{etag, ETagCondition, IfMatch, IfNoneMatch},
+    events::EventKind,
+    extract::{Json, Path, Query},
+    keys::Scope,
+    list::{self, ListParams, Page},
+    patch::NotePatch,
+    sharing::Role,
+    store::{NoteStore, StoreError},
+    AppState,
+};
+
+/// Client supplied content of a note, accepted by create and update.
+/// Server managed fields sent along with it are ignored.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq, PartialOrd, ToSchema)]
+pub struct NoteInput {
+    pub title: String,
+    pub note: String,
+}
+
+impl NoteInput {
+    pub fn new(title: String, note: String) -> Self {
+        Self { title, note }
+    }
+}
+
+impl From<&Note> for NoteInput {
+    fn from(note: &Note) -> Self {
+        Self::new(note.title.clone(), note.note.clone())
+    }
+}
+
+/// A stored note. Everything but `title` and `note` is managed by the store;
+/// `notebook_id` and `tags` are changed through their own endpoints.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, ToSchema)]
+pub struct Note {
+    pub id: u32,
+    /// The user who created the note.
+    pub owner_id: u32,
+    pub title: String,
+    pub note: String,
+    /// The notebook the note is filed in; `None` at the top level.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notebook_id: Option<u32>,
+    #[serde(default)]
+    pub tags: BTreeSet<String>,
+    /// Starts at 1 and increases with every write. Exposed as the `ETag`.
+    pub revision: u64,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    /// Set while the note is in the trash.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+impl Note {
+    pub fn location(&self) -> String {
+        format!("/v1/notes/{}", self.id)
+    }
+}
+
+/// The note as JSON with its `ETag`.
+pub(crate) fn tagged(note: Note) -> Response {
+    ([(header::ETAG, etag(note.revision))], Json(note)).into_response()
+}
+
+/// A conflicting conditional write means the revision the client matched
+/// against is gone.
+pub(crate) fn precondition(e: StoreError) -> ApiError {
+    match e {
+        StoreError::Conflict => {
+            ApiError::PreconditionFailed("Note was modified concurrently".into())
+        }
+        e => e.into(),
+    }
+}
+
+/// The role `user` has on `note`, if any.
+pub(crate) async fn role(
+    state: &AppState,
+    user: &AuthUser,
+    note: &Note,
+) -> Result<Option<Role>, ApiError> {
+    Ok(role_of(state.store.as_ref(), user.id, note).await?)
+}
+
+/// The role of the user `user_id` on `note`, for when there is no request
+/// to take the user from.
+pub(crate) async fn role_of(
+    store: &dyn NoteStore,
+    user_id: u32,
+    note: &Note,
+) -> Result<Option<Role>, StoreError> {
+    if note.owner_id == user_id {
+        return Ok(Some(Role::Owner));
+    }
+    let grants = store.grants(note.id).await?;
+    Ok(grants
+        .into_iter()
+        .find(|grant| grant.user_id == user_id)
+        .map(|grant| grant.role))
+}
+
+/// Fetches a live note on behalf of `user`, who needs at least `needed` on
+/// it. Notes the user has no role on are reported as missing so that their
+/// ids do not leak.
+pub(crate) async fn find_note(
+    state: &AppState,
+    user: &AuthUser,
+    id: u32,
+    needed: Role,
+) -> Result<Note, ApiError> {
+    let note = state
+        .store
+        .get(id)
+        .await?
+        .ok_or_else(|| ApiError::note_not_found(id))?;
+    match role(state, user, &note).await? {
+        None => Err(ApiError::note_not_found(id)),
+        Some(role) if role < needed => Err(ApiError::Forbidden(format!(
+            "Note {} needs the `{}` role",
+            id, needed
+        ))),
+        Some(_) => Ok(note),
+    }
+}
+
+/// Resolves `If-Match` to the revision a write to `note` must find. `*`
+/// only asks for the note to exist, whatever its revision.
+pub(crate) fn if_match_revision(note: &Note, if_match: &IfMatch) -> Result<Option<u64>, ApiError> {
+    match &if_match.0 {
+        None | Some(ETagCondition::Any) => Ok(None),
+        Some(_) => {
+            if_match.check(note.revision)?;
+            Ok(Some(note.revision))
+        }
+    }
+}
+
+/// Checks that `user` has at least `needed` on the note and resolves
+/// `If-Match` to the revision the write must find.
+pub(crate) async fn expected_revision(
+    state: &AppState,
+    user: &AuthUser,
+    id: u32,
+    needed: Role,
+    if_match: &IfMatch,
+) -> Result<Option<u64>, ApiError> {
+    let note = find_note(state, user, id, needed).await?;
+    if_match_revision(&note, if_match)
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/notes",
+    tag = "notes",
+    request_body = NoteInput,
+    responses(
+        (status = 201, description = "The new note", body = Note, headers(("Location" = String), ("ETag" = String))),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Not allowed for the caller", body = ErrorBody),
+        (status = 422, description = "Invalid input", body = ErrorBody),
+    ),
+)]
+pub async fn create_note(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Json(payload): Json<NoteInput>,
+) -> Result<impl IntoResponse, ApiError> {
+    user.require(Scope::Write)?;
+    let events = state.events.publisher().await;
+    let note = state.store.create(user.id, payload).await?;
+    tracing::info!(user_id = user.id, note_id = note.id, "note created");
+    events.publish(EventKind::Created, &note);
+    Ok((
+        StatusCode::CREATED,
+        [(header::LOCATION, note.location())],
+        tagged(note),
+    ))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/v1/notes/{id}",
+    tag = "notes",
+    params(("id" = u32, Path, description = "Note id"), ("If-Match" = Option<String>, Header, description = "Only write if the note is still at this `ETag`")),
+    responses(
+        (status = 204, description = "Note moved to the trash"),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Not allowed for the caller", body = ErrorBody),
+        (status = 404, description = "Note not found", body = ErrorBody),
+        (status = 412, description = "`If-Match` does not match the current revision", body = ErrorBody),
+    ),
+)]
+pub async fn delete_note(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Path(id): Path<u32>,
+    if_match: IfMatch,
+) -> Result<StatusCode, ApiError> {
+    user.require(Scope::Delete)?;
+    let note = find_note(&state, &user, id, Role::Owner).await?;
+    let expected = if_match_revision(&note, &if_match)?;
+    let events = state.events.publisher().await;
+    if !state
+        .store
+        .delete(id, expected)
+        .await
+        .map_err(precondition)?
+    {
+        return Err(ApiError::note_not_found(id));
+    }
+    tracing::info!(user_id = user.id, note_id = id, "note deleted");
+    events.publish(EventKind::Deleted, &note);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    put,
+    path = "/v1/notes/{id}",
+    tag = "notes",
+    params(("id" = u32, Path, description = "Note id"), ("If-Match" = Option<String>, Header, description = "Only write if the note is still at this `ETag`")),
+    request_body = NoteInput,
+    responses(
+        (status = 200, description = "The updated note", body = Note, headers(("ETag" = String))),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Not allowed for the caller", body = ErrorBody),
+        (status = 404, description = "Note not found", body = ErrorBody),
+        (status = 412, description = "`If-Match` does not match the current revision", body = ErrorBody),
+        (status = 422, description = "Invalid input", body = ErrorBody),
+    ),
+)]
+pub async fn update_note(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Path(id): Path<u32>,
+    if_match: IfMatch,
+    Json(payload): Json<NoteInput>,
+) -> Result<Response, ApiError> {
+    user.require(Scope::Write)?;
+    let expected = expected_revision(&state, &user, id, Role::Editor, &if_match).await?;
+    let events = state.events.publisher().await;
+    let note = state
+        .store
+        .update(id, payload, expected)
+        .await
+        .map_err(precondition)?
+        .ok_or_else(|| ApiError::note_not_found(id))?;
+    tracing::info!(
+        user_id = user.id,
+        note_id = id,
+        revision = note.revision,
+        "note updated"
+    );
+    events.publish(EventKind::Updated, &note);
+    Ok(tagged(note))
+}
+
+/// How often a patch is re-applied when the note changes underneath it.
+const PATCH_ATTEMPTS: usize = 3;
+
+/// Applies a merge patch or JSON patch. The patch is computed against the
+/// stored note and only written if that note is still current, so
+/// concurrent writes are never lost.
+#[utoipa::path(
+    patch,
+    path = "/v1/notes/{id}",
+    tag = "notes",
+    params(("id" = u32, Path, description = "Note id"), ("If-Match" = Option<String>, Header, description = "Only write if the note is still at this `ETag`")),
+    request_body(content = Object, content_type = "application/merge-patch+json", description = "A JSON merge patch, or a JSON patch sent as `application/json-patch+json`"),
+    responses(
+        (status = 200, description = "The patched note", body = Note, headers(("ETag" = String))),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Not allowed for the caller", body = ErrorBody),
+        (status = 404, description = "Note not found", body = ErrorBody),
+        (status = 409, description = "A JSON patch `test` failed", body = ErrorBody),
+        (status = 412, description = "`If-Match` does not match the current revision", body = ErrorBody),
+        (status = 415, description = "Neither a merge patch nor a JSON patch", body = ErrorBody),
+        (status = 422, description = "Invalid input", body = ErrorBody),
+    ),
+)]
+pub async fn patch_note(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Path(id): Path<u32>,
+    if_match: IfMatch,
+    patch: NotePatch,
+) -> Result<Response, ApiError> {
+    user.require(Scope::Write)?;
+    for _ in 0..PATCH_ATTEMPTS {
+        let note = find_note(&state, &user, id, Role::Editor).await?;
+        if_match.check(note.revision)?;
+        let input = patch.apply(&note)?;
+        let events = state.events.publisher().await;
+        match state.store.update(id, input, Some(note.revision)).await {
+            Ok(Some(note)) => {
+                tracing::info!(
+                    user_id = user.id,
+                    note_id = id,
+                    revision = note.revision,
+                    "note patched"
+                );
+                events.publish(EventKind::Updated, &note);
+                return Ok(tagged(note));
+            }
+            Ok(None) => return Err(ApiError::note_not_found(id)),
+            Err(StoreError::Conflict) => {
+                tracing::debug!(note_id = id, "note changed while patching, retrying");
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Err(StoreError::Conflict.into())
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/notes",
+    tag = "notes",
+    params(ListParams),
+    responses(
+        (status = 200, description = "One page of the caller's notes", body = NotePage),
+        (status = 400, description = "Malformed request", body = ErrorBody),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Not allowed for the caller", body = ErrorBody),
+    ),
+)]
+pub async fn list_notes(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Query(params): Query<ListParams>,
+) -> Result<Json<Page<Note>>, ApiError> {
+    user.require(Scope::Read)?;
+    let notes = state.store.list(user.id).await?;
+    Ok(Json(list::paginate(notes, &params)?))
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/notes/{id}",
+    tag = "notes",
+    params(("id" = u32, Path, description = "Note id"), ("If-None-Match" = Option<String>, Header, description = "Answer 304 if the note still has this `ETag`")),
+    responses(
+        (status = 200, description = "The note", body = Note, headers(("ETag" = String))),
+        (status = 304, description = "The note still matches `If-None-Match`"),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Not allowed for the caller", body = ErrorBody),
+        (status = 404, description = "Note not found", body = ErrorBody),
+    ),
+)]
+pub async fn read_note(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Path(id): Path<u32>,
+    if_none_match: IfNoneMatch,
+) -> Result<Response, ApiError> {
+    user.require(Scope::Read)?;
+    let note = find_note(&state, &user, id, Role::Viewer).await?;
+    if if_none_match.is_fresh(note.revision) {
+        return Ok((
+            StatusCode::NOT_MODIFIED,
+            [(header::ETAG, etag(note.revision))],
+        )
+            .into_response());
+    }
+    Ok(tagged(note))
+}
@@ -0,0 +1,306 @@
+//! The OpenAPI description of the API, generated from the handlers and the
+//! types they take and return, and a page rendering it.
+//!
+//! Every `/v1` handler carries a `#[utoipa::path]` attribute; a handler added
+//! to the router also needs adding to [`ApiDoc`]. The legacy routes are left
+//! out since new clients should not use them.
+
+use std::sync::OnceLock;
+
+use axum::response::Html;
+use utoipa::{
+    openapi::{
+        security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme},
+        OpenApi as Document,
+    },
+    Modify, OpenApi,
+};
+
+use crate::{
+    auth, error, events, extract::Json, history, keys, list, notebooks, notes, search, sharing,
+    tags, trash, webhooks, workspaces,
+};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Notes API",
+        description = "Notes with history, notebooks, tags, sharing, search, a change feed \
+            and webhooks. Requests go to the default workspace unless they carry an \
+            `X-Workspace` header or a `/w/{name}` path prefix."
+    ),
+    paths(
+        auth::register,
+        auth::login,
+        auth::me,
+        keys::create_key,
+        keys::list_keys,
+        keys::revoke_key,
+        workspaces::list_workspaces,
+        workspaces::create_workspace,
+        workspaces::list_users,
+        workspaces::set_admin,
+        notes::list_notes,
+        notes::create_note,
+        notes::read_note,
+        notes::update_note,
+        notes::patch_note,
+        notes::delete_note,
+        history::list_revisions,
+        history::read_revision,
+        history::restore_revision,
+        history::diff_revisions,
+        notebooks::move_note,
+        sharing::list_grants,
+        sharing::put_grant,
+        sharing::delete_grant,
+        sharing::list_links,
+        sharing::create_link,
+        sharing::delete_link,
+        sharing::list_shared,
+        sharing::read_public,
+        notebooks::list_notebooks,
+        notebooks::create_notebook,
+        notebooks::read_notebook,
+        notebooks::update_notebook,
+        notebooks::delete_notebook,
+        notebooks::notebook_contents,
+        tags::add_tags,
+        tags::remove_tag,
+        tags::list_tags,
+        tags::rename_tag,
+        search::search_notes,
+        events::stream_events,
+        events::events_socket,
+        webhooks::list_webhooks,
+        webhooks::create_webhook,
+        webhooks::delete_webhook,
+        webhooks::list_deliveries,
+        webhooks::redeliver,
+        trash::list_trash,
+        trash::purge_note,
+        trash::restore_note,
+    ),
+    components(schemas(
+        error::ErrorBody,
+        error::ErrorDetail,
+        auth::User,
+        auth::Token,
+        auth::Credentials,
+        keys::Scope,
+        keys::ApiKey,
+        keys::ApiKeyInput,
+        keys::IssuedKey,
+        workspaces::WorkspaceInput,
+        workspaces::NewWorkspace,
+        workspaces::AdminInput,
+        notes::Note,
+        notes::NoteInput,
+        list::NotePage,
+        list::SortField,
+        list::SortOrder,
+        history::Revision,
+        history::RevisionDiff,
+        history::DiffLine,
+        history::LineOp,
+        notebooks::Notebook,
+        notebooks::NotebookInput,
+        notebooks::NotebookTree,
+        notebooks::MoveInput,
+        tags::TagCount,
+        tags::TagsInput,
+        tags::RenameInput,
+        tags::TagRename,
+        sharing::Role,
+        sharing::Grant,
+        sharing::GrantInput,
+        sharing::SharedNote,
+        sharing::IssuedLink,
+        sharing::ShareLink,
+        sharing::ShareLinkInput,
+        sharing::PublicNote,
+        search::SearchHit,
+        search::SearchResults,
+        events::EventKind,
+        events::NoteEvent,
+        webhooks::Webhook,
+        webhooks::WebhookInput,
+        webhooks::Delivery,
+        webhooks::DeliveryStatus,
+    )),
+    modifiers(&SecuritySchemes),
+    security(("bearer" = []), ("api_key" = [])),
+)]
+pub struct ApiDoc;
+
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut Document) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .description(Some(
+                        "A token from `/v1/auth/login`, or an API key".to_owned(),
+                    ))
+                    .build(),
+            ),
+        );
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("X-Api-Key"))),
+        );
+    }
+}
+
+/// The document is the same for every request, so it is built once.
+fn document() -> &'static Document {
+    static DOCUMENT: OnceLock<Document> = OnceLock::new();
+    DOCUMENT.get_or_init(ApiDoc::openapi)
+}
+
+pub async fn openapi_json() -> Json<&'static Document> {
+    Json(document())
+}
+
+/// Interactive documentation rendering `openapi.json` with Swagger UI, which
+/// the browser loads from a CDN.
+pub async fn docs() -> Html<&'static str> {
+    Html(DOCS_PAGE)
+}
+
+/// The spec URL is relative so that the page also works under a `/w/{name}`
+/// workspace prefix.
+const DOCS_PAGE: &str = r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+  <meta charset="utf-8">
+  <title>Notes API</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+  <div id="docs"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+  <script>
+    SwaggerUIBundle({ url: "openapi.json", dom_id: "#docs" });
+  </script>
+</body>
+</html>
+"##;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        body::Body,
+        extract::Request,
+        http::{header, Method, StatusCode},
+    };
+    use tower::ServiceExt;
+    use utoipa::openapi::PathItemType;
+
+    use crate::test_util::{json_body, seeded_store, test_app};
+
+    /// Every `$ref` in `value`.
+    fn refs(value: &serde_json::Value, found: &mut Vec<String>) {
+        match value {
+            serde_json::Value::Object(map) => {
+                for (key, value) in map {
+                    match (key.as_str(), value) {
+                        ("$ref", serde_json::Value::String(target)) => found.push(target.clone()),
+                        _ => refs(value, found),
+                    }
+                }
+            }
+            serde_json::Value::Array(values) => values.iter().for_each(|v| refs(v, found)),
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn schemas_resolve() {
+        let document = serde_json::to_value(document()).unwrap();
+        let mut found = Vec::new();
+        refs(&document, &mut found);
+        assert!(!found.is_empty());
+        for target in found {
+            let name = target.trim_start_matches("#/components/schemas/");
+            assert!(
+                document["components"]["schemas"].get(name).is_some(),
+                "{target} is not defined"
+            );
+        }
+    }
+
+    /// Every documented operation is routed: requests to it may fail, but
+    /// not for lack of a route.
+    #[tokio::test]
+    async fn operations_are_routed() {
+        let app = test_app(seeded_store().await);
+        for (path, item) in &document().paths.paths {
+            let uri: String = path
+                .split('/')
+                .map(|segment| match segment.starts_with('{') {
+                    true => "1",
+                    false => segment,
+                })
+                .collect::<Vec<_>>()
+                .join("/");
+            for operation in item.operations.keys() {
+                let method = match operation {
+                    PathItemType::Get => Method::GET,
+                    PathItemType::Post => Method::POST,
+                    PathItemType::Put => Method::PUT,
+                    PathItemType::Patch => Method::PATCH,
+                    PathItemType::Delete => Method::DELETE,
+                    _ => panic!("unexpected operation on {path}"),
+                };
+                let request = Request::builder()
+                    .method(method.clone())
+                    .uri(&uri)
+                    .body(Body::empty())
+                    .unwrap();
+                let response = app.clone().oneshot(request).await.unwrap();
+                assert_ne!(
+                    response.status(),
+                    StatusCode::METHOD_NOT_ALLOWED,
+                    "{method} {uri}"
+                );
+                if response.status() == StatusCode::NOT_FOUND {
+                    // Handlers explain themselves; missing routes do not.
+                    assert!(
+                        json_body(response).await["error"].is_object(),
+                        "{method} {uri}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn serves_document_and_docs() {
+        let app = test_app(seeded_store().await);
+        let request = Request::get("/openapi.json").body(Body::empty()).unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = json_body(response).await;
+        assert!(body["openapi"].as_str().unwrap().starts_with("3."));
+        assert_eq!(
+            body["paths"]["/v1/notes/{id}"]["get"]["responses"]["200"]["content"]
+                ["application/json"]["schema"]["$ref"],
+            "#/components/schemas/Note"
+        );
+        assert!(body["components"]["securitySchemes"]["bearer"].is_object());
+
+        let request = Request::get("/w/default/docs").body(Body::empty()).unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers()[header::CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .starts_with("text/html"));
+    }
+}
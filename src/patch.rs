@@ -0,0 +1,200 @@
+//! Partial note updates via JSON Merge Patch (RFC 7396) and JSON Patch
+//! (RFC 6902).
+
+use async_trait::async_trait;
+use axum::{
+    body::Bytes,
+    extract::{FromRequest, Request},
+    http::header,
+};
+use json_patch::{PatchErrorKind, PatchOperation};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{error::ApiError, Note, NoteInput};
+
+pub const MERGE_PATCH: &str = "application/merge-patch+json";
+pub const JSON_PATCH: &str = "application/json-patch+json";
+
+/// Fields of the note representation that a patch must leave untouched.
+const READ_ONLY: &[&str] = &[
+    "id",
+    "owner_id",
+    "notebook_id",
+    "tags",
+    "revision",
+    "created_at",
+    "updated_at",
+    "deleted_at",
+];
+
+/// A patch document, chosen by the request's `Content-Type`. Plain
+/// `application/json` is treated as a merge patch.
+#[derive(Debug, Clone, PartialEq)]
+pub enum NotePatch {
+    Merge(Value),
+    Json(Vec<PatchOperation>),
+}
+
+/// The editable part of a patched note. Unlike [`NoteInput`] this rejects
+/// unknown fields so that a patch cannot silently add them.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Patched {
+    title: String,
+    note: String,
+}
+
+impl NotePatch {
+    pub fn parse(content_type: &str, body: &[u8]) -> Result<Self, ApiError> {
+        let mime = content_type.split(';').next().unwrap_or("").trim();
+        let invalid = |e: serde_json::Error| ApiError::BadRequest(format!("Invalid patch: {}", e));
+        match mime {
+            MERGE_PATCH | "application/json" => Ok(NotePatch::Merge(
+                serde_json::from_slice(body).map_err(invalid)?,
+            )),
+            JSON_PATCH => Ok(NotePatch::Json(
+                serde_json::from_slice(body).map_err(invalid)?,
+            )),
+            _ => Err(ApiError::UnsupportedMediaType(format!(
+                "PATCH expects {} or {}",
+                MERGE_PATCH, JSON_PATCH
+            ))),
+        }
+    }
+
+    /// Applies the patch to the representation of `note` and returns the
+    /// resulting content. Nothing is written; the caller stores the result.
+    pub fn apply(&self, note: &Note) -> Result<NoteInput, ApiError> {
+        let original = serde_json::to_value(note).expect("notes serialize");
+        let mut doc = original.clone();
+        match self {
+            NotePatch::Merge(patch) => json_patch::merge(&mut doc, patch),
+            NotePatch::Json(ops) => json_patch::patch(&mut doc, ops).map_err(|e| match e.kind {
+                PatchErrorKind::TestFailed => {
+                    ApiError::Conflict(format!("Patch test failed: {}", e))
+                }
+                _ => ApiError::Unprocessable(format!("Invalid patch: {}", e)),
+            })?,
+        }
+
+        let Value::Object(mut fields) = doc else {
+            return Err(ApiError::Unprocessable(
+                "Patched note must be an object".into(),
+            ));
+        };
+        for field in READ_ONLY {
+            if fields.remove(*field).as_ref() != original.get(*field) {
+                return Err(ApiError::Unprocessable(format!("`{}` is read-only", field)));
+            }
+        }
+        let patched: Patched = serde_json::from_value(Value::Object(fields))
+            .map_err(|e| ApiError::Unprocessable(format!("Invalid patched note: {}", e)))?;
+        Ok(NoteInput::new(patched.title, patched.note))
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequest<S> for NotePatch {
+    type Rejection = ApiError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let content_type = req
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or("")
+            .to_owned();
+        let body = Bytes::from_request(req, state).await?;
+        NotePatch::parse(&content_type, &body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use serde_json::json;
+
+    fn note() -> Note {
+        Note {
+            id: 1,
+            owner_id: 1,
+            title: "title".into(),
+            note: "body".into(),
+            notebook_id: None,
+            tags: Default::default(),
+            revision: 1,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            deleted_at: None,
+        }
+    }
+
+    fn json_patch(ops: Value) -> NotePatch {
+        NotePatch::parse(JSON_PATCH, ops.to_string().as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn merge_patch() {
+        let patch = NotePatch::parse(MERGE_PATCH, br#"{"note": "merged"}"#).unwrap();
+        assert_eq!(
+            patch.apply(&note()).unwrap(),
+            NoteInput::new("title".into(), "merged".into())
+        );
+    }
+
+    #[test]
+    fn json_patch_ops() {
+        let patch = json_patch(json!([
+            {"op": "test", "path": "/title", "value": "title"},
+            {"op": "copy", "from": "/title", "path": "/note"},
+            {"op": "replace", "path": "/title", "value": "new"},
+        ]));
+        assert_eq!(
+            patch.apply(&note()).unwrap(),
+            NoteInput::new("new".into(), "title".into())
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_paths() {
+        let unknown = json_patch(json!([{"op": "replace", "path": "/nope", "value": 1}]));
+        assert!(matches!(
+            unknown.apply(&note()),
+            Err(ApiError::Unprocessable(_))
+        ));
+
+        let added = json_patch(json!([{"op": "add", "path": "/extra", "value": 1}]));
+        assert!(matches!(
+            added.apply(&note()),
+            Err(ApiError::Unprocessable(_))
+        ));
+
+        let removed = json_patch(json!([{"op": "remove", "path": "/title"}]));
+        assert!(matches!(
+            removed.apply(&note()),
+            Err(ApiError::Unprocessable(_))
+        ));
+
+        let read_only = NotePatch::parse(MERGE_PATCH, br#"{"id": 5}"#).unwrap();
+        assert_eq!(
+            read_only.apply(&note()),
+            Err(ApiError::Unprocessable("`id` is read-only".into()))
+        );
+    }
+
+    #[test]
+    fn failed_test_is_conflict() {
+        let patch = json_patch(json!([{"op": "test", "path": "/title", "value": "other"}]));
+        assert!(matches!(patch.apply(&note()), Err(ApiError::Conflict(_))));
+    }
+
+    #[test]
+    fn rejects_other_content_types() {
+        assert!(matches!(
+            NotePatch::parse("text/plain", b"{}"),
+            Err(ApiError::UnsupportedMediaType(_))
+        ));
+    }
+}
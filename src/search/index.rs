@@ -0,0 +1,344 @@
+//! In-memory inverted index with BM25 ranking, phrase matching and
+//! highlighted snippets.
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::OnceLock,
+};
+
+use rust_stemmers::{Algorithm, Stemmer};
+
+use super::query::{Clause, SearchQuery};
+use crate::Note;
+
+const K1: f64 = 1.2;
+const B: f64 = 0.75;
+/// Indexed fields are the title followed by the body.
+const FIELDS: usize = 2;
+const NOTE: usize = 1;
+/// Title matches count twice as much as body matches.
+const FIELD_WEIGHTS: [f64; FIELDS] = [2.0, 1.0];
+
+/// Number of words shown in a snippet, and how many of them come before the
+/// first match.
+const SNIPPET_WORDS: usize = 16;
+const SNIPPET_LEAD: usize = 4;
+
+fn stemmer() -> &'static Stemmer {
+    static STEMMER: OnceLock<Stemmer> = OnceLock::new();
+    STEMMER.get_or_init(|| Stemmer::create(Algorithm::English))
+}
+
+/// A word of a field: its stemmed, lowercased term and its byte range in the
+/// original text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub term: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Splits `text` into alphanumeric words and normalizes each to a term.
+pub fn tokenize(text: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+    for (i, c) in text.char_indices().chain([(text.len(), ' ')]) {
+        match (start, c.is_alphanumeric()) {
+            (None, true) => start = Some(i),
+            (Some(s), false) => {
+                let word = text[s..i].to_lowercase();
+                tokens.push(Token {
+                    term: stemmer().stem(&word).into_owned(),
+                    start: s,
+                    end: i,
+                });
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    tokens
+}
+
+struct Document {
+    note: String,
+    tokens: [Vec<Token>; FIELDS],
+}
+
+/// Positions of one term in one document, per field.
+type Positions = [Vec<u32>; FIELDS];
+
+#[derive(Default)]
+pub struct Index {
+    docs: HashMap<u32, Document>,
+    postings: HashMap<String, HashMap<u32, Positions>>,
+    total_len: [usize; FIELDS],
+}
+
+/// A ranked match.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hit {
+    pub id: u32,
+    pub score: f64,
+    pub snippet: String,
+}
+
+impl Index {
+    pub fn build(notes: &[Note]) -> Self {
+        let mut index = Index::default();
+        for note in notes {
+            index.insert(note);
+        }
+        index
+    }
+
+    /// Indexes `note`, replacing whatever was indexed under its id.
+    pub fn insert(&mut self, note: &Note) {
+        self.remove(note.id);
+        let tokens = [tokenize(&note.title), tokenize(&note.note)];
+        for (field, field_tokens) in tokens.iter().enumerate() {
+            self.total_len[field] += field_tokens.len();
+            for (position, token) in field_tokens.iter().enumerate() {
+                self.postings
+                    .entry(token.term.clone())
+                    .or_default()
+                    .entry(note.id)
+                    .or_default()[field]
+                    .push(position as u32);
+            }
+        }
+        self.docs.insert(
+            note.id,
+            Document {
+                note: note.note.clone(),
+                tokens,
+            },
+        );
+    }
+
+    pub fn remove(&mut self, id: u32) {
+        let Some(doc) = self.docs.remove(&id) else {
+            return;
+        };
+        for (field, field_tokens) in doc.tokens.iter().enumerate() {
+            self.total_len[field] -= field_tokens.len();
+            for token in field_tokens {
+                if let Some(docs) = self.postings.get_mut(&token.term) {
+                    docs.remove(&id);
+                    if docs.is_empty() {
+                        self.postings.remove(&token.term);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns the documents matching every clause of `query`, best first.
+    pub fn search(&self, query: &SearchQuery) -> Vec<Hit> {
+        let Some(first) = query.clauses.first() else {
+            return Vec::new();
+        };
+        let candidates: Vec<u32> = match self.postings.get(first.terms()[0].as_str()) {
+            Some(docs) => docs.keys().copied().collect(),
+            None => return Vec::new(),
+        };
+        let terms = query.terms();
+
+        let mut hits: Vec<Hit> = candidates
+            .into_iter()
+            .filter(|id| query.clauses.iter().all(|clause| self.matches(*id, clause)))
+            .map(|id| Hit {
+                id,
+                score: terms.iter().map(|term| self.score(id, term)).sum(),
+                snippet: self.snippet(id, &terms),
+            })
+            .collect();
+        hits.sort_by(|a, b| b.score.total_cmp(&a.score).then(a.id.cmp(&b.id)));
+        hits
+    }
+
+    fn matches(&self, id: u32, clause: &Clause) -> bool {
+        let positions = |term: &str| self.postings.get(term).and_then(|docs| docs.get(&id));
+        match clause {
+            Clause::Term(term) => positions(term).is_some(),
+            Clause::Phrase(terms) => {
+                let Some(all): Option<Vec<&Positions>> =
+                    terms.iter().map(|term| positions(term)).collect()
+                else {
+                    return false;
+                };
+                (0..FIELDS).any(|field| {
+                    all[0][field].iter().any(|&start| {
+                        all.iter()
+                            .enumerate()
+                            .skip(1)
+                            .all(|(offset, p)| p[field].contains(&(start + offset as u32)))
+                    })
+                })
+            }
+        }
+    }
+
+    /// BM25 contribution of `term` to the score of document `id`, summed over
+    /// the weighted fields.
+    fn score(&self, id: u32, term: &str) -> f64 {
+        let Some(docs) = self.postings.get(term) else {
+            return 0.0;
+        };
+        let Some(positions) = docs.get(&id) else {
+            return 0.0;
+        };
+        let n = self.docs.len() as f64;
+        let df = docs.len() as f64;
+        let idf = (1.0 + (n - df + 0.5) / (df + 0.5)).ln();
+        let doc = &self.docs[&id];
+
+        (0..FIELDS)
+            .map(|field| {
+                let tf = positions[field].len() as f64;
+                if tf == 0.0 {
+                    return 0.0;
+                }
+                let len = doc.tokens[field].len() as f64;
+                let avg = (self.total_len[field] as f64 / n).max(1.0);
+                FIELD_WEIGHTS[field] * tf * (K1 + 1.0) / (tf + K1 * (1.0 - B + B * len / avg))
+            })
+            .sum::<f64>()
+            * idf
+    }
+
+    /// An excerpt of the body around the first match, with matching words
+    /// wrapped in `<mark>`. The result is HTML: the body text is escaped.
+    fn snippet(&self, id: u32, terms: &HashSet<&str>) -> String {
+        let doc = &self.docs[&id];
+        let tokens = &doc.tokens[NOTE];
+        if tokens.is_empty() {
+            return String::new();
+        }
+        let first_hit = tokens
+            .iter()
+            .position(|token| terms.contains(token.term.as_str()))
+            .unwrap_or(0);
+        let start = first_hit.saturating_sub(SNIPPET_LEAD);
+        let end = (start + SNIPPET_WORDS).min(tokens.len());
+
+        let mut snippet = String::new();
+        if start > 0 {
+            snippet.push('…');
+        }
+        let mut cursor = tokens[start].start;
+        for token in &tokens[start..end] {
+            escape_into(&mut snippet, &doc.note[cursor..token.start]);
+            let word = &doc.note[token.start..token.end];
+            if terms.contains(token.term.as_str()) {
+                snippet.push_str("<mark>");
+                escape_into(&mut snippet, word);
+                snippet.push_str("</mark>");
+            } else {
+                escape_into(&mut snippet, word);
+            }
+            cursor = token.end;
+        }
+        if end < tokens.len() {
+            snippet.push('…');
+        }
+        snippet
+    }
+}
+
+fn escape_into(out: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            _ => out.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn note(id: u32, title: &str, body: &str) -> Note {
+        Note {
+            id,
+            owner_id: 1,
+            title: title.into(),
+            note: body.into(),
+            notebook_id: None,
+            tags: Default::default(),
+            revision: 1,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            deleted_at: None,
+        }
+    }
+
+    fn ids(index: &Index, q: &str) -> Vec<u32> {
+        index
+            .search(&SearchQuery::parse(q))
+            .iter()
+            .map(|hit| hit.id)
+            .collect()
+    }
+
+    #[test]
+    fn tokenizes_and_stems() {
+        let terms: Vec<_> = tokenize("Running, runs & RAN!")
+            .into_iter()
+            .map(|token| token.term)
+            .collect();
+        assert_eq!(terms, vec!["run", "run", "ran"]);
+    }
+
+    #[test]
+    fn ranks_and_requires_every_term() {
+        let index = Index::build(&[
+            note(1, "groceries", "buy milk and eggs"),
+            note(2, "milk", "remember the milk"),
+            note(3, "chores", "walk the dog"),
+        ]);
+        assert_eq!(ids(&index, "milk"), vec![2, 1]);
+        assert_eq!(ids(&index, "milk eggs"), vec![1]);
+        assert!(ids(&index, "cats").is_empty());
+    }
+
+    #[test]
+    fn phrases() {
+        let index = Index::build(&[
+            note(1, "a", "the quick brown fox"),
+            note(2, "b", "brown and quick"),
+        ]);
+        assert_eq!(ids(&index, "\"quick brown\""), vec![1]);
+        // Without quotes the shorter note ranks first.
+        assert_eq!(ids(&index, "quick brown"), vec![2, 1]);
+    }
+
+    #[test]
+    fn updates_and_removals() {
+        let mut index = Index::build(&[note(1, "a", "old words")]);
+        index.insert(&note(1, "a", "new words"));
+        assert!(ids(&index, "old").is_empty());
+        assert_eq!(ids(&index, "new"), vec![1]);
+        index.remove(1);
+        assert!(ids(&index, "words").is_empty());
+        assert_eq!(index.total_len, [0, 0]);
+    }
+
+    #[test]
+    fn highlighted_snippet() {
+        let body = "one two three four five six seven <eight> nine ten eleven twelve \
+                    thirteen fourteen fifteen sixteen seventeen eighteen nineteen";
+        let index = Index::build(&[note(1, "t", body)]);
+        let hits = index.search(&SearchQuery::parse("eight"));
+        assert_eq!(
+            hits[0].snippet,
+            "…four five six seven &lt;<mark>eight</mark>&gt; nine ten eleven twelve \
+             thirteen fourteen fifteen sixteen seventeen eighteen nineteen"
+        );
+    }
+}
@@ -0,0 +1,547 @@
+//! Full-text search over note titles and bodies.
+//!
+//! [`IndexedStore`] wraps the real store and keeps an inverted index in step
+//! with every write that goes through it.
+
+mod index;
+mod query;
+
+use std::{
+    collections::{hash_map::Entry, BTreeSet, HashMap},
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use axum::extract::State;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex, MutexGuard};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    auth::{AuthUser, User},
+    error::ApiError,
+    events::EventKind,
+    extract::{Json, Query},
+    history::Revision,
+    keys::Scope,
+    keys::{ApiKey, ApiKeyInput},
+    list::{DEFAULT_LIMIT, MAX_LIMIT},
+    metrics,
+    notebooks::{Notebook, NotebookInput},
+    sharing::{Grant, Role, ShareLink},
+    store::{NoteStore, StoreError},
+    tags::TagCount,
+    webhooks::{Attempt, Delivery, Webhook, WebhookInput},
+    AppState, Note, NoteInput,
+};
+
+pub use index::{tokenize, Token};
+pub use query::{Clause, SearchQuery};
+
+use index::Index;
+
+/// A [`NoteStore`] that indexes the live notes of the store it wraps.
+///
+/// Every user gets an index of their own, built from the wrapped store the
+/// first time they search. Writes hold the index lock until the indexes
+/// reflect them, so a search never sees a write half applied.
+///
+/// Every workspace's store is wrapped in one, which makes this the place
+/// where store operations are timed for [`metrics`].
+pub struct IndexedStore {
+    store: Arc<dyn NoteStore>,
+    /// Indexes by owner.
+    indexes: Mutex<HashMap<u32, Index>>,
+}
+
+impl IndexedStore {
+    pub fn new(store: Arc<dyn NoteStore>) -> Self {
+        Self {
+            store,
+            indexes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn lock(&self) -> MutexGuard<'_, HashMap<u32, Index>> {
+        metrics::lock("search_index", &self.indexes).await
+    }
+
+    /// Returns how many notes of `owner` match `query`, and the best `limit`
+    /// of them, best first, with their scores and snippets.
+    pub async fn search(
+        &self,
+        owner: u32,
+        query: &SearchQuery,
+        limit: usize,
+    ) -> Result<(usize, Vec<SearchHit>), StoreError> {
+        let mut hits = {
+            let mut indexes = self.lock().await;
+            if let Entry::Vacant(entry) = indexes.entry(owner) {
+                entry.insert(Index::build(
+                    &metrics::time("list", self.store.list(owner)).await?,
+                ));
+            }
+            indexes[&owner].search(query)
+        };
+        let total = hits.len();
+        hits.truncate(limit);
+
+        // Only the notes on the page are read, and without holding the
+        // indexes, so that a broad query does not hold up writes.
+        let mut results = Vec::with_capacity(hits.len());
+        for hit in hits {
+            if let Some(note) = metrics::time("get", self.store.get(hit.id)).await? {
+                results.push(SearchHit {
+                    id: note.id,
+                    title: note.title,
+                    updated_at: note.updated_at,
+                    score: hit.score,
+                    snippet: hit.snippet,
+                });
+            }
+        }
+        Ok((total, results))
+    }
+}
+
+#[async_trait]
+impl NoteStore for IndexedStore {
+    async fn create(&self, owner: u32, input: NoteInput) -> Result<Note, StoreError> {
+        let mut indexes = self.lock().await;
+        let note = metrics::time("create", self.store.create(owner, input)).await?;
+        if let Some(index) = indexes.get_mut(&owner) {
+            index.insert(&note);
+        }
+        Ok(note)
+    }
+
+    async fn get(&self, id: u32) -> Result<Option<Note>, StoreError> {
+        metrics::time("get", self.store.get(id)).await
+    }
+
+    async fn update(
+        &self,
+        id: u32,
+        input: NoteInput,
+        expected_revision: Option<u64>,
+    ) -> Result<Option<Note>, StoreError> {
+        let mut indexes = self.lock().await;
+        let note = metrics::time("update", self.store.update(id, input, expected_revision)).await?;
+        if let Some(note) = &note {
+            if let Some(index) = indexes.get_mut(&note.owner_id) {
+                index.insert(note);
+            }
+        }
+        Ok(note)
+    }
+
+    async fn delete(&self, id: u32, expected_revision: Option<u64>) -> Result<bool, StoreError> {
+        let mut indexes = self.lock().await;
+        let deleted = metrics::time("delete", self.store.delete(id, expected_revision)).await?;
+        if deleted {
+            for index in indexes.values_mut() {
+                index.remove(id);
+            }
+        }
+        Ok(deleted)
+    }
+
+    // Tags are not indexed.
+    async fn update_tags(
+        &self,
+        id: u32,
+        add: &BTreeSet<String>,
+        remove: &BTreeSet<String>,
+        expected_revision: Option<u64>,
+    ) -> Result<Option<Note>, StoreError> {
+        metrics::time(
+            "update_tags",
+            self.store.update_tags(id, add, remove, expected_revision),
+        )
+        .await
+    }
+
+    async fn tag_counts(&self, owner: u32) -> Result<Vec<TagCount>, StoreError> {
+        metrics::time("tag_counts", self.store.tag_counts(owner)).await
+    }
+
+    async fn rename_tag(&self, owner: u32, from: &str, to: &str) -> Result<usize, StoreError> {
+        metrics::time("rename_tag", self.store.rename_tag(owner, from, to)).await
+    }
+
+    async fn move_note(
+        &self,
+        id: u32,
+        notebook_id: Option<u32>,
+        expected_revision: Option<u64>,
+    ) -> Result<Option<Note>, StoreError> {
+        metrics::time(
+            "move_note",
+            self.store.move_note(id, notebook_id, expected_revision),
+        )
+        .await
+    }
+
+    async fn create_notebook(
+        &self,
+        owner: u32,
+        input: NotebookInput,
+    ) -> Result<Notebook, StoreError> {
+        metrics::time("create_notebook", self.store.create_notebook(owner, input)).await
+    }
+
+    async fn get_notebook(&self, id: u32) -> Result<Option<Notebook>, StoreError> {
+        metrics::time("get_notebook", self.store.get_notebook(id)).await
+    }
+
+    async fn list_notebooks(&self, owner: u32) -> Result<Vec<Notebook>, StoreError> {
+        metrics::time("list_notebooks", self.store.list_notebooks(owner)).await
+    }
+
+    async fn update_notebook(
+        &self,
+        id: u32,
+        input: NotebookInput,
+    ) -> Result<Option<Notebook>, StoreError> {
+        metrics::time("update_notebook", self.store.update_notebook(id, input)).await
+    }
+
+    // A cascading delete trashes notes without saying which, so the indexes
+    // are dropped and rebuilt by the next search.
+    async fn delete_notebook(&self, id: u32, cascade: bool) -> Result<bool, StoreError> {
+        let mut indexes = self.lock().await;
+        let deleted =
+            metrics::time("delete_notebook", self.store.delete_notebook(id, cascade)).await?;
+        if deleted && cascade {
+            indexes.clear();
+        }
+        Ok(deleted)
+    }
+
+    async fn list(&self, owner: u32) -> Result<Vec<Note>, StoreError> {
+        metrics::time("list", self.store.list(owner)).await
+    }
+
+    async fn list_trash(&self, owner: u32) -> Result<Vec<Note>, StoreError> {
+        metrics::time("list_trash", self.store.list_trash(owner)).await
+    }
+
+    async fn restore(&self, id: u32) -> Result<Option<Note>, StoreError> {
+        let mut indexes = self.lock().await;
+        let note = metrics::time("restore", self.store.restore(id)).await?;
+        if let Some(note) = &note {
+            if let Some(index) = indexes.get_mut(&note.owner_id) {
+                index.insert(note);
+            }
+        }
+        Ok(note)
+    }
+
+    // Trashed notes are already out of the index, so purging leaves it alone.
+    async fn purge(&self, id: u32) -> Result<bool, StoreError> {
+        metrics::time("purge", self.store.purge(id)).await
+    }
+
+    async fn purge_trashed_before(&self, cutoff: DateTime<Utc>) -> Result<usize, StoreError> {
+        metrics::time(
+            "purge_trashed_before",
+            self.store.purge_trashed_before(cutoff),
+        )
+        .await
+    }
+
+    async fn count_notes(&self) -> Result<u64, StoreError> {
+        metrics::time("count_notes", self.store.count_notes()).await
+    }
+
+    async fn revisions(&self, id: u32) -> Result<Vec<Revision>, StoreError> {
+        metrics::time("revisions", self.store.revisions(id)).await
+    }
+
+    async fn revision(&self, id: u32, revision: u64) -> Result<Option<Revision>, StoreError> {
+        metrics::time("revision", self.store.revision(id, revision)).await
+    }
+
+    async fn create_user(
+        &self,
+        username: &str,
+        password_hash: &str,
+        is_admin: bool,
+    ) -> Result<User, StoreError> {
+        metrics::time(
+            "create_user",
+            self.store.create_user(username, password_hash, is_admin),
+        )
+        .await
+    }
+
+    async fn claim_unowned(&self, owner: u32) -> Result<usize, StoreError> {
+        let mut indexes = self.lock().await;
+        let claimed = metrics::time("claim_unowned", self.store.claim_unowned(owner)).await?;
+        indexes.remove(&owner);
+        Ok(claimed)
+    }
+
+    async fn get_user(&self, id: u32) -> Result<Option<User>, StoreError> {
+        metrics::time("get_user", self.store.get_user(id)).await
+    }
+
+    async fn user_credentials(&self, username: &str) -> Result<Option<(User, String)>, StoreError> {
+        metrics::time("user_credentials", self.store.user_credentials(username)).await
+    }
+
+    async fn list_users(&self) -> Result<Vec<User>, StoreError> {
+        metrics::time("list_users", self.store.list_users()).await
+    }
+
+    async fn set_admin(&self, id: u32, is_admin: bool) -> Result<Option<User>, StoreError> {
+        metrics::time("set_admin", self.store.set_admin(id, is_admin)).await
+    }
+
+    async fn create_api_key(
+        &self,
+        owner: u32,
+        input: ApiKeyInput,
+        prefix: &str,
+        key_hash: &str,
+    ) -> Result<ApiKey, StoreError> {
+        metrics::time(
+            "create_api_key",
+            self.store.create_api_key(owner, input, prefix, key_hash),
+        )
+        .await
+    }
+
+    async fn list_api_keys(&self, owner: u32) -> Result<Vec<ApiKey>, StoreError> {
+        metrics::time("list_api_keys", self.store.list_api_keys(owner)).await
+    }
+
+    async fn revoke_api_key(&self, id: u32) -> Result<bool, StoreError> {
+        metrics::time("revoke_api_key", self.store.revoke_api_key(id)).await
+    }
+
+    async fn use_api_key(&self, key_hash: &str) -> Result<Option<ApiKey>, StoreError> {
+        metrics::time("use_api_key", self.store.use_api_key(key_hash)).await
+    }
+
+    async fn grants(&self, note_id: u32) -> Result<Vec<Grant>, StoreError> {
+        metrics::time("grants", self.store.grants(note_id)).await
+    }
+
+    async fn grant(&self, note_id: u32, user_id: u32, role: Role) -> Result<(), StoreError> {
+        metrics::time("grant", self.store.grant(note_id, user_id, role)).await
+    }
+
+    async fn revoke_grant(&self, note_id: u32, user_id: u32) -> Result<bool, StoreError> {
+        metrics::time("revoke_grant", self.store.revoke_grant(note_id, user_id)).await
+    }
+
+    async fn shared_with(&self, user_id: u32) -> Result<Vec<(Note, Role)>, StoreError> {
+        metrics::time("shared_with", self.store.shared_with(user_id)).await
+    }
+
+    async fn create_share_link(
+        &self,
+        note_id: u32,
+        token_hash: &str,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<ShareLink, StoreError> {
+        metrics::time(
+            "create_share_link",
+            self.store
+                .create_share_link(note_id, token_hash, expires_at),
+        )
+        .await
+    }
+
+    async fn share_links(&self, note_id: u32) -> Result<Vec<ShareLink>, StoreError> {
+        metrics::time("share_links", self.store.share_links(note_id)).await
+    }
+
+    async fn share_link(&self, token_hash: &str) -> Result<Option<ShareLink>, StoreError> {
+        metrics::time("share_link", self.store.share_link(token_hash)).await
+    }
+
+    async fn delete_share_link(&self, id: u32) -> Result<bool, StoreError> {
+        metrics::time("delete_share_link", self.store.delete_share_link(id)).await
+    }
+
+    async fn create_webhook(&self, owner: u32, input: WebhookInput) -> Result<Webhook, StoreError> {
+        metrics::time("create_webhook", self.store.create_webhook(owner, input)).await
+    }
+
+    async fn get_webhook(&self, id: u32) -> Result<Option<Webhook>, StoreError> {
+        metrics::time("get_webhook", self.store.get_webhook(id)).await
+    }
+
+    async fn list_webhooks(&self, owner: u32) -> Result<Vec<Webhook>, StoreError> {
+        metrics::time("list_webhooks", self.store.list_webhooks(owner)).await
+    }
+
+    async fn webhooks_for(
+        &self,
+        users: &BTreeSet<u32>,
+        event: EventKind,
+    ) -> Result<Vec<Webhook>, StoreError> {
+        metrics::time("webhooks_for", self.store.webhooks_for(users, event)).await
+    }
+
+    async fn delete_webhook(&self, id: u32) -> Result<bool, StoreError> {
+        metrics::time("delete_webhook", self.store.delete_webhook(id)).await
+    }
+
+    async fn create_delivery(
+        &self,
+        webhook_id: u32,
+        event: EventKind,
+        payload: &serde_json::Value,
+    ) -> Result<Delivery, StoreError> {
+        metrics::time(
+            "create_delivery",
+            self.store.create_delivery(webhook_id, event, payload),
+        )
+        .await
+    }
+
+    async fn record_attempt(
+        &self,
+        id: u32,
+        attempt: Attempt,
+    ) -> Result<Option<Delivery>, StoreError> {
+        metrics::time("record_attempt", self.store.record_attempt(id, attempt)).await
+    }
+
+    async fn delivery(&self, id: u32) -> Result<Option<Delivery>, StoreError> {
+        metrics::time("delivery", self.store.delivery(id)).await
+    }
+
+    async fn deliveries(&self, webhook_id: u32) -> Result<Vec<Delivery>, StoreError> {
+        metrics::time("deliveries", self.store.deliveries(webhook_id)).await
+    }
+
+    async fn pending_deliveries(&self) -> Result<Vec<Delivery>, StoreError> {
+        metrics::time("pending_deliveries", self.store.pending_deliveries()).await
+    }
+
+    async fn flush(&self) -> Result<(), StoreError> {
+        metrics::time("flush", self.store.flush()).await
+    }
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[serde(deny_unknown_fields)]
+pub struct SearchParams {
+    pub q: String,
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct SearchHit {
+    pub id: u32,
+    pub title: String,
+    pub updated_at: DateTime<Utc>,
+    /// BM25 relevance; only meaningful relative to the other hits.
+    pub score: f64,
+    /// HTML excerpt of the body with the matching words in `<mark>`.
+    pub snippet: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct SearchResults {
+    /// Number of matching notes, of which at most `limit` are returned.
+    pub total: usize,
+    pub hits: Vec<SearchHit>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/search",
+    tag = "search",
+    params(SearchParams),
+    responses(
+        (status = 200, description = "Matching notes, best first", body = SearchResults),
+        (status = 400, description = "Malformed request", body = ErrorBody),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Not allowed for the caller", body = ErrorBody),
+    ),
+)]
+pub async fn search_notes(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Query(params): Query<SearchParams>,
+) -> Result<Json<SearchResults>, ApiError> {
+    user.require(Scope::Read)?;
+    let query = SearchQuery::parse(&params.q);
+    if query.is_empty() {
+        return Err(ApiError::BadRequest("q must contain a word".into()));
+    }
+    let limit = match params.limit {
+        Some(0) => return Err(ApiError::BadRequest("limit must be at least 1".into())),
+        Some(limit) => limit.min(MAX_LIMIT),
+        None => DEFAULT_LIMIT,
+    };
+    let (total, hits) = state.search.search(user.id, &query, limit).await?;
+    Ok(Json(SearchResults { total, hits }))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    use crate::test_util::{json_body, request, seeded_store, test_app};
+
+    #[tokio::test]
+    async fn search() {
+        let app = test_app(seeded_store().await);
+        for (title, note) in [
+            ("groceries", "Buy milk & eggs"),
+            ("reminders", "Milk the cows, then buy more milk"),
+        ] {
+            app.clone()
+                .oneshot(request(
+                    "POST",
+                    "/v1/notes",
+                    Some(json!({"title": title, "note": note})),
+                ))
+                .await
+                .unwrap();
+        }
+        let response = app
+            .clone()
+            .oneshot(request("GET", "/v1/search?q=milk&limit=1", None))
+            .await
+            .unwrap();
+        let results = json_body(response).await;
+        assert_eq!(results["total"], 2);
+        assert_eq!(results["hits"].as_array().unwrap().len(), 1);
+        assert_eq!(results["hits"][0]["id"], 3);
+
+        app.clone()
+            .oneshot(request("DELETE", "/v1/notes/3", None))
+            .await
+            .unwrap();
+
+        let response = app
+            .clone()
+            .oneshot(request("GET", "/v1/search?q=buying%20milk", None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let results = json_body(response).await;
+        assert_eq!(results["total"], 1);
+        assert_eq!(results["hits"][0]["id"], 2);
+        assert_eq!(
+            results["hits"][0]["snippet"],
+            "<mark>Buy</mark> <mark>milk</mark> &amp; eggs"
+        );
+
+        let response = app
+            .oneshot(request("GET", "/v1/search?q=%22%20%22", None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
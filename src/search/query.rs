@@ -0,0 +1,80 @@
+//! Search query syntax: whitespace separated words, and `"quoted phrases"`
+//! whose words must appear next to each other.
+
+use std::collections::HashSet;
+
+use super::index::tokenize;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Clause {
+    Term(String),
+    Phrase(Vec<String>),
+}
+
+impl Clause {
+    pub fn terms(&self) -> &[String] {
+        match self {
+            Clause::Term(term) => std::slice::from_ref(term),
+            Clause::Phrase(terms) => terms,
+        }
+    }
+}
+
+/// A parsed query. A note matches when it matches every clause.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchQuery {
+    pub clauses: Vec<Clause>,
+}
+
+impl SearchQuery {
+    /// Parses `q`. Words are normalized like indexed text; an unterminated
+    /// quote runs to the end of the query.
+    pub fn parse(q: &str) -> Self {
+        let mut clauses = Vec::new();
+        for (i, part) in q.split('"').enumerate() {
+            let terms = tokenize(part).into_iter().map(|token| token.term);
+            if i % 2 == 1 {
+                let terms: Vec<String> = terms.collect();
+                match terms.len() {
+                    0 => {}
+                    1 => clauses.extend(terms.into_iter().map(Clause::Term)),
+                    _ => clauses.push(Clause::Phrase(terms)),
+                }
+            } else {
+                clauses.extend(terms.map(Clause::Term));
+            }
+        }
+        SearchQuery { clauses }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.clauses.is_empty()
+    }
+
+    /// Every distinct term of the query, including those inside phrases.
+    pub fn terms(&self) -> HashSet<&str> {
+        self.clauses
+            .iter()
+            .flat_map(|clause| clause.terms())
+            .map(String::as_str)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn words_and_phrases() {
+        assert_eq!(
+            SearchQuery::parse(r#"Meeting "action items" "notes"#).clauses,
+            vec![
+                Clause::Term("meet".into()),
+                Clause::Phrase(vec!["action".into(), "item".into()]),
+                Clause::Term("note".into()),
+            ]
+        );
+        assert!(SearchQuery::parse(r#" "" ?! "#).is_empty());
+    }
+}
@@ -0,0 +1,617 @@
+//! Sharing notes: per-note access control lists granting roles to other
+//! users, and public read-only links.
+//!
+//! The owner of a note always has the [`Role::Owner`] role on it. Every
+//! handler working on a single note checks the caller's role through
+//! [`crate::notes::find_note`].
+
+use std::{collections::BTreeSet, fmt, str::FromStr};
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use axum::{extract::State, http::StatusCode, response::IntoResponse};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{
+    auth::AuthUser,
+    error::ApiError,
+    extract::{Json, Path},
+    keys::{hash_key, Scope},
+    notes::find_note,
+    AppState, Note,
+};
+
+/// What a user may do with a note. Each role allows everything the ones
+/// before it do.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, ToSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Read the note and its history.
+    Viewer,
+    /// Change the note's content and tags.
+    Editor,
+    /// Delete the note and manage who it is shared with.
+    Owner,
+}
+
+impl Role {
+    pub const ALL: [Role; 3] = [Role::Viewer, Role::Editor, Role::Owner];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Editor => "editor",
+            Role::Owner => "owner",
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Role::ALL
+            .into_iter()
+            .find(|role| role.as_str() == s)
+            .ok_or_else(|| format!("unknown role `{}`", s))
+    }
+}
+
+/// A role on a note granted to a user other than its owner.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct Grant {
+    pub user_id: u32,
+    pub username: String,
+    pub role: Role,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct GrantInput {
+    pub role: Role,
+}
+
+/// A note shared with the caller, with the role they have on it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct SharedNote {
+    pub role: Role,
+    #[serde(flatten)]
+    pub note: Note,
+}
+
+/// A link giving anyone holding its token read access to a note, at
+/// `/v1/public/{token}`. Only a hash of the token is stored.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct ShareLink {
+    pub id: u32,
+    pub note_id: u32,
+    pub created_at: DateTime<Utc>,
+    /// The link stops working at this time; `None` keeps it working until it
+    /// is deleted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl ShareLink {
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|at| at <= now)
+    }
+}
+
+#[derive(Debug, Default, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ShareLinkInput {
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// A freshly created link. `token` is not stored and cannot be shown again.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct IssuedLink {
+    #[serde(flatten)]
+    pub link: ShareLink,
+    pub token: String,
+}
+
+/// What a public link shows of a note.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct PublicNote {
+    pub title: String,
+    pub note: String,
+    pub tags: BTreeSet<String>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl From<Note> for PublicNote {
+    fn from(note: Note) -> Self {
+        Self {
+            title: note.title,
+            note: note.note,
+            tags: note.tags,
+            updated_at: note.updated_at,
+        }
+    }
+}
+
+/// An unguessable token for a share link.
+fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+fn no_grant(id: u32, username: &str) -> ApiError {
+    ApiError::NotFound(format!("Note {} is not shared with {}", id, username))
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/notes/{id}/acl",
+    tag = "sharing",
+    params(("id" = u32, Path, description = "Note id")),
+    responses(
+        (status = 200, description = "Roles granted on the note", body = [Grant]),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Not allowed for the caller", body = ErrorBody),
+        (status = 404, description = "Note not found", body = ErrorBody),
+    ),
+)]
+pub async fn list_grants(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Path(id): Path<u32>,
+) -> Result<Json<Vec<Grant>>, ApiError> {
+    user.require(Scope::Read)?;
+    find_note(&state, &user, id, Role::Owner).await?;
+    Ok(Json(state.store.grants(id).await?))
+}
+
+/// Grants `username` a role on the note, replacing any role they had.
+#[utoipa::path(
+    put,
+    path = "/v1/notes/{id}/acl/{username}",
+    tag = "sharing",
+    params(("id" = u32, Path, description = "Note id"), ("username" = String, Path, description = "User the note is shared with")),
+    request_body = GrantInput,
+    responses(
+        (status = 200, description = "The grant", body = Grant),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Not allowed for the caller", body = ErrorBody),
+        (status = 404, description = "Note not found", body = ErrorBody),
+        (status = 422, description = "Invalid input", body = ErrorBody),
+    ),
+)]
+pub async fn put_grant(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Path((id, username)): Path<(u32, String)>,
+    Json(payload): Json<GrantInput>,
+) -> Result<Json<Grant>, ApiError> {
+    user.require(Scope::Write)?;
+    let note = find_note(&state, &user, id, Role::Owner).await?;
+    let username = username.trim().to_lowercase();
+    let (grantee, _) = state
+        .store
+        .user_credentials(&username)
+        .await?
+        .ok_or_else(|| ApiError::Unprocessable(format!("User {} not found", username)))?;
+    if grantee.id == note.owner_id {
+        return Err(ApiError::Unprocessable(format!(
+            "{} already owns note {}",
+            username, id
+        )));
+    }
+    state.store.grant(id, grantee.id, payload.role).await?;
+    tracing::info!(
+        user_id = user.id,
+        note_id = id,
+        grantee_id = grantee.id,
+        role = payload.role.as_str(),
+        "note shared"
+    );
+    Ok(Json(Grant {
+        user_id: grantee.id,
+        username: grantee.username,
+        role: payload.role,
+    }))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/v1/notes/{id}/acl/{username}",
+    tag = "sharing",
+    params(("id" = u32, Path, description = "Note id"), ("username" = String, Path, description = "User the note is shared with")),
+    responses(
+        (status = 204, description = "Grant revoked"),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Not allowed for the caller", body = ErrorBody),
+        (status = 404, description = "Note or grant not found", body = ErrorBody),
+    ),
+)]
+pub async fn delete_grant(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Path((id, username)): Path<(u32, String)>,
+) -> Result<StatusCode, ApiError> {
+    user.require(Scope::Write)?;
+    find_note(&state, &user, id, Role::Owner).await?;
+    let username = username.trim().to_lowercase();
+    let grant = state
+        .store
+        .grants(id)
+        .await?
+        .into_iter()
+        .find(|grant| grant.username == username)
+        .ok_or_else(|| no_grant(id, &username))?;
+    if !state.store.revoke_grant(id, grant.user_id).await? {
+        return Err(no_grant(id, &username));
+    }
+    tracing::info!(
+        user_id = user.id,
+        note_id = id,
+        grantee_id = grant.user_id,
+        "note unshared"
+    );
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Lists the notes other users have shared with the caller.
+#[utoipa::path(
+    get,
+    path = "/v1/shared",
+    tag = "sharing",
+    responses(
+        (status = 200, description = "Notes shared with the caller", body = [SharedNote]),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Not allowed for the caller", body = ErrorBody),
+    ),
+)]
+pub async fn list_shared(
+    State(state): State<AppState>,
+    user: AuthUser,
+) -> Result<Json<Vec<SharedNote>>, ApiError> {
+    user.require(Scope::Read)?;
+    let shared = state.store.shared_with(user.id).await?;
+    Ok(Json(
+        shared
+            .into_iter()
+            .map(|(note, role)| SharedNote { role, note })
+            .collect(),
+    ))
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/notes/{id}/links",
+    tag = "sharing",
+    params(("id" = u32, Path, description = "Note id")),
+    responses(
+        (status = 200, description = "Share links of the note", body = [ShareLink]),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Not allowed for the caller", body = ErrorBody),
+        (status = 404, description = "Note not found", body = ErrorBody),
+    ),
+)]
+pub async fn list_links(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Path(id): Path<u32>,
+) -> Result<Json<Vec<ShareLink>>, ApiError> {
+    user.require(Scope::Read)?;
+    find_note(&state, &user, id, Role::Owner).await?;
+    Ok(Json(state.store.share_links(id).await?))
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/notes/{id}/links",
+    tag = "sharing",
+    params(("id" = u32, Path, description = "Note id")),
+    request_body = ShareLinkInput,
+    responses(
+        (status = 201, description = "The new link; `token` is only shown here", body = IssuedLink),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Not allowed for the caller", body = ErrorBody),
+        (status = 404, description = "Note not found", body = ErrorBody),
+        (status = 422, description = "Invalid input", body = ErrorBody),
+    ),
+)]
+pub async fn create_link(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Path(id): Path<u32>,
+    Json(payload): Json<ShareLinkInput>,
+) -> Result<impl IntoResponse, ApiError> {
+    user.require(Scope::Write)?;
+    find_note(&state, &user, id, Role::Owner).await?;
+    if payload.expires_at.is_some_and(|at| at <= Utc::now()) {
+        return Err(ApiError::Unprocessable(
+            "`expires_at` must be in the future".into(),
+        ));
+    }
+    let token = generate_token();
+    let link = state
+        .store
+        .create_share_link(id, &hash_key(&token), payload.expires_at)
+        .await?;
+    tracing::info!(
+        user_id = user.id,
+        note_id = id,
+        link_id = link.id,
+        "share link created"
+    );
+    Ok((StatusCode::CREATED, Json(IssuedLink { link, token })))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/v1/notes/{id}/links/{link_id}",
+    tag = "sharing",
+    params(("id" = u32, Path, description = "Note id"), ("link_id" = u32, Path, description = "Link id")),
+    responses(
+        (status = 204, description = "Link deleted"),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Not allowed for the caller", body = ErrorBody),
+        (status = 404, description = "Note or link not found", body = ErrorBody),
+    ),
+)]
+pub async fn delete_link(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Path((id, link_id)): Path<(u32, u32)>,
+) -> Result<StatusCode, ApiError> {
+    user.require(Scope::Write)?;
+    find_note(&state, &user, id, Role::Owner).await?;
+    let not_found = || ApiError::NotFound(format!("Link {} of note {} not found", link_id, id));
+    let links = state.store.share_links(id).await?;
+    if !links.iter().any(|link| link.id == link_id) {
+        return Err(not_found());
+    }
+    if !state.store.delete_share_link(link_id).await? {
+        return Err(not_found());
+    }
+    tracing::info!(
+        user_id = user.id,
+        note_id = id,
+        link_id,
+        "share link deleted"
+    );
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Reads a note through a share link. Needs no authentication; unknown and
+/// expired links, and links to trashed notes, are all reported as missing.
+#[utoipa::path(
+    get,
+    path = "/v1/public/{token}",
+    tag = "sharing",
+    params(("token" = String, Path, description = "Token of the share link")),
+    responses(
+        (status = 200, description = "The shared note", body = PublicNote),
+        (status = 404, description = "Link not found", body = ErrorBody),
+    ),
+    security(()),
+)]
+pub async fn read_public(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+) -> Result<Json<PublicNote>, ApiError> {
+    let not_found = || ApiError::NotFound("Link not found".into());
+    let link = state
+        .store
+        .share_link(&hash_key(&token))
+        .await?
+        .filter(|link| !link.is_expired(Utc::now()))
+        .ok_or_else(not_found)?;
+    let note = state.store.get(link.note_id).await?.ok_or_else(not_found)?;
+    Ok(Json(note.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{header, StatusCode};
+    use chrono::Duration;
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        store::NoteStore,
+        test_util::{json_body, request, seeded_store, test_app, token, with_header},
+    };
+
+    #[test]
+    fn roles() {
+        assert!(Role::Viewer < Role::Editor && Role::Editor < Role::Owner);
+        assert_eq!("editor".parse::<Role>(), Ok(Role::Editor));
+        assert!("admin".parse::<Role>().is_err());
+    }
+
+    #[test]
+    fn link_expiry() {
+        let now = Utc::now();
+        let mut link = ShareLink {
+            id: 1,
+            note_id: 1,
+            created_at: now,
+            expires_at: None,
+        };
+        assert_eq!(generate_token().len(), 43);
+        assert!(!link.is_expired(now));
+        link.expires_at = Some(now + Duration::hours(1));
+        assert!(!link.is_expired(now));
+        assert!(link.is_expired(now + Duration::hours(1)));
+    }
+
+    #[tokio::test]
+    async fn sharing() {
+        let store = seeded_store().await;
+        store.create_user("bob", "not a hash", false).await.unwrap();
+        let app = test_app(store);
+        let as_bob = |req| {
+            with_header(
+                req,
+                header::AUTHORIZATION,
+                &format!("Bearer {}", token(2, "bob")),
+            )
+        };
+        let edit = json!({"title": "by bob", "note": "n"});
+
+        let response = app
+            .clone()
+            .oneshot(request(
+                "PUT",
+                "/v1/notes/1/acl/Bob",
+                Some(json!({"role": "viewer"})),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(
+            json_body(response).await,
+            json!({"user_id": 2, "username": "bob", "role": "viewer"})
+        );
+        let response = app
+            .clone()
+            .oneshot(as_bob(request("GET", "/v1/notes/1", None)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = app
+            .clone()
+            .oneshot(as_bob(request("PUT", "/v1/notes/1", Some(edit.clone()))))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = app
+            .clone()
+            .oneshot(as_bob(request("GET", "/v1/shared", None)))
+            .await
+            .unwrap();
+        let shared = json_body(response).await;
+        assert_eq!(shared[0]["id"], 1);
+        assert_eq!(shared[0]["role"], "viewer");
+
+        app.clone()
+            .oneshot(request(
+                "PUT",
+                "/v1/notes/1/acl/bob",
+                Some(json!({"role": "editor"})),
+            ))
+            .await
+            .unwrap();
+        let response = app
+            .clone()
+            .oneshot(as_bob(request("PUT", "/v1/notes/1", Some(edit))))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        for (method, uri) in [
+            ("DELETE", "/v1/notes/1"),
+            ("GET", "/v1/notes/1/acl"),
+            ("PUT", "/v1/notes/1/notebook"),
+        ] {
+            let response = app
+                .clone()
+                .oneshot(as_bob(request(
+                    method,
+                    uri,
+                    Some(json!({"notebook_id": null})),
+                )))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::FORBIDDEN, "{method} {uri}");
+        }
+        let response = app
+            .clone()
+            .oneshot(request(
+                "PUT",
+                "/v1/notes/1/acl/alice",
+                Some(json!({"role": "viewer"})),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let response = app
+            .clone()
+            .oneshot(request("DELETE", "/v1/notes/1/acl/bob", None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let response = app
+            .clone()
+            .oneshot(as_bob(request("GET", "/v1/notes/1", None)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn share_links() {
+        let app = test_app(seeded_store().await);
+        let response = app
+            .clone()
+            .oneshot(request("POST", "/v1/notes/1/links", Some(json!({}))))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let link = json_body(response).await;
+        let public = format!("/v1/public/{}", link["token"].as_str().unwrap());
+        let response = app
+            .clone()
+            .oneshot(request("GET", "/v1/notes/1/links", None))
+            .await
+            .unwrap();
+        let links = json_body(response).await;
+        assert_eq!(links[0]["id"], link["id"]);
+        assert!(links[0].get("token").is_none());
+
+        let anonymous = axum::extract::Request::get(&public)
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(anonymous).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = json_body(response).await;
+        assert_eq!(body["title"], "test_title");
+        assert!(body.get("owner_id").is_none());
+
+        let response = app
+            .clone()
+            .oneshot(request(
+                "POST",
+                "/v1/notes/1/links",
+                Some(json!({"expires_at": "2000-01-01T00:00:00Z"})),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let uri = format!("/v1/notes/1/links/{}", link["id"]);
+        let response = app
+            .clone()
+            .oneshot(request("DELETE", &uri, None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let response = app
+            .clone()
+            .oneshot(request("GET", &public, None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = app
+            .oneshot(request("GET", "/v1/public/guess", None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,147 @@
+//! Stopping the server without cutting off requests.
+//!
+//! Once a shutdown is asked for, the listener stops accepting connections
+//! and the requests in flight are given a while to finish. Whatever is
+//! still running after that is no longer waited for and ends with the
+//! process, so that a stuck request (or an event stream, which never ends by
+//! itself) cannot hold it up.
+
+use std::{
+    future::{Future, IntoFuture},
+    io,
+    time::Duration,
+};
+
+use axum::Router;
+use tokio::{net::TcpListener, sync::oneshot};
+
+/// How serving came to an end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stopped {
+    /// Every request finished in time.
+    Drained,
+    /// Some requests were still running when the time was up.
+    TimedOut,
+}
+
+/// Completes on SIGINT (Ctrl-C) or, on Unix, SIGTERM.
+pub async fn signal() {
+    let interrupt = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!(error = %e, "failed to listen for SIGINT");
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(e) => {
+                tracing::error!(error = %e, "failed to listen for SIGTERM");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = interrupt => {}
+        _ = terminate => {}
+    }
+}
+
+/// Serves `app` on `listener` until `shutdown` completes, then waits up to
+/// `timeout` for the requests in flight.
+pub async fn serve(
+    listener: TcpListener,
+    app: Router,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+    timeout: Duration,
+) -> io::Result<Stopped> {
+    let (stopping, stopped) = oneshot::channel();
+    let server = axum::serve(listener, app).with_graceful_shutdown(async move {
+        shutdown.await;
+        tracing::info!("shutting down, draining requests");
+        let _ = stopping.send(());
+    });
+    let server = server.into_future();
+    tokio::pin!(server);
+    let deadline = async {
+        match stopped.await {
+            Ok(()) => tokio::time::sleep(timeout).await,
+            // The server stopped by itself.
+            Err(_) => std::future::pending().await,
+        }
+    };
+    tokio::select! {
+        result = &mut server => result.map(|()| Stopped::Drained),
+        () = deadline => Ok(Stopped::TimedOut),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::get;
+
+    /// A server whose `/slow` takes `delay` to respond, stopped on the
+    /// returned sender.
+    async fn slow_server(
+        delay: Duration,
+        timeout: Duration,
+    ) -> (
+        String,
+        oneshot::Sender<()>,
+        tokio::task::JoinHandle<io::Result<Stopped>>,
+    ) {
+        let app = Router::new().route(
+            "/slow",
+            get(move || async move {
+                tokio::time::sleep(delay).await;
+                "done"
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/slow", listener.local_addr().unwrap());
+        let (stop, stopped) = oneshot::channel::<()>();
+        let server = tokio::spawn(serve(
+            listener,
+            app,
+            async move {
+                let _ = stopped.await;
+            },
+            timeout,
+        ));
+        (url, stop, server)
+    }
+
+    #[tokio::test]
+    async fn drains_requests_in_flight() {
+        let (url, stop, server) =
+            slow_server(Duration::from_millis(200), Duration::from_secs(5)).await;
+        let request = tokio::spawn(reqwest::get(url.clone()));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        stop.send(()).unwrap();
+
+        let response = request.await.unwrap().unwrap();
+        assert_eq!(response.text().await.unwrap(), "done");
+        assert_eq!(server.await.unwrap().unwrap(), Stopped::Drained);
+        // Nothing is listening any more.
+        assert!(reqwest::get(url).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_timeout() {
+        let (url, stop, server) =
+            slow_server(Duration::from_secs(60), Duration::from_millis(100)).await;
+        let _request = tokio::spawn(reqwest::get(url));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        stop.send(()).unwrap();
+
+        let stopped = tokio::time::timeout(Duration::from_secs(5), server).await;
+        assert_eq!(stopped.unwrap().unwrap().unwrap(), Stopped::TimedOut);
+    }
+}
@@ -0,0 +1,780 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use tokio::sync::{Mutex, MutexGuard};
+
+use super::{NoteStore, StoreError, WorkspaceStores};
+use crate::{
+    auth::User,
+    events::EventKind,
+    history::Revision,
+    keys::{ApiKey, ApiKeyInput},
+    metrics,
+    notebooks::{Notebook, NotebookInput},
+    sharing::{Grant, Role, ShareLink},
+    tags::TagCount,
+    webhooks::{Attempt, Delivery, DeliveryStatus, Webhook, WebhookInput},
+    Note, NoteInput,
+};
+
+#[derive(Default)]
+struct Inner {
+    id: u32,
+    data: HashMap<u32, Note>,
+    history: HashMap<u32, Vec<Revision>>,
+    notebook_id: u32,
+    notebooks: HashMap<u32, Notebook>,
+    user_id: u32,
+    /// Users with their password hashes.
+    users: HashMap<u32, (User, String)>,
+    api_key_id: u32,
+    /// API keys with their hashes.
+    api_keys: HashMap<u32, (ApiKey, String)>,
+    /// Roles granted on each note, by user id.
+    grants: HashMap<u32, BTreeMap<u32, Role>>,
+    share_link_id: u32,
+    /// Share links with the hashes of their tokens.
+    share_links: HashMap<u32, (ShareLink, String)>,
+    webhook_id: u32,
+    webhooks: BTreeMap<u32, Webhook>,
+    delivery_id: u32,
+    deliveries: BTreeMap<u32, Delivery>,
+}
+
+impl Inner {
+    fn live(&mut self, id: u32) -> Option<&mut Note> {
+        self.data
+            .get_mut(&id)
+            .filter(|note| note.deleted_at.is_none())
+    }
+
+    /// Removes every trace of a note.
+    fn purge(&mut self, id: u32) {
+        self.data.remove(&id);
+        self.history.remove(&id);
+        self.grants.remove(&id);
+        self.share_links.retain(|_, (link, _)| link.note_id != id);
+    }
+
+    /// Bumps the revision of a note that was just changed in place and
+    /// records it in the history.
+    fn commit(&mut self, id: u32) -> Note {
+        let note = self.data.get_mut(&id).expect("committed note exists");
+        note.revision += 1;
+        note.updated_at = Utc::now();
+        let note = note.clone();
+        self.history
+            .entry(id)
+            .or_default()
+            .push(Revision::from(&note));
+        note
+    }
+
+    /// Checks that `parent` can hold the notebook `id`, or a new notebook
+    /// for `None`.
+    fn check_parent(&self, id: Option<u32>, parent: Option<u32>) -> Result<(), StoreError> {
+        let mut ancestor = parent;
+        while let Some(current) = ancestor {
+            if Some(current) == id {
+                return Err(StoreError::NotebookCycle);
+            }
+            ancestor = self
+                .notebooks
+                .get(&current)
+                .ok_or(StoreError::NotebookNotFound(current))?
+                .parent_id;
+        }
+        Ok(())
+    }
+
+    /// The notebook `id` and every notebook below it.
+    fn subtree(&self, id: u32) -> HashSet<u32> {
+        let mut found = HashSet::from([id]);
+        let mut pending = vec![id];
+        while let Some(parent) = pending.pop() {
+            for notebook in self.notebooks.values() {
+                if notebook.parent_id == Some(parent) && found.insert(notebook.id) {
+                    pending.push(notebook.id);
+                }
+            }
+        }
+        found
+    }
+
+    fn sorted(&self, owner: u32, trashed: bool) -> Vec<Note> {
+        let mut notes: Vec<_> = self
+            .data
+            .values()
+            .filter(|note| note.owner_id == owner && note.deleted_at.is_some() == trashed)
+            .cloned()
+            .collect();
+        notes.sort_by_key(|note| note.id);
+        notes
+    }
+}
+
+/// Volatile store keeping every note in a `HashMap`. Used by tests and for
+/// running the service without a database.
+#[derive(Default)]
+pub struct MemoryStore {
+    inner: Mutex<Inner>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    async fn lock(&self) -> MutexGuard<'_, Inner> {
+        metrics::lock("memory", &self.inner).await
+    }
+}
+
+/// Creates a fresh [`MemoryStore`] for every workspace. Nothing outlives the
+/// process, so there are never workspaces to reopen.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MemoryWorkspaces;
+
+impl WorkspaceStores for MemoryWorkspaces {
+    fn open(&self, _name: &str, create: bool) -> Result<Option<Arc<dyn NoteStore>>, StoreError> {
+        Ok(create.then(|| Arc::new(MemoryStore::new()) as Arc<dyn NoteStore>))
+    }
+
+    fn names(&self) -> Result<Vec<String>, StoreError> {
+        Ok(Vec::new())
+    }
+
+    fn remove(&self, _name: &str) -> Result<(), StoreError> {
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl NoteStore for MemoryStore {
+    async fn create(&self, owner: u32, input: NoteInput) -> Result<Note, StoreError> {
+        let mut inner = self.lock().await;
+        let new_id = inner.id + 1;
+        let now = Utc::now();
+        let note = Note {
+            id: new_id,
+            owner_id: owner,
+            title: input.title,
+            note: input.note,
+            notebook_id: None,
+            tags: BTreeSet::new(),
+            revision: 1,
+            created_at: now,
+            updated_at: now,
+            deleted_at: None,
+        };
+        inner.data.insert(new_id, note.clone());
+        inner.history.insert(new_id, vec![Revision::from(&note)]);
+        inner.id = new_id;
+        Ok(note)
+    }
+
+    async fn get(&self, id: u32) -> Result<Option<Note>, StoreError> {
+        Ok(self.lock().await.live(id).cloned())
+    }
+
+    async fn update(
+        &self,
+        id: u32,
+        input: NoteInput,
+        expected_revision: Option<u64>,
+    ) -> Result<Option<Note>, StoreError> {
+        let mut inner = self.lock().await;
+        let Some(note) = inner.live(id) else {
+            return Ok(None);
+        };
+        if expected_revision.is_some_and(|expected| expected != note.revision) {
+            return Err(StoreError::Conflict);
+        }
+        note.title = input.title;
+        note.note = input.note;
+        Ok(Some(inner.commit(id)))
+    }
+
+    async fn delete(&self, id: u32, expected_revision: Option<u64>) -> Result<bool, StoreError> {
+        let mut inner = self.lock().await;
+        let Some(note) = inner.live(id) else {
+            return Ok(false);
+        };
+        if expected_revision.is_some_and(|expected| expected != note.revision) {
+            return Err(StoreError::Conflict);
+        }
+        note.deleted_at = Some(Utc::now());
+        Ok(true)
+    }
+
+    async fn update_tags(
+        &self,
+        id: u32,
+        add: &BTreeSet<String>,
+        remove: &BTreeSet<String>,
+        expected_revision: Option<u64>,
+    ) -> Result<Option<Note>, StoreError> {
+        let mut inner = self.lock().await;
+        let Some(note) = inner.live(id) else {
+            return Ok(None);
+        };
+        if expected_revision.is_some_and(|expected| expected != note.revision) {
+            return Err(StoreError::Conflict);
+        }
+        let tags: BTreeSet<String> = note.tags.union(add).cloned().collect();
+        let tags = tags.difference(remove).cloned().collect();
+        if tags == note.tags {
+            return Ok(Some(note.clone()));
+        }
+        note.tags = tags;
+        Ok(Some(inner.commit(id)))
+    }
+
+    async fn tag_counts(&self, owner: u32) -> Result<Vec<TagCount>, StoreError> {
+        let inner = self.lock().await;
+        let mut counts = BTreeMap::<&str, usize>::new();
+        let live = |note: &&Note| note.owner_id == owner && note.deleted_at.is_none();
+        for note in inner.data.values().filter(live) {
+            for tag in &note.tags {
+                *counts.entry(tag).or_default() += 1;
+            }
+        }
+        Ok(counts
+            .into_iter()
+            .map(|(tag, count)| TagCount {
+                tag: tag.to_owned(),
+                count,
+            })
+            .collect())
+    }
+
+    async fn rename_tag(&self, owner: u32, from: &str, to: &str) -> Result<usize, StoreError> {
+        let mut inner = self.lock().await;
+        let tagged: Vec<u32> = inner
+            .data
+            .values()
+            .filter(|note| note.owner_id == owner && note.tags.contains(from))
+            .map(|note| note.id)
+            .collect();
+        if from == to {
+            return Ok(tagged.len());
+        }
+        for id in &tagged {
+            let note = inner.data.get_mut(id).expect("tagged note exists");
+            note.tags.remove(from);
+            note.tags.insert(to.to_owned());
+            inner.commit(*id);
+        }
+        Ok(tagged.len())
+    }
+
+    async fn move_note(
+        &self,
+        id: u32,
+        notebook_id: Option<u32>,
+        expected_revision: Option<u64>,
+    ) -> Result<Option<Note>, StoreError> {
+        let mut inner = self.lock().await;
+        if let Some(notebook) = notebook_id.filter(|n| !inner.notebooks.contains_key(n)) {
+            return Err(StoreError::NotebookNotFound(notebook));
+        }
+        let Some(note) = inner.live(id) else {
+            return Ok(None);
+        };
+        if expected_revision.is_some_and(|expected| expected != note.revision) {
+            return Err(StoreError::Conflict);
+        }
+        if note.notebook_id == notebook_id {
+            return Ok(Some(note.clone()));
+        }
+        note.notebook_id = notebook_id;
+        Ok(Some(inner.commit(id)))
+    }
+
+    async fn create_notebook(
+        &self,
+        owner: u32,
+        input: NotebookInput,
+    ) -> Result<Notebook, StoreError> {
+        let mut inner = self.lock().await;
+        inner.check_parent(None, input.parent_id)?;
+        let now = Utc::now();
+        inner.notebook_id += 1;
+        let notebook = Notebook {
+            id: inner.notebook_id,
+            owner_id: owner,
+            name: input.name,
+            parent_id: input.parent_id,
+            created_at: now,
+            updated_at: now,
+        };
+        inner.notebooks.insert(notebook.id, notebook.clone());
+        Ok(notebook)
+    }
+
+    async fn get_notebook(&self, id: u32) -> Result<Option<Notebook>, StoreError> {
+        Ok(self.lock().await.notebooks.get(&id).cloned())
+    }
+
+    async fn list_notebooks(&self, owner: u32) -> Result<Vec<Notebook>, StoreError> {
+        let inner = self.lock().await;
+        let mut notebooks: Vec<_> = inner
+            .notebooks
+            .values()
+            .filter(|notebook| notebook.owner_id == owner)
+            .cloned()
+            .collect();
+        notebooks.sort_by_key(|notebook| notebook.id);
+        Ok(notebooks)
+    }
+
+    async fn update_notebook(
+        &self,
+        id: u32,
+        input: NotebookInput,
+    ) -> Result<Option<Notebook>, StoreError> {
+        let mut inner = self.lock().await;
+        if !inner.notebooks.contains_key(&id) {
+            return Ok(None);
+        }
+        inner.check_parent(Some(id), input.parent_id)?;
+        let notebook = inner.notebooks.get_mut(&id).expect("notebook exists");
+        notebook.name = input.name;
+        notebook.parent_id = input.parent_id;
+        notebook.updated_at = Utc::now();
+        Ok(Some(notebook.clone()))
+    }
+
+    async fn delete_notebook(&self, id: u32, cascade: bool) -> Result<bool, StoreError> {
+        let mut inner = self.lock().await;
+        if !inner.notebooks.contains_key(&id) {
+            return Ok(false);
+        }
+        let subtree = inner.subtree(id);
+        let filed = |note: &Note| note.notebook_id.is_some_and(|n| subtree.contains(&n));
+        if !cascade
+            && (subtree.len() > 1
+                || inner
+                    .data
+                    .values()
+                    .any(|note| note.deleted_at.is_none() && filed(note)))
+        {
+            return Err(StoreError::NotebookNotEmpty);
+        }
+        let now = Utc::now();
+        for note in inner.data.values_mut().filter(|note| filed(note)) {
+            note.notebook_id = None;
+            note.deleted_at.get_or_insert(now);
+        }
+        inner.notebooks.retain(|id, _| !subtree.contains(id));
+        Ok(true)
+    }
+
+    async fn list(&self, owner: u32) -> Result<Vec<Note>, StoreError> {
+        Ok(self.lock().await.sorted(owner, false))
+    }
+
+    async fn list_trash(&self, owner: u32) -> Result<Vec<Note>, StoreError> {
+        Ok(self.lock().await.sorted(owner, true))
+    }
+
+    async fn restore(&self, id: u32) -> Result<Option<Note>, StoreError> {
+        let mut inner = self.lock().await;
+        Ok(inner
+            .data
+            .get_mut(&id)
+            .filter(|note| note.deleted_at.is_some())
+            .map(|note| {
+                note.deleted_at = None;
+                note.clone()
+            }))
+    }
+
+    async fn purge(&self, id: u32) -> Result<bool, StoreError> {
+        let mut inner = self.lock().await;
+        if inner
+            .data
+            .get(&id)
+            .is_none_or(|note| note.deleted_at.is_none())
+        {
+            return Ok(false);
+        }
+        inner.purge(id);
+        Ok(true)
+    }
+
+    async fn purge_trashed_before(&self, cutoff: DateTime<Utc>) -> Result<usize, StoreError> {
+        let mut inner = self.lock().await;
+        let expired: Vec<u32> = inner
+            .data
+            .values()
+            .filter(|note| note.deleted_at.is_some_and(|at| at < cutoff))
+            .map(|note| note.id)
+            .collect();
+        for id in &expired {
+            inner.purge(*id);
+        }
+        Ok(expired.len())
+    }
+
+    async fn count_notes(&self) -> Result<u64, StoreError> {
+        let inner = self.lock().await;
+        let live = inner.data.values().filter(|note| note.deleted_at.is_none());
+        Ok(live.count() as u64)
+    }
+
+    async fn revisions(&self, id: u32) -> Result<Vec<Revision>, StoreError> {
+        let mut inner = self.lock().await;
+        if inner.live(id).is_none() {
+            return Ok(Vec::new());
+        }
+        Ok(inner.history.get(&id).cloned().unwrap_or_default())
+    }
+
+    async fn revision(&self, id: u32, revision: u64) -> Result<Option<Revision>, StoreError> {
+        let mut inner = self.lock().await;
+        if inner.live(id).is_none() {
+            return Ok(None);
+        }
+        Ok(inner
+            .history
+            .get(&id)
+            .and_then(|revisions| revisions.iter().find(|r| r.revision == revision))
+            .cloned())
+    }
+
+    async fn create_user(
+        &self,
+        username: &str,
+        password_hash: &str,
+        is_admin: bool,
+    ) -> Result<User, StoreError> {
+        let mut inner = self.lock().await;
+        if inner
+            .users
+            .values()
+            .any(|(user, _)| user.username == username)
+        {
+            return Err(StoreError::UsernameTaken);
+        }
+        inner.user_id += 1;
+        let user = User {
+            id: inner.user_id,
+            username: username.to_owned(),
+            created_at: Utc::now(),
+            is_admin,
+        };
+        inner
+            .users
+            .insert(user.id, (user.clone(), password_hash.to_owned()));
+        Ok(user)
+    }
+
+    async fn claim_unowned(&self, _owner: u32) -> Result<usize, StoreError> {
+        // Nothing outlives the process, so every note has been written by a
+        // user.
+        Ok(0)
+    }
+
+    async fn get_user(&self, id: u32) -> Result<Option<User>, StoreError> {
+        let inner = self.lock().await;
+        Ok(inner.users.get(&id).map(|(user, _)| user.clone()))
+    }
+
+    async fn user_credentials(&self, username: &str) -> Result<Option<(User, String)>, StoreError> {
+        let inner = self.lock().await;
+        Ok(inner
+            .users
+            .values()
+            .find(|(user, _)| user.username == username)
+            .cloned())
+    }
+
+    async fn list_users(&self) -> Result<Vec<User>, StoreError> {
+        let inner = self.lock().await;
+        let mut users: Vec<User> = inner.users.values().map(|(user, _)| user.clone()).collect();
+        users.sort_by_key(|user| user.id);
+        Ok(users)
+    }
+
+    async fn set_admin(&self, id: u32, is_admin: bool) -> Result<Option<User>, StoreError> {
+        let mut inner = self.lock().await;
+        Ok(inner.users.get_mut(&id).map(|(user, _)| {
+            user.is_admin = is_admin;
+            user.clone()
+        }))
+    }
+
+    async fn create_api_key(
+        &self,
+        owner: u32,
+        input: ApiKeyInput,
+        prefix: &str,
+        key_hash: &str,
+    ) -> Result<ApiKey, StoreError> {
+        let mut inner = self.lock().await;
+        inner.api_key_id += 1;
+        let key = ApiKey {
+            id: inner.api_key_id,
+            user_id: owner,
+            name: input.name,
+            prefix: prefix.to_owned(),
+            scopes: input.scopes,
+            created_at: Utc::now(),
+            last_used_at: None,
+        };
+        inner
+            .api_keys
+            .insert(key.id, (key.clone(), key_hash.to_owned()));
+        Ok(key)
+    }
+
+    async fn list_api_keys(&self, owner: u32) -> Result<Vec<ApiKey>, StoreError> {
+        let inner = self.lock().await;
+        let mut keys: Vec<ApiKey> = inner
+            .api_keys
+            .values()
+            .filter(|(key, _)| key.user_id == owner)
+            .map(|(key, _)| key.clone())
+            .collect();
+        keys.sort_by_key(|key| key.id);
+        Ok(keys)
+    }
+
+    async fn revoke_api_key(&self, id: u32) -> Result<bool, StoreError> {
+        let mut inner = self.lock().await;
+        Ok(inner.api_keys.remove(&id).is_some())
+    }
+
+    async fn use_api_key(&self, key_hash: &str) -> Result<Option<ApiKey>, StoreError> {
+        let mut inner = self.lock().await;
+        Ok(inner
+            .api_keys
+            .values_mut()
+            .find(|(_, hash)| hash == key_hash)
+            .map(|(key, _)| {
+                key.last_used_at = Some(Utc::now());
+                key.clone()
+            }))
+    }
+
+    async fn grants(&self, note_id: u32) -> Result<Vec<Grant>, StoreError> {
+        let inner = self.lock().await;
+        let Some(grants) = inner.grants.get(&note_id) else {
+            return Ok(Vec::new());
+        };
+        Ok(grants
+            .iter()
+            .filter_map(|(user_id, role)| {
+                inner.users.get(user_id).map(|(user, _)| Grant {
+                    user_id: *user_id,
+                    username: user.username.clone(),
+                    role: *role,
+                })
+            })
+            .collect())
+    }
+
+    async fn grant(&self, note_id: u32, user_id: u32, role: Role) -> Result<(), StoreError> {
+        let mut inner = self.lock().await;
+        inner
+            .grants
+            .entry(note_id)
+            .or_default()
+            .insert(user_id, role);
+        Ok(())
+    }
+
+    async fn revoke_grant(&self, note_id: u32, user_id: u32) -> Result<bool, StoreError> {
+        let mut inner = self.lock().await;
+        Ok(inner
+            .grants
+            .get_mut(&note_id)
+            .and_then(|grants| grants.remove(&user_id))
+            .is_some())
+    }
+
+    async fn shared_with(&self, user_id: u32) -> Result<Vec<(Note, Role)>, StoreError> {
+        let inner = self.lock().await;
+        let mut shared: Vec<(Note, Role)> = inner
+            .grants
+            .iter()
+            .filter_map(|(note_id, grants)| {
+                let role = *grants.get(&user_id)?;
+                let note = inner.data.get(note_id)?;
+                note.deleted_at.is_none().then(|| (note.clone(), role))
+            })
+            .collect();
+        shared.sort_by_key(|(note, _)| note.id);
+        Ok(shared)
+    }
+
+    async fn create_share_link(
+        &self,
+        note_id: u32,
+        token_hash: &str,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<ShareLink, StoreError> {
+        let mut inner = self.lock().await;
+        inner.share_link_id += 1;
+        let link = ShareLink {
+            id: inner.share_link_id,
+            note_id,
+            created_at: Utc::now(),
+            expires_at,
+        };
+        inner
+            .share_links
+            .insert(link.id, (link.clone(), token_hash.to_owned()));
+        Ok(link)
+    }
+
+    async fn share_links(&self, note_id: u32) -> Result<Vec<ShareLink>, StoreError> {
+        let inner = self.lock().await;
+        let mut links: Vec<ShareLink> = inner
+            .share_links
+            .values()
+            .map(|(link, _)| link)
+            .filter(|link| link.note_id == note_id)
+            .cloned()
+            .collect();
+        links.sort_by_key(|link| link.id);
+        Ok(links)
+    }
+
+    async fn share_link(&self, token_hash: &str) -> Result<Option<ShareLink>, StoreError> {
+        let inner = self.lock().await;
+        Ok(inner
+            .share_links
+            .values()
+            .find(|(_, hash)| hash == token_hash)
+            .map(|(link, _)| link.clone()))
+    }
+
+    async fn delete_share_link(&self, id: u32) -> Result<bool, StoreError> {
+        let mut inner = self.lock().await;
+        Ok(inner.share_links.remove(&id).is_some())
+    }
+
+    async fn create_webhook(&self, owner: u32, input: WebhookInput) -> Result<Webhook, StoreError> {
+        let mut inner = self.lock().await;
+        inner.webhook_id += 1;
+        let webhook = Webhook {
+            id: inner.webhook_id,
+            user_id: owner,
+            url: input.url,
+            events: input.events,
+            secret: input.secret,
+            created_at: Utc::now(),
+        };
+        inner.webhooks.insert(webhook.id, webhook.clone());
+        Ok(webhook)
+    }
+
+    async fn get_webhook(&self, id: u32) -> Result<Option<Webhook>, StoreError> {
+        let inner = self.lock().await;
+        Ok(inner.webhooks.get(&id).cloned())
+    }
+
+    async fn list_webhooks(&self, owner: u32) -> Result<Vec<Webhook>, StoreError> {
+        let inner = self.lock().await;
+        Ok(inner
+            .webhooks
+            .values()
+            .filter(|webhook| webhook.user_id == owner)
+            .cloned()
+            .collect())
+    }
+
+    async fn webhooks_for(
+        &self,
+        users: &BTreeSet<u32>,
+        event: EventKind,
+    ) -> Result<Vec<Webhook>, StoreError> {
+        let inner = self.lock().await;
+        Ok(inner
+            .webhooks
+            .values()
+            .filter(|webhook| users.contains(&webhook.user_id) && webhook.wants(event))
+            .cloned()
+            .collect())
+    }
+
+    async fn delete_webhook(&self, id: u32) -> Result<bool, StoreError> {
+        let mut inner = self.lock().await;
+        inner
+            .deliveries
+            .retain(|_, delivery| delivery.webhook_id != id);
+        Ok(inner.webhooks.remove(&id).is_some())
+    }
+
+    async fn create_delivery(
+        &self,
+        webhook_id: u32,
+        event: EventKind,
+        payload: &serde_json::Value,
+    ) -> Result<Delivery, StoreError> {
+        let mut inner = self.lock().await;
+        inner.delivery_id += 1;
+        let delivery = Delivery {
+            id: inner.delivery_id,
+            webhook_id,
+            event,
+            payload: payload.clone(),
+            status: DeliveryStatus::Pending,
+            attempts: 0,
+            response_status: None,
+            error: None,
+            created_at: Utc::now(),
+            last_attempt_at: None,
+        };
+        inner.deliveries.insert(delivery.id, delivery.clone());
+        Ok(delivery)
+    }
+
+    async fn record_attempt(
+        &self,
+        id: u32,
+        attempt: Attempt,
+    ) -> Result<Option<Delivery>, StoreError> {
+        let mut inner = self.lock().await;
+        Ok(inner.deliveries.get_mut(&id).map(|delivery| {
+            delivery.status = attempt.status;
+            delivery.attempts += 1;
+            delivery.response_status = attempt.response_status;
+            delivery.error = attempt.error;
+            delivery.last_attempt_at = Some(Utc::now());
+            delivery.clone()
+        }))
+    }
+
+    async fn delivery(&self, id: u32) -> Result<Option<Delivery>, StoreError> {
+        let inner = self.lock().await;
+        Ok(inner.deliveries.get(&id).cloned())
+    }
+
+    async fn deliveries(&self, webhook_id: u32) -> Result<Vec<Delivery>, StoreError> {
+        let inner = self.lock().await;
+        Ok(inner
+            .deliveries
+            .values()
+            .rev()
+            .filter(|delivery| delivery.webhook_id == webhook_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn pending_deliveries(&self) -> Result<Vec<Delivery>, StoreError> {
+        let inner = self.lock().await;
+        Ok(inner
+            .deliveries
+            .values()
+            .filter(|delivery| delivery.status == DeliveryStatus::Pending)
+            .cloned()
+            .collect())
+    }
+}
@@ -0,0 +1,343 @@
+mod memory;
+mod sqlite;
+
+use std::{collections::BTreeSet, fmt, sync::Arc};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+
+use crate::{
+    auth::User,
+    events::EventKind,
+    history::Revision,
+    keys::{ApiKey, ApiKeyInput},
+    notebooks::{Notebook, NotebookInput},
+    sharing::{Grant, Role, ShareLink},
+    tags::TagCount,
+    webhooks::{Attempt, Delivery, Webhook, WebhookInput},
+    Note, NoteInput,
+};
+
+pub use memory::{MemoryStore, MemoryWorkspaces};
+pub use sqlite::{SqliteStore, SqliteWorkspaces};
+
+/// Storage backend for notes. Handlers only talk to this trait so that
+/// backends and test doubles can be swapped without touching them.
+///
+/// Every successful create and update, including changes to a note's tags,
+/// also records the written content as an immutable [`Revision`].
+///
+/// Notes and notebooks belong to the user that created them. Methods that
+/// work on a whole collection only see the given owner's share of it;
+/// checking who may touch a single note or notebook is up to the caller.
+///
+/// Deleting a note only moves it to the trash. Trashed notes are invisible
+/// to everything but the trash methods until they are restored or purged.
+/// Purging a note also drops its grants and share links.
+#[async_trait]
+pub trait NoteStore: Send + Sync {
+    /// Stores a new note owned by `owner`, assigning its id, timestamps and
+    /// first revision.
+    async fn create(&self, owner: u32, input: NoteInput) -> Result<Note, StoreError>;
+
+    async fn get(&self, id: u32) -> Result<Option<Note>, StoreError>;
+
+    /// Replaces the content of the note stored under `id`, bumping its
+    /// `revision` and `updated_at`. Returns `None` if there is no such note.
+    ///
+    /// When `expected_revision` is given the write only happens if the note
+    /// is still at that revision, and fails with [`StoreError::Conflict`]
+    /// otherwise.
+    async fn update(
+        &self,
+        id: u32,
+        input: NoteInput,
+        expected_revision: Option<u64>,
+    ) -> Result<Option<Note>, StoreError>;
+
+    /// Moves the note stored under `id` to the trash. Returns `false` if
+    /// there is no such note. `expected_revision` behaves as in
+    /// [`NoteStore::update`].
+    async fn delete(&self, id: u32, expected_revision: Option<u64>) -> Result<bool, StoreError>;
+
+    /// Adds `add` to and then removes `remove` from the tags of the note
+    /// stored under `id`. Returns `None` if there is no such note, and the
+    /// note untouched if its tags would not change. `expected_revision`
+    /// behaves as in [`NoteStore::update`].
+    async fn update_tags(
+        &self,
+        id: u32,
+        add: &BTreeSet<String>,
+        remove: &BTreeSet<String>,
+        expected_revision: Option<u64>,
+    ) -> Result<Option<Note>, StoreError>;
+
+    /// Returns every tag in use by a live note of `owner` and how many such
+    /// notes carry it, ordered by tag.
+    async fn tag_counts(&self, owner: u32) -> Result<Vec<TagCount>, StoreError>;
+
+    /// Replaces `from` with `to` on every note of `owner` carrying it,
+    /// trashed ones included, merging the two when a note already has `to`.
+    /// Returns how many notes were changed.
+    async fn rename_tag(&self, owner: u32, from: &str, to: &str) -> Result<usize, StoreError>;
+
+    /// Files the note stored under `id` into a notebook, or at the top level
+    /// for `None`. Returns `None` if there is no such note and the note
+    /// untouched if it is already there. Fails with
+    /// [`StoreError::NotebookNotFound`] for a missing notebook.
+    /// `expected_revision` behaves as in [`NoteStore::update`].
+    async fn move_note(
+        &self,
+        id: u32,
+        notebook_id: Option<u32>,
+        expected_revision: Option<u64>,
+    ) -> Result<Option<Note>, StoreError>;
+
+    /// Stores a new notebook owned by `owner`. Fails with
+    /// [`StoreError::NotebookNotFound`] if the parent does not exist.
+    async fn create_notebook(
+        &self,
+        owner: u32,
+        input: NotebookInput,
+    ) -> Result<Notebook, StoreError>;
+
+    async fn get_notebook(&self, id: u32) -> Result<Option<Notebook>, StoreError>;
+
+    /// Returns every notebook of `owner` ordered by id.
+    async fn list_notebooks(&self, owner: u32) -> Result<Vec<Notebook>, StoreError>;
+
+    /// Renames and re-parents a notebook. Returns `None` if there is no such
+    /// notebook. Fails with [`StoreError::NotebookNotFound`] for a missing
+    /// parent and [`StoreError::NotebookCycle`] for a parent inside the
+    /// notebook itself.
+    async fn update_notebook(
+        &self,
+        id: u32,
+        input: NotebookInput,
+    ) -> Result<Option<Notebook>, StoreError>;
+
+    /// Deletes a notebook. With `cascade` its sub-notebooks go with it and
+    /// every live note in them is moved to the trash; without it a notebook
+    /// holding notebooks or live notes fails with
+    /// [`StoreError::NotebookNotEmpty`]. Notes left in the trash are filed
+    /// at the top level. Returns `false` if there is no such notebook.
+    async fn delete_notebook(&self, id: u32, cascade: bool) -> Result<bool, StoreError>;
+
+    /// Returns every live note of `owner` ordered by id.
+    async fn list(&self, owner: u32) -> Result<Vec<Note>, StoreError>;
+
+    /// Returns every trashed note of `owner` ordered by id.
+    async fn list_trash(&self, owner: u32) -> Result<Vec<Note>, StoreError>;
+
+    /// Takes a note out of the trash. Returns `None` if there is no such
+    /// trashed note.
+    async fn restore(&self, id: u32) -> Result<Option<Note>, StoreError>;
+
+    /// Permanently removes a trashed note and its history. Returns `false` if
+    /// there is no such trashed note.
+    async fn purge(&self, id: u32) -> Result<bool, StoreError>;
+
+    /// Permanently removes every note trashed before `cutoff` and returns how
+    /// many there were.
+    async fn purge_trashed_before(&self, cutoff: DateTime<Utc>) -> Result<usize, StoreError>;
+
+    /// Counts the notes of every user, leaving out trashed ones.
+    async fn count_notes(&self) -> Result<u64, StoreError>;
+
+    /// Returns the history of a note, oldest first. Empty if there is no
+    /// such live note.
+    async fn revisions(&self, id: u32) -> Result<Vec<Revision>, StoreError>;
+
+    async fn revision(&self, id: u32, revision: u64) -> Result<Option<Revision>, StoreError>;
+
+    /// Stores a new user, an admin of the workspace if `is_admin`. Fails
+    /// with [`StoreError::UsernameTaken`] if the name is in use.
+    async fn create_user(
+        &self,
+        username: &str,
+        password_hash: &str,
+        is_admin: bool,
+    ) -> Result<User, StoreError>;
+
+    /// Gives `owner` the notes and notebooks written before there were
+    /// users. Returns how many notes that was.
+    async fn claim_unowned(&self, owner: u32) -> Result<usize, StoreError>;
+
+    async fn get_user(&self, id: u32) -> Result<Option<User>, StoreError>;
+
+    /// Returns the user called `username` with their password hash.
+    async fn user_credentials(&self, username: &str) -> Result<Option<(User, String)>, StoreError>;
+
+    /// Returns every user ordered by id.
+    async fn list_users(&self) -> Result<Vec<User>, StoreError>;
+
+    /// Makes a user an admin or takes that away. Returns `None` if there is
+    /// no such user.
+    async fn set_admin(&self, id: u32, is_admin: bool) -> Result<Option<User>, StoreError>;
+
+    /// Stores a new API key for `owner`. Only the first characters of the
+    /// key, `prefix`, and its hash are kept.
+    async fn create_api_key(
+        &self,
+        owner: u32,
+        input: ApiKeyInput,
+        prefix: &str,
+        key_hash: &str,
+    ) -> Result<ApiKey, StoreError>;
+
+    /// Returns every API key of `owner` ordered by id.
+    async fn list_api_keys(&self, owner: u32) -> Result<Vec<ApiKey>, StoreError>;
+
+    /// Deletes an API key. Returns `false` if there is no such key.
+    async fn revoke_api_key(&self, id: u32) -> Result<bool, StoreError>;
+
+    /// Looks up the API key stored under `key_hash` and records that it was
+    /// just used.
+    async fn use_api_key(&self, key_hash: &str) -> Result<Option<ApiKey>, StoreError>;
+
+    /// Returns the roles granted on a note, ordered by user id.
+    async fn grants(&self, note_id: u32) -> Result<Vec<Grant>, StoreError>;
+
+    /// Grants `user_id` a role on a note, replacing the one they had.
+    async fn grant(&self, note_id: u32, user_id: u32, role: Role) -> Result<(), StoreError>;
+
+    /// Takes back the role of `user_id` on a note. Returns `false` if they
+    /// had none.
+    async fn revoke_grant(&self, note_id: u32, user_id: u32) -> Result<bool, StoreError>;
+
+    /// Returns every live note `user_id` has been granted a role on, with
+    /// that role, ordered by id.
+    async fn shared_with(&self, user_id: u32) -> Result<Vec<(Note, Role)>, StoreError>;
+
+    /// Stores a new share link. Only the hash of its token is kept.
+    async fn create_share_link(
+        &self,
+        note_id: u32,
+        token_hash: &str,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<ShareLink, StoreError>;
+
+    /// Returns the share links of a note ordered by id, expired ones
+    /// included.
+    async fn share_links(&self, note_id: u32) -> Result<Vec<ShareLink>, StoreError>;
+
+    /// Returns the share link whose token hashes to `token_hash`.
+    async fn share_link(&self, token_hash: &str) -> Result<Option<ShareLink>, StoreError>;
+
+    /// Deletes a share link. Returns `false` if there is no such link.
+    async fn delete_share_link(&self, id: u32) -> Result<bool, StoreError>;
+
+    async fn create_webhook(&self, owner: u32, input: WebhookInput) -> Result<Webhook, StoreError>;
+
+    async fn get_webhook(&self, id: u32) -> Result<Option<Webhook>, StoreError>;
+
+    /// Returns every webhook of `owner` ordered by id.
+    async fn list_webhooks(&self, owner: u32) -> Result<Vec<Webhook>, StoreError>;
+
+    /// Returns the webhooks of `users` that want `event`, ordered by id.
+    async fn webhooks_for(
+        &self,
+        users: &BTreeSet<u32>,
+        event: EventKind,
+    ) -> Result<Vec<Webhook>, StoreError>;
+
+    /// Deletes a webhook and its deliveries. Returns `false` if there is no
+    /// such webhook.
+    async fn delete_webhook(&self, id: u32) -> Result<bool, StoreError>;
+
+    /// Logs a new pending delivery of `payload` to a webhook.
+    async fn create_delivery(
+        &self,
+        webhook_id: u32,
+        event: EventKind,
+        payload: &serde_json::Value,
+    ) -> Result<Delivery, StoreError>;
+
+    /// Records the outcome of an attempt at a delivery, counting the attempt.
+    /// Returns `None` if there is no such delivery.
+    async fn record_attempt(
+        &self,
+        id: u32,
+        attempt: Attempt,
+    ) -> Result<Option<Delivery>, StoreError>;
+
+    async fn delivery(&self, id: u32) -> Result<Option<Delivery>, StoreError>;
+
+    /// Returns the deliveries of a webhook, latest first.
+    async fn deliveries(&self, webhook_id: u32) -> Result<Vec<Delivery>, StoreError>;
+
+    /// Returns the deliveries of every webhook still to be sent, oldest
+    /// first.
+    async fn pending_deliveries(&self) -> Result<Vec<Delivery>, StoreError>;
+
+    /// Writes out whatever the store still holds in memory, waiting for
+    /// writes in progress. Called before the process exits; stores that
+    /// keep nothing back need not override it.
+    async fn flush(&self) -> Result<(), StoreError> {
+        Ok(())
+    }
+}
+
+/// Opens the stores of workspaces other than the default one. Each workspace
+/// has a store of its own, so nothing it holds is visible to the others.
+pub trait WorkspaceStores: Send + Sync {
+    /// Opens the store of the workspace `name`, creating the workspace when
+    /// `create` is set. Returns `None` if there is no such workspace and it
+    /// is not to be created.
+    fn open(&self, name: &str, create: bool) -> Result<Option<Arc<dyn NoteStore>>, StoreError>;
+
+    /// Returns the names of the workspaces that can be opened, in no
+    /// particular order.
+    fn names(&self) -> Result<Vec<String>, StoreError>;
+
+    /// Deletes the workspace `name` with everything in it, undoing a
+    /// creation that could not be finished.
+    fn remove(&self, name: &str) -> Result<(), StoreError>;
+}
+
+#[derive(Debug)]
+pub enum StoreError {
+    /// A conditional write found the note modified in the meantime.
+    Conflict,
+    /// A notebook referred to by the write does not exist.
+    NotebookNotFound(u32),
+    /// The write would make a notebook its own ancestor.
+    NotebookCycle,
+    /// A notebook still holds notebooks or notes.
+    NotebookNotEmpty,
+    UsernameTaken,
+    WorkspaceExists,
+    Sqlite(rusqlite::Error),
+    Io(std::io::Error),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::Conflict => write!(f, "note was modified concurrently"),
+            StoreError::NotebookNotFound(id) => write!(f, "notebook {} not found", id),
+            StoreError::NotebookCycle => {
+                write!(f, "a notebook cannot be nested inside itself")
+            }
+            StoreError::NotebookNotEmpty => write!(f, "notebook is not empty"),
+            StoreError::UsernameTaken => write!(f, "username is already taken"),
+            StoreError::WorkspaceExists => write!(f, "workspace already exists"),
+            StoreError::Sqlite(e) => write!(f, "sqlite error: {}", e),
+            StoreError::Io(e) => write!(f, "io error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+impl From<rusqlite::Error> for StoreError {
+    fn from(e: rusqlite::Error) -> Self {
+        StoreError::Sqlite(e)
+    }
+}
+
+impl From<std::io::Error> for StoreError {
+    fn from(e: std::io::Error) -> Self {
+        StoreError::Io(e)
+    }
+}
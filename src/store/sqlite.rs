@@ -0,0 +1,1827 @@
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use tokio::sync::Mutex;
+
+use super::{NoteStore, StoreError, WorkspaceStores};
+use crate::{
+    auth::User,
+    events::EventKind,
+    history::Revision,
+    keys::{hash_key, join_scopes, split_scopes, ApiKey, ApiKeyInput},
+    metrics,
+    notebooks::{Notebook, NotebookInput},
+    sharing::{Grant, Role, ShareLink},
+    tags::TagCount,
+    webhooks::{Attempt, Delivery, DeliveryStatus, Webhook, WebhookInput},
+    Note, NoteInput,
+};
+
+/// Schema changes, applied in order. `PRAGMA user_version` records how many
+/// of them a database has already seen.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE IF NOT EXISTS notes (
+        id    INTEGER PRIMARY KEY AUTOINCREMENT,
+        title TEXT NOT NULL,
+        note  TEXT NOT NULL
+    );",
+    "ALTER TABLE notes ADD COLUMN created_at TEXT NOT NULL DEFAULT '';
+     ALTER TABLE notes ADD COLUMN updated_at TEXT NOT NULL DEFAULT '';
+     UPDATE notes SET created_at = strftime('%Y-%m-%d %H:%M:%f+00:00', 'now'),
+                      updated_at = strftime('%Y-%m-%d %H:%M:%f+00:00', 'now');",
+    "ALTER TABLE notes ADD COLUMN revision INTEGER NOT NULL DEFAULT 1;",
+    "CREATE TABLE note_revisions (
+        note_id    INTEGER NOT NULL,
+        revision   INTEGER NOT NULL,
+        title      TEXT NOT NULL,
+        note       TEXT NOT NULL,
+        created_at TEXT NOT NULL,
+        PRIMARY KEY (note_id, revision)
+    );
+    INSERT INTO note_revisions SELECT id, revision, title, note, updated_at FROM notes;",
+    "ALTER TABLE notes ADD COLUMN deleted_at TEXT;",
+    "CREATE TABLE note_tags (
+        note_id INTEGER NOT NULL,
+        tag     TEXT NOT NULL,
+        PRIMARY KEY (note_id, tag)
+    );
+    CREATE INDEX note_tags_by_tag ON note_tags (tag);",
+    "CREATE TABLE notebooks (
+        id         INTEGER PRIMARY KEY AUTOINCREMENT,
+        name       TEXT NOT NULL,
+        parent_id  INTEGER,
+        created_at TEXT NOT NULL,
+        updated_at TEXT NOT NULL
+    );
+    ALTER TABLE notes ADD COLUMN notebook_id INTEGER;",
+    "CREATE TABLE users (
+        id            INTEGER PRIMARY KEY AUTOINCREMENT,
+        username      TEXT NOT NULL UNIQUE,
+        password_hash TEXT NOT NULL,
+        created_at    TEXT NOT NULL
+    );
+    ALTER TABLE notes ADD COLUMN owner_id INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE notebooks ADD COLUMN owner_id INTEGER NOT NULL DEFAULT 0;",
+    "CREATE TABLE api_keys (
+        id           INTEGER PRIMARY KEY AUTOINCREMENT,
+        user_id      INTEGER NOT NULL,
+        name         TEXT NOT NULL,
+        prefix       TEXT NOT NULL,
+        key_hash     TEXT NOT NULL UNIQUE,
+        scopes       TEXT NOT NULL,
+        created_at   TEXT NOT NULL,
+        last_used_at TEXT
+    );",
+    "CREATE TABLE note_grants (
+        note_id INTEGER NOT NULL,
+        user_id INTEGER NOT NULL,
+        role    TEXT NOT NULL,
+        PRIMARY KEY (note_id, user_id)
+    );
+    CREATE INDEX note_grants_by_user ON note_grants (user_id);
+    CREATE TABLE share_links (
+        id         INTEGER PRIMARY KEY AUTOINCREMENT,
+        note_id    INTEGER NOT NULL,
+        token      TEXT NOT NULL UNIQUE,
+        created_at TEXT NOT NULL,
+        expires_at TEXT
+    );",
+    "ALTER TABLE users ADD COLUMN is_admin INTEGER NOT NULL DEFAULT 0;",
+    "CREATE TABLE webhooks (
+        id         INTEGER PRIMARY KEY AUTOINCREMENT,
+        user_id    INTEGER NOT NULL,
+        url        TEXT NOT NULL,
+        events     TEXT NOT NULL,
+        secret     TEXT NOT NULL,
+        created_at TEXT NOT NULL
+    );
+    CREATE TABLE webhook_deliveries (
+        id              INTEGER PRIMARY KEY AUTOINCREMENT,
+        webhook_id      INTEGER NOT NULL,
+        event           TEXT NOT NULL,
+        payload         TEXT NOT NULL,
+        status          TEXT NOT NULL,
+        attempts        INTEGER NOT NULL DEFAULT 0,
+        response_status INTEGER,
+        error           TEXT,
+        created_at      TEXT NOT NULL,
+        last_attempt_at TEXT
+    );
+    CREATE INDEX webhook_deliveries_by_webhook ON webhook_deliveries (webhook_id);",
+    "CREATE INDEX webhooks_by_user ON webhooks (user_id);",
+    "ALTER TABLE share_links RENAME COLUMN token TO token_hash;",
+];
+
+/// Owner of the rows written before there were users.
+const NO_OWNER: u32 = 0;
+
+/// Tags are aggregated into one comma separated column; they cannot contain
+/// commas themselves.
+const NOTE_COLUMNS: &str = "id, title, note, revision, created_at, updated_at, deleted_at,
+    notebook_id, owner_id, (SELECT group_concat(tag, ',') FROM note_tags WHERE note_id = notes.id)";
+const REVISION_COLUMNS: &str = "note_id, revision, title, note, created_at";
+const NOTEBOOK_COLUMNS: &str = "id, name, parent_id, created_at, updated_at, owner_id";
+const USER_COLUMNS: &str = "id, username, created_at, is_admin";
+const API_KEY_COLUMNS: &str = "id, user_id, name, prefix, scopes, created_at, last_used_at";
+/// The migration after which the share link tokens stored so far are
+/// replaced by their hashes, which SQL cannot compute.
+const HASH_SHARE_TOKENS: usize = 13;
+
+const SHARE_LINK_COLUMNS: &str = "id, note_id, created_at, expires_at";
+/// Events are stored comma separated, like tags.
+const WEBHOOK_COLUMNS: &str = "id, user_id, url, events, secret, created_at";
+const DELIVERY_COLUMNS: &str = "id, webhook_id, event, payload, status, attempts,
+    response_status, error, created_at, last_attempt_at";
+
+/// SQLite backed note storage.
+pub struct SqliteStore {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStore {
+    /// Runs `f` with the connection on a blocking thread, so that SQLite's
+    /// disk I/O does not hold up the async workers.
+    async fn run<T, F>(&self, f: F) -> Result<T, StoreError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T, StoreError> + Send + 'static,
+    {
+        let mut conn = metrics::lock_owned("sqlite", self.conn.clone()).await;
+        tokio::task::spawn_blocking(move || f(&mut conn))
+            .await
+            .map_err(|e| StoreError::Io(std::io::Error::other(e)))?
+    }
+
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, StoreError> {
+        Self::init(Connection::open(path)?)
+    }
+
+    pub fn open_in_memory() -> Result<Self, StoreError> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(mut conn: Connection) -> Result<Self, StoreError> {
+        let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        let tx = conn.transaction()?;
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            tx.execute_batch(migration)?;
+            if i == HASH_SHARE_TOKENS {
+                hash_share_tokens(&tx)?;
+            }
+            tx.pragma_update(None, "user_version", i + 1)?;
+        }
+        tx.commit()?;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+}
+
+/// Keeps the database of each workspace as `{name}.db` in one directory.
+pub struct SqliteWorkspaces {
+    dir: PathBuf,
+}
+
+impl SqliteWorkspaces {
+    /// Creates `dir` if it does not exist yet.
+    pub fn new(dir: impl Into<PathBuf>) -> Result<Self, StoreError> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{name}.db"))
+    }
+}
+
+impl WorkspaceStores for SqliteWorkspaces {
+    fn open(&self, name: &str, create: bool) -> Result<Option<Arc<dyn NoteStore>>, StoreError> {
+        let path = self.path(name);
+        if !create && !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(Arc::new(SqliteStore::open(path)?)))
+    }
+
+    fn names(&self) -> Result<Vec<String>, StoreError> {
+        let mut names = Vec::new();
+        for entry in std::fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "db") {
+                if let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) {
+                    names.push(name.to_owned());
+                }
+            }
+        }
+        Ok(names)
+    }
+
+    fn remove(&self, name: &str) -> Result<(), StoreError> {
+        let path = self.path(name);
+        for suffix in ["-journal", "-wal", "-shm"] {
+            let mut side = path.clone().into_os_string();
+            side.push(suffix);
+            match std::fs::remove_file(side) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+}
+
+fn note_from_row(row: &Row) -> rusqlite::Result<Note> {
+    Ok(Note {
+        id: row.get(0)?,
+        title: row.get(1)?,
+        note: row.get(2)?,
+        revision: row.get(3)?,
+        created_at: row.get(4)?,
+        updated_at: row.get(5)?,
+        deleted_at: row.get(6)?,
+        notebook_id: row.get(7)?,
+        owner_id: row.get(8)?,
+        tags: row
+            .get::<_, Option<String>>(9)?
+            .map(|tags| tags.split(',').map(str::to_owned).collect())
+            .unwrap_or_default(),
+    })
+}
+
+fn notebook_from_row(row: &Row) -> rusqlite::Result<Notebook> {
+    Ok(Notebook {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        parent_id: row.get(2)?,
+        created_at: row.get(3)?,
+        updated_at: row.get(4)?,
+        owner_id: row.get(5)?,
+    })
+}
+
+fn user_from_row(row: &Row) -> rusqlite::Result<User> {
+    Ok(User {
+        id: row.get(0)?,
+        username: row.get(1)?,
+        created_at: row.get(2)?,
+        is_admin: row.get(3)?,
+    })
+}
+
+fn api_key_from_row(row: &Row) -> rusqlite::Result<ApiKey> {
+    Ok(ApiKey {
+        id: row.get(0)?,
+        user_id: row.get(1)?,
+        name: row.get(2)?,
+        prefix: row.get(3)?,
+        scopes: split_scopes(&row.get::<_, String>(4)?),
+        created_at: row.get(5)?,
+        last_used_at: row.get(6)?,
+    })
+}
+
+fn share_link_from_row(row: &Row) -> rusqlite::Result<ShareLink> {
+    Ok(ShareLink {
+        id: row.get(0)?,
+        note_id: row.get(1)?,
+        created_at: row.get(2)?,
+        expires_at: row.get(3)?,
+    })
+}
+
+fn hash_share_tokens(conn: &Connection) -> rusqlite::Result<()> {
+    let tokens: Vec<(u32, String)> = {
+        let mut stmt = conn.prepare("SELECT id, token_hash FROM share_links")?;
+        let tokens = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<_, _>>()?;
+        tokens
+    };
+    for (id, token) in tokens {
+        conn.execute(
+            "UPDATE share_links SET token_hash = ?2 WHERE id = ?1",
+            params![id, hash_key(&token)],
+        )?;
+    }
+    Ok(())
+}
+
+/// Parses a column holding the name of something; an unknown name is a
+/// corrupt row.
+fn parsed<T: FromStr<Err = String>>(row: &Row, index: usize) -> rusqlite::Result<T> {
+    let name: String = row.get(index)?;
+    name.parse().map_err(|e: String| {
+        rusqlite::Error::FromSqlConversionFailure(index, rusqlite::types::Type::Text, e.into())
+    })
+}
+
+fn webhook_from_row(row: &Row) -> rusqlite::Result<Webhook> {
+    let events: String = row.get(3)?;
+    Ok(Webhook {
+        id: row.get(0)?,
+        user_id: row.get(1)?,
+        url: row.get(2)?,
+        events: events.split(',').filter_map(|e| e.parse().ok()).collect(),
+        secret: row.get(4)?,
+        created_at: row.get(5)?,
+    })
+}
+
+fn delivery_from_row(row: &Row) -> rusqlite::Result<Delivery> {
+    let payload: String = row.get(3)?;
+    Ok(Delivery {
+        id: row.get(0)?,
+        webhook_id: row.get(1)?,
+        event: parsed(row, 2)?,
+        payload: serde_json::from_str(&payload).map_err(|e| {
+            rusqlite::Error::FromSqlConversionFailure(3, rusqlite::types::Type::Text, e.into())
+        })?,
+        status: parsed(row, 4)?,
+        attempts: row.get(5)?,
+        response_status: row.get(6)?,
+        error: row.get(7)?,
+        created_at: row.get(8)?,
+        last_attempt_at: row.get(9)?,
+    })
+}
+
+fn revision_from_row(row: &Row) -> rusqlite::Result<Revision> {
+    Ok(Revision {
+        note_id: row.get(0)?,
+        revision: row.get(1)?,
+        title: row.get(2)?,
+        note: row.get(3)?,
+        created_at: row.get(4)?,
+    })
+}
+
+fn record_revision(conn: &Connection, note: &Note) -> rusqlite::Result<()> {
+    conn.execute(
+        &format!("INSERT INTO note_revisions ({REVISION_COLUMNS}) VALUES (?1, ?2, ?3, ?4, ?5)"),
+        params![
+            note.id,
+            note.revision,
+            note.title,
+            note.note,
+            note.updated_at
+        ],
+    )?;
+    Ok(())
+}
+
+fn purge_note(conn: &Connection, id: u32) -> rusqlite::Result<()> {
+    conn.execute("DELETE FROM notes WHERE id = ?1", params![id])?;
+    conn.execute("DELETE FROM note_revisions WHERE note_id = ?1", params![id])?;
+    conn.execute("DELETE FROM note_tags WHERE note_id = ?1", params![id])?;
+    conn.execute("DELETE FROM note_grants WHERE note_id = ?1", params![id])?;
+    conn.execute("DELETE FROM share_links WHERE note_id = ?1", params![id])?;
+    Ok(())
+}
+
+/// Bumps the revision of a note whose tags or notebook were just changed and
+/// records it in the history.
+fn commit(conn: &Connection, id: u32) -> rusqlite::Result<Note> {
+    let note = conn.query_row(
+        &format!(
+            "UPDATE notes SET revision = revision + 1, updated_at = ?2 WHERE id = ?1
+             RETURNING {NOTE_COLUMNS}"
+        ),
+        params![id, Utc::now()],
+        note_from_row,
+    )?;
+    record_revision(conn, &note)?;
+    Ok(note)
+}
+
+fn parent_of(conn: &Connection, id: u32) -> rusqlite::Result<Option<Option<u32>>> {
+    conn.query_row(
+        "SELECT parent_id FROM notebooks WHERE id = ?1",
+        params![id],
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+/// Checks that `parent` can hold the notebook `id`, or a new notebook for
+/// `None`.
+fn check_parent(conn: &Connection, id: Option<u32>, parent: Option<u32>) -> Result<(), StoreError> {
+    let mut ancestor = parent;
+    while let Some(current) = ancestor {
+        if Some(current) == id {
+            return Err(StoreError::NotebookCycle);
+        }
+        ancestor = parent_of(conn, current)?.ok_or(StoreError::NotebookNotFound(current))?;
+    }
+    Ok(())
+}
+
+fn is_live(conn: &Connection, id: u32) -> rusqlite::Result<bool> {
+    conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM notes WHERE id = ?1 AND deleted_at IS NULL)",
+        params![id],
+        |row| row.get(0),
+    )
+}
+
+/// Checks `expected_revision` against the stored note. Returns `false` if
+/// the note does not exist or is trashed.
+fn check_revision(
+    conn: &Connection,
+    id: u32,
+    expected_revision: Option<u64>,
+) -> Result<bool, StoreError> {
+    let Some(expected) = expected_revision else {
+        return Ok(true);
+    };
+    let current: Option<u64> = conn
+        .query_row(
+            "SELECT revision FROM notes WHERE id = ?1 AND deleted_at IS NULL",
+            params![id],
+            |row| row.get(0),
+        )
+        .optional()?;
+    match current {
+        None => Ok(false),
+        Some(current) if current != expected => Err(StoreError::Conflict),
+        Some(_) => Ok(true),
+    }
+}
+
+#[async_trait]
+impl NoteStore for SqliteStore {
+    async fn create(&self, owner: u32, input: NoteInput) -> Result<Note, StoreError> {
+        self.run(move |conn| {
+            let tx = conn.transaction()?;
+            let now = Utc::now();
+            let note = tx.query_row(
+                &format!(
+                    "INSERT INTO notes (title, note, created_at, updated_at, owner_id)
+                     VALUES (?1, ?2, ?3, ?3, ?4)
+                     RETURNING {NOTE_COLUMNS}"
+                ),
+                params![input.title, input.note, now, owner],
+                note_from_row,
+            )?;
+            record_revision(&tx, &note)?;
+            tx.commit()?;
+            Ok(note)
+        })
+        .await
+    }
+
+    async fn get(&self, id: u32) -> Result<Option<Note>, StoreError> {
+        self.run(move |conn| {
+            let note = conn
+                .query_row(
+                    &format!(
+                        "SELECT {NOTE_COLUMNS} FROM notes WHERE id = ?1 AND deleted_at IS NULL"
+                    ),
+                    params![id],
+                    note_from_row,
+                )
+                .optional()?;
+            Ok(note)
+        })
+        .await
+    }
+
+    async fn update(
+        &self,
+        id: u32,
+        input: NoteInput,
+        expected_revision: Option<u64>,
+    ) -> Result<Option<Note>, StoreError> {
+        self.run(move |conn| {
+            let tx = conn.transaction()?;
+            if !check_revision(&tx, id, expected_revision)? {
+                return Ok(None);
+            }
+            let note = tx
+                .query_row(
+                    &format!(
+                        "UPDATE notes SET title = ?2, note = ?3, revision = revision + 1, updated_at = ?4
+                         WHERE id = ?1 AND deleted_at IS NULL
+                         RETURNING {NOTE_COLUMNS}"
+                    ),
+                    params![id, input.title, input.note, Utc::now()],
+                    note_from_row,
+                )
+                .optional()?;
+            if let Some(note) = &note {
+                record_revision(&tx, note)?;
+            }
+            tx.commit()?;
+            Ok(note)
+        })
+        .await
+    }
+
+    async fn delete(&self, id: u32, expected_revision: Option<u64>) -> Result<bool, StoreError> {
+        self.run(move |conn| {
+            let tx = conn.transaction()?;
+            if !check_revision(&tx, id, expected_revision)? {
+                return Ok(false);
+            }
+            let changed = tx.execute(
+                "UPDATE notes SET deleted_at = ?2 WHERE id = ?1 AND deleted_at IS NULL",
+                params![id, Utc::now()],
+            )?;
+            tx.commit()?;
+            Ok(changed > 0)
+        })
+        .await
+    }
+
+    async fn update_tags(
+        &self,
+        id: u32,
+        add: &BTreeSet<String>,
+        remove: &BTreeSet<String>,
+        expected_revision: Option<u64>,
+    ) -> Result<Option<Note>, StoreError> {
+        let add = add.to_owned();
+        let remove = remove.to_owned();
+        self.run(move |conn| {
+            let tx = conn.transaction()?;
+            if !check_revision(&tx, id, expected_revision)? {
+                return Ok(None);
+            }
+            let Some(note) = tx
+                .query_row(
+                    &format!(
+                        "SELECT {NOTE_COLUMNS} FROM notes WHERE id = ?1 AND deleted_at IS NULL"
+                    ),
+                    params![id],
+                    note_from_row,
+                )
+                .optional()?
+            else {
+                return Ok(None);
+            };
+            let tags: BTreeSet<String> = note.tags.union(&add).cloned().collect();
+            let tags: BTreeSet<String> = tags.difference(&remove).cloned().collect();
+            if tags == note.tags {
+                return Ok(Some(note));
+            }
+            tx.execute("DELETE FROM note_tags WHERE note_id = ?1", params![id])?;
+            for tag in &tags {
+                tx.execute(
+                    "INSERT INTO note_tags (note_id, tag) VALUES (?1, ?2)",
+                    params![id, tag],
+                )?;
+            }
+            let note = commit(&tx, id)?;
+            tx.commit()?;
+            Ok(Some(note))
+        })
+        .await
+    }
+
+    async fn tag_counts(&self, owner: u32) -> Result<Vec<TagCount>, StoreError> {
+        self.run(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT tag, COUNT(*) FROM note_tags JOIN notes ON notes.id = note_tags.note_id
+                 WHERE owner_id = ?1 AND deleted_at IS NULL GROUP BY tag ORDER BY tag",
+            )?;
+            let counts = stmt
+                .query_map(params![owner], |row| {
+                    Ok(TagCount {
+                        tag: row.get(0)?,
+                        count: row.get(1)?,
+                    })
+                })?
+                .collect::<Result<_, _>>()?;
+            Ok(counts)
+        })
+        .await
+    }
+
+    async fn rename_tag(&self, owner: u32, from: &str, to: &str) -> Result<usize, StoreError> {
+        let from = from.to_owned();
+        let to = to.to_owned();
+        self.run(move |conn| {
+            let tx = conn.transaction()?;
+            let tagged: Vec<u32> = {
+                let mut stmt = tx.prepare(
+                    "SELECT note_id FROM note_tags JOIN notes ON notes.id = note_tags.note_id
+                     WHERE owner_id = ?1 AND tag = ?2",
+                )?;
+                let ids = stmt
+                    .query_map(params![owner, from], |row| row.get(0))?
+                    .collect::<Result<_, _>>()?;
+                ids
+            };
+            if from != to {
+                for id in &tagged {
+                    tx.execute(
+                        "INSERT OR IGNORE INTO note_tags (note_id, tag) VALUES (?1, ?2)",
+                        params![id, to],
+                    )?;
+                    tx.execute(
+                        "DELETE FROM note_tags WHERE note_id = ?1 AND tag = ?2",
+                        params![id, from],
+                    )?;
+                    commit(&tx, *id)?;
+                }
+            }
+            tx.commit()?;
+            Ok(tagged.len())
+        })
+        .await
+    }
+
+    async fn move_note(
+        &self,
+        id: u32,
+        notebook_id: Option<u32>,
+        expected_revision: Option<u64>,
+    ) -> Result<Option<Note>, StoreError> {
+        self.run(move |conn| {
+            let tx = conn.transaction()?;
+            if let Some(notebook) = notebook_id {
+                if parent_of(&tx, notebook)?.is_none() {
+                    return Err(StoreError::NotebookNotFound(notebook));
+                }
+            }
+            if !check_revision(&tx, id, expected_revision)? {
+                return Ok(None);
+            }
+            let Some(note) = tx
+                .query_row(
+                    &format!(
+                        "SELECT {NOTE_COLUMNS} FROM notes WHERE id = ?1 AND deleted_at IS NULL"
+                    ),
+                    params![id],
+                    note_from_row,
+                )
+                .optional()?
+            else {
+                return Ok(None);
+            };
+            if note.notebook_id == notebook_id {
+                return Ok(Some(note));
+            }
+            tx.execute(
+                "UPDATE notes SET notebook_id = ?2 WHERE id = ?1",
+                params![id, notebook_id],
+            )?;
+            let note = commit(&tx, id)?;
+            tx.commit()?;
+            Ok(Some(note))
+        })
+        .await
+    }
+
+    async fn create_notebook(
+        &self,
+        owner: u32,
+        input: NotebookInput,
+    ) -> Result<Notebook, StoreError> {
+        self.run(move |conn| {
+            let tx = conn.transaction()?;
+            check_parent(&tx, None, input.parent_id)?;
+            let notebook = tx.query_row(
+                &format!(
+                    "INSERT INTO notebooks (name, parent_id, created_at, updated_at, owner_id)
+                     VALUES (?1, ?2, ?3, ?3, ?4) RETURNING {NOTEBOOK_COLUMNS}"
+                ),
+                params![input.name, input.parent_id, Utc::now(), owner],
+                notebook_from_row,
+            )?;
+            tx.commit()?;
+            Ok(notebook)
+        })
+        .await
+    }
+
+    async fn get_notebook(&self, id: u32) -> Result<Option<Notebook>, StoreError> {
+        self.run(move |conn| {
+            let notebook = conn
+                .query_row(
+                    &format!("SELECT {NOTEBOOK_COLUMNS} FROM notebooks WHERE id = ?1"),
+                    params![id],
+                    notebook_from_row,
+                )
+                .optional()?;
+            Ok(notebook)
+        })
+        .await
+    }
+
+    async fn list_notebooks(&self, owner: u32) -> Result<Vec<Notebook>, StoreError> {
+        self.run(move |conn| {
+            let mut stmt = conn.prepare(&format!(
+                "SELECT {NOTEBOOK_COLUMNS} FROM notebooks WHERE owner_id = ?1 ORDER BY id"
+            ))?;
+            let notebooks = stmt
+                .query_map(params![owner], notebook_from_row)?
+                .collect::<Result<_, _>>()?;
+            Ok(notebooks)
+        })
+        .await
+    }
+
+    async fn update_notebook(
+        &self,
+        id: u32,
+        input: NotebookInput,
+    ) -> Result<Option<Notebook>, StoreError> {
+        self.run(move |conn| {
+            let tx = conn.transaction()?;
+            if parent_of(&tx, id)?.is_none() {
+                return Ok(None);
+            }
+            check_parent(&tx, Some(id), input.parent_id)?;
+            let notebook = tx.query_row(
+                &format!(
+                    "UPDATE notebooks SET name = ?2, parent_id = ?3, updated_at = ?4 WHERE id = ?1
+                     RETURNING {NOTEBOOK_COLUMNS}"
+                ),
+                params![id, input.name, input.parent_id, Utc::now()],
+                notebook_from_row,
+            )?;
+            tx.commit()?;
+            Ok(Some(notebook))
+        })
+        .await
+    }
+
+    async fn delete_notebook(&self, id: u32, cascade: bool) -> Result<bool, StoreError> {
+        self.run(move |conn| {
+            let tx = conn.transaction()?;
+            if parent_of(&tx, id)?.is_none() {
+                return Ok(false);
+            }
+            let subtree: Vec<u32> = {
+                let mut stmt = tx.prepare(
+                    "WITH RECURSIVE subtree(id) AS (
+                         SELECT ?1
+                         UNION SELECT notebooks.id FROM notebooks
+                               JOIN subtree ON notebooks.parent_id = subtree.id
+                     )
+                     SELECT id FROM subtree",
+                )?;
+                let ids = stmt
+                    .query_map(params![id], |row| row.get(0))?
+                    .collect::<Result<_, _>>()?;
+                ids
+            };
+            if !cascade {
+                let holds_notes: bool = tx.query_row(
+                    "SELECT EXISTS (SELECT 1 FROM notes WHERE notebook_id = ?1 AND deleted_at IS NULL)",
+                    params![id],
+                    |row| row.get(0),
+                )?;
+                if subtree.len() > 1 || holds_notes {
+                    return Err(StoreError::NotebookNotEmpty);
+                }
+            }
+            let now = Utc::now();
+            for notebook in &subtree {
+                tx.execute(
+                    "UPDATE notes SET deleted_at = coalesce(deleted_at, ?2), notebook_id = NULL
+                     WHERE notebook_id = ?1",
+                    params![notebook, now],
+                )?;
+                tx.execute("DELETE FROM notebooks WHERE id = ?1", params![notebook])?;
+            }
+            tx.commit()?;
+            Ok(true)
+        })
+        .await
+    }
+
+    async fn list(&self, owner: u32) -> Result<Vec<Note>, StoreError> {
+        self.run(move |conn| {
+            let mut stmt = conn.prepare(&format!(
+                "SELECT {NOTE_COLUMNS} FROM notes WHERE owner_id = ?1 AND deleted_at IS NULL
+                 ORDER BY id"
+            ))?;
+            let notes = stmt
+                .query_map(params![owner], note_from_row)?
+                .collect::<Result<_, _>>()?;
+            Ok(notes)
+        })
+        .await
+    }
+
+    async fn list_trash(&self, owner: u32) -> Result<Vec<Note>, StoreError> {
+        self.run(move |conn| {
+            let mut stmt = conn.prepare(&format!(
+                "SELECT {NOTE_COLUMNS} FROM notes WHERE owner_id = ?1 AND deleted_at IS NOT NULL
+                 ORDER BY id"
+            ))?;
+            let notes = stmt
+                .query_map(params![owner], note_from_row)?
+                .collect::<Result<_, _>>()?;
+            Ok(notes)
+        })
+        .await
+    }
+
+    async fn restore(&self, id: u32) -> Result<Option<Note>, StoreError> {
+        self.run(move |conn| {
+            let note = conn
+                .query_row(
+                    &format!(
+                        "UPDATE notes SET deleted_at = NULL WHERE id = ?1 AND deleted_at IS NOT NULL
+                         RETURNING {NOTE_COLUMNS}"
+                    ),
+                    params![id],
+                    note_from_row,
+                )
+                .optional()?;
+            Ok(note)
+        })
+        .await
+    }
+
+    async fn purge(&self, id: u32) -> Result<bool, StoreError> {
+        self.run(move |conn| {
+            let tx = conn.transaction()?;
+            let trashed: bool = tx.query_row(
+                "SELECT EXISTS (SELECT 1 FROM notes WHERE id = ?1 AND deleted_at IS NOT NULL)",
+                params![id],
+                |row| row.get(0),
+            )?;
+            if trashed {
+                purge_note(&tx, id)?;
+            }
+            tx.commit()?;
+            Ok(trashed)
+        })
+        .await
+    }
+
+    async fn purge_trashed_before(&self, cutoff: DateTime<Utc>) -> Result<usize, StoreError> {
+        self.run(move |conn| {
+            let tx = conn.transaction()?;
+            let expired: Vec<u32> = {
+                let mut stmt =
+                    tx.prepare("SELECT id, deleted_at FROM notes WHERE deleted_at IS NOT NULL")?;
+                let rows = stmt.query_map([], |row| {
+                    Ok((row.get::<_, u32>(0)?, row.get::<_, DateTime<Utc>>(1)?))
+                })?;
+                let mut expired = Vec::new();
+                for row in rows {
+                    let (id, deleted_at) = row?;
+                    if deleted_at < cutoff {
+                        expired.push(id);
+                    }
+                }
+                expired
+            };
+            for id in &expired {
+                purge_note(&tx, *id)?;
+            }
+            tx.commit()?;
+            Ok(expired.len())
+        })
+        .await
+    }
+
+    async fn count_notes(&self) -> Result<u64, StoreError> {
+        self.run(move |conn| {
+            let count = conn.query_row(
+                "SELECT COUNT(*) FROM notes WHERE deleted_at IS NULL",
+                [],
+                |row| row.get(0),
+            )?;
+            Ok(count)
+        })
+        .await
+    }
+
+    async fn revisions(&self, id: u32) -> Result<Vec<Revision>, StoreError> {
+        self.run(move |conn| {
+            if !is_live(conn, id)? {
+                return Ok(Vec::new());
+            }
+            let mut stmt = conn.prepare(&format!(
+                "SELECT {REVISION_COLUMNS} FROM note_revisions WHERE note_id = ?1 ORDER BY revision"
+            ))?;
+            let revisions = stmt
+                .query_map(params![id], revision_from_row)?
+                .collect::<Result<_, _>>()?;
+            Ok(revisions)
+        })
+        .await
+    }
+
+    async fn revision(&self, id: u32, revision: u64) -> Result<Option<Revision>, StoreError> {
+        self.run(move |conn| {
+            if !is_live(conn, id)? {
+                return Ok(None);
+            }
+            let revision = conn
+                .query_row(
+                    &format!(
+                        "SELECT {REVISION_COLUMNS} FROM note_revisions
+                         WHERE note_id = ?1 AND revision = ?2"
+                    ),
+                    params![id, revision],
+                    revision_from_row,
+                )
+                .optional()?;
+            Ok(revision)
+        })
+        .await
+    }
+
+    async fn create_user(
+        &self,
+        username: &str,
+        password_hash: &str,
+        is_admin: bool,
+    ) -> Result<User, StoreError> {
+        let username = username.to_owned();
+        let password_hash = password_hash.to_owned();
+        self.run(move |conn| {
+            let tx = conn.transaction()?;
+            let taken: bool = tx.query_row(
+                "SELECT EXISTS (SELECT 1 FROM users WHERE username = ?1)",
+                params![username],
+                |row| row.get(0),
+            )?;
+            if taken {
+                return Err(StoreError::UsernameTaken);
+            }
+            let user = tx.query_row(
+                &format!(
+                    "INSERT INTO users (username, password_hash, created_at, is_admin)
+                     VALUES (?1, ?2, ?3, ?4) RETURNING {USER_COLUMNS}"
+                ),
+                params![username, password_hash, Utc::now(), is_admin],
+                user_from_row,
+            )?;
+            tx.commit()?;
+            Ok(user)
+        })
+        .await
+    }
+
+    async fn claim_unowned(&self, owner: u32) -> Result<usize, StoreError> {
+        self.run(move |conn| {
+            let tx = conn.transaction()?;
+            let claimed = tx.execute(
+                "UPDATE notes SET owner_id = ?1 WHERE owner_id = ?2",
+                params![owner, NO_OWNER],
+            )?;
+            tx.execute(
+                "UPDATE notebooks SET owner_id = ?1 WHERE owner_id = ?2",
+                params![owner, NO_OWNER],
+            )?;
+            tx.commit()?;
+            Ok(claimed)
+        })
+        .await
+    }
+
+    async fn get_user(&self, id: u32) -> Result<Option<User>, StoreError> {
+        self.run(move |conn| {
+            let user = conn
+                .query_row(
+                    &format!("SELECT {USER_COLUMNS} FROM users WHERE id = ?1"),
+                    params![id],
+                    user_from_row,
+                )
+                .optional()?;
+            Ok(user)
+        })
+        .await
+    }
+
+    async fn user_credentials(&self, username: &str) -> Result<Option<(User, String)>, StoreError> {
+        let username = username.to_owned();
+        self.run(move |conn| {
+            let credentials = conn
+                .query_row(
+                    &format!("SELECT {USER_COLUMNS}, password_hash FROM users WHERE username = ?1"),
+                    params![username],
+                    |row| Ok((user_from_row(row)?, row.get(4)?)),
+                )
+                .optional()?;
+            Ok(credentials)
+        })
+        .await
+    }
+
+    async fn list_users(&self) -> Result<Vec<User>, StoreError> {
+        self.run(move |conn| {
+            let mut stmt =
+                conn.prepare(&format!("SELECT {USER_COLUMNS} FROM users ORDER BY id"))?;
+            let users = stmt
+                .query_map([], user_from_row)?
+                .collect::<Result<_, _>>()?;
+            Ok(users)
+        })
+        .await
+    }
+
+    async fn set_admin(&self, id: u32, is_admin: bool) -> Result<Option<User>, StoreError> {
+        self.run(move |conn| {
+            let user = conn
+                .query_row(
+                    &format!(
+                        "UPDATE users SET is_admin = ?2 WHERE id = ?1 RETURNING {USER_COLUMNS}"
+                    ),
+                    params![id, is_admin],
+                    user_from_row,
+                )
+                .optional()?;
+            Ok(user)
+        })
+        .await
+    }
+
+    async fn create_api_key(
+        &self,
+        owner: u32,
+        input: ApiKeyInput,
+        prefix: &str,
+        key_hash: &str,
+    ) -> Result<ApiKey, StoreError> {
+        let prefix = prefix.to_owned();
+        let key_hash = key_hash.to_owned();
+        self.run(move |conn| {
+            let key = conn.query_row(
+                &format!(
+                    "INSERT INTO api_keys (user_id, name, prefix, key_hash, scopes, created_at)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6) RETURNING {API_KEY_COLUMNS}"
+                ),
+                params![
+                    owner,
+                    input.name,
+                    prefix,
+                    key_hash,
+                    join_scopes(&input.scopes),
+                    Utc::now()
+                ],
+                api_key_from_row,
+            )?;
+            Ok(key)
+        })
+        .await
+    }
+
+    async fn list_api_keys(&self, owner: u32) -> Result<Vec<ApiKey>, StoreError> {
+        self.run(move |conn| {
+            let mut stmt = conn.prepare(&format!(
+                "SELECT {API_KEY_COLUMNS} FROM api_keys WHERE user_id = ?1 ORDER BY id"
+            ))?;
+            let keys = stmt
+                .query_map(params![owner], api_key_from_row)?
+                .collect::<Result<_, _>>()?;
+            Ok(keys)
+        })
+        .await
+    }
+
+    async fn revoke_api_key(&self, id: u32) -> Result<bool, StoreError> {
+        self.run(move |conn| {
+            let deleted = conn.execute("DELETE FROM api_keys WHERE id = ?1", params![id])?;
+            Ok(deleted > 0)
+        })
+        .await
+    }
+
+    async fn use_api_key(&self, key_hash: &str) -> Result<Option<ApiKey>, StoreError> {
+        let key_hash = key_hash.to_owned();
+        self.run(move |conn| {
+            let key = conn
+                .query_row(
+                    &format!(
+                        "UPDATE api_keys SET last_used_at = ?1 WHERE key_hash = ?2
+                         RETURNING {API_KEY_COLUMNS}"
+                    ),
+                    params![Utc::now(), key_hash],
+                    api_key_from_row,
+                )
+                .optional()?;
+            Ok(key)
+        })
+        .await
+    }
+
+    async fn grants(&self, note_id: u32) -> Result<Vec<Grant>, StoreError> {
+        self.run(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT users.id, users.username, note_grants.role
+                 FROM note_grants JOIN users ON users.id = note_grants.user_id
+                 WHERE note_grants.note_id = ?1 ORDER BY users.id",
+            )?;
+            let grants = stmt
+                .query_map(params![note_id], |row| {
+                    Ok(Grant {
+                        user_id: row.get(0)?,
+                        username: row.get(1)?,
+                        role: parsed(row, 2)?,
+                    })
+                })?
+                .collect::<Result<_, _>>()?;
+            Ok(grants)
+        })
+        .await
+    }
+
+    async fn grant(&self, note_id: u32, user_id: u32, role: Role) -> Result<(), StoreError> {
+        self.run(move |conn| {
+            conn.execute(
+                "INSERT INTO note_grants (note_id, user_id, role) VALUES (?1, ?2, ?3)
+                 ON CONFLICT (note_id, user_id) DO UPDATE SET role = excluded.role",
+                params![note_id, user_id, role.as_str()],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn revoke_grant(&self, note_id: u32, user_id: u32) -> Result<bool, StoreError> {
+        self.run(move |conn| {
+            let deleted = conn.execute(
+                "DELETE FROM note_grants WHERE note_id = ?1 AND user_id = ?2",
+                params![note_id, user_id],
+            )?;
+            Ok(deleted > 0)
+        })
+        .await
+    }
+
+    async fn shared_with(&self, user_id: u32) -> Result<Vec<(Note, Role)>, StoreError> {
+        self.run(move |conn| {
+            let mut stmt = conn.prepare(&format!(
+                "SELECT {NOTE_COLUMNS}, note_grants.role
+                 FROM notes JOIN note_grants ON note_grants.note_id = notes.id
+                 WHERE note_grants.user_id = ?1 AND notes.deleted_at IS NULL ORDER BY notes.id"
+            ))?;
+            let shared = stmt
+                .query_map(params![user_id], |row| {
+                    Ok((note_from_row(row)?, parsed::<Role>(row, 10)?))
+                })?
+                .collect::<Result<_, _>>()?;
+            Ok(shared)
+        })
+        .await
+    }
+
+    async fn create_share_link(
+        &self,
+        note_id: u32,
+        token_hash: &str,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<ShareLink, StoreError> {
+        let token_hash = token_hash.to_owned();
+        self.run(move |conn| {
+            let link = conn.query_row(
+                &format!(
+                    "INSERT INTO share_links (note_id, token_hash, created_at, expires_at)
+                     VALUES (?1, ?2, ?3, ?4) RETURNING {SHARE_LINK_COLUMNS}"
+                ),
+                params![note_id, token_hash, Utc::now(), expires_at],
+                share_link_from_row,
+            )?;
+            Ok(link)
+        })
+        .await
+    }
+
+    async fn share_links(&self, note_id: u32) -> Result<Vec<ShareLink>, StoreError> {
+        self.run(move |conn| {
+            let mut stmt = conn.prepare(&format!(
+                "SELECT {SHARE_LINK_COLUMNS} FROM share_links WHERE note_id = ?1 ORDER BY id"
+            ))?;
+            let links = stmt
+                .query_map(params![note_id], share_link_from_row)?
+                .collect::<Result<_, _>>()?;
+            Ok(links)
+        })
+        .await
+    }
+
+    async fn share_link(&self, token_hash: &str) -> Result<Option<ShareLink>, StoreError> {
+        let token_hash = token_hash.to_owned();
+        self.run(move |conn| {
+            let link = conn
+                .query_row(
+                    &format!("SELECT {SHARE_LINK_COLUMNS} FROM share_links WHERE token_hash = ?1"),
+                    params![token_hash],
+                    share_link_from_row,
+                )
+                .optional()?;
+            Ok(link)
+        })
+        .await
+    }
+
+    async fn delete_share_link(&self, id: u32) -> Result<bool, StoreError> {
+        self.run(move |conn| {
+            let deleted = conn.execute("DELETE FROM share_links WHERE id = ?1", params![id])?;
+            Ok(deleted > 0)
+        })
+        .await
+    }
+
+    async fn create_webhook(&self, owner: u32, input: WebhookInput) -> Result<Webhook, StoreError> {
+        self.run(move |conn| {
+            let events: Vec<&str> = input.events.iter().map(|event| event.as_str()).collect();
+            let webhook = conn.query_row(
+                &format!(
+                    "INSERT INTO webhooks (user_id, url, events, secret, created_at)
+                     VALUES (?1, ?2, ?3, ?4, ?5) RETURNING {WEBHOOK_COLUMNS}"
+                ),
+                params![owner, input.url, events.join(","), input.secret, Utc::now()],
+                webhook_from_row,
+            )?;
+            Ok(webhook)
+        })
+        .await
+    }
+
+    async fn get_webhook(&self, id: u32) -> Result<Option<Webhook>, StoreError> {
+        self.run(move |conn| {
+            let webhook = conn
+                .query_row(
+                    &format!("SELECT {WEBHOOK_COLUMNS} FROM webhooks WHERE id = ?1"),
+                    params![id],
+                    webhook_from_row,
+                )
+                .optional()?;
+            Ok(webhook)
+        })
+        .await
+    }
+
+    async fn list_webhooks(&self, owner: u32) -> Result<Vec<Webhook>, StoreError> {
+        self.run(move |conn| {
+            let mut stmt = conn.prepare(&format!(
+                "SELECT {WEBHOOK_COLUMNS} FROM webhooks WHERE user_id = ?1 ORDER BY id"
+            ))?;
+            let webhooks = stmt
+                .query_map(params![owner], webhook_from_row)?
+                .collect::<Result<_, _>>()?;
+            Ok(webhooks)
+        })
+        .await
+    }
+
+    async fn webhooks_for(
+        &self,
+        users: &BTreeSet<u32>,
+        event: EventKind,
+    ) -> Result<Vec<Webhook>, StoreError> {
+        let users = users.to_owned();
+        self.run(move |conn| {
+            // No events stored means every event.
+            let mut stmt = conn.prepare(&format!(
+                "SELECT {WEBHOOK_COLUMNS} FROM webhooks WHERE user_id = ?1
+                 AND (events = '' OR instr(',' || events || ',', ?2) > 0)"
+            ))?;
+            let event = format!(",{},", event.as_str());
+            let mut webhooks = Vec::new();
+            for user in users {
+                for webhook in stmt.query_map(params![user, event], webhook_from_row)? {
+                    webhooks.push(webhook?);
+                }
+            }
+            webhooks.sort_by_key(|webhook: &Webhook| webhook.id);
+            Ok(webhooks)
+        })
+        .await
+    }
+
+    async fn delete_webhook(&self, id: u32) -> Result<bool, StoreError> {
+        self.run(move |conn| {
+            let tx = conn.transaction()?;
+            tx.execute(
+                "DELETE FROM webhook_deliveries WHERE webhook_id = ?1",
+                params![id],
+            )?;
+            let deleted = tx.execute("DELETE FROM webhooks WHERE id = ?1", params![id])?;
+            tx.commit()?;
+            Ok(deleted > 0)
+        })
+        .await
+    }
+
+    async fn create_delivery(
+        &self,
+        webhook_id: u32,
+        event: EventKind,
+        payload: &serde_json::Value,
+    ) -> Result<Delivery, StoreError> {
+        let payload = payload.to_owned();
+        self.run(move |conn| {
+            let delivery = conn.query_row(
+                &format!(
+                    "INSERT INTO webhook_deliveries (webhook_id, event, payload, status, created_at)
+                     VALUES (?1, ?2, ?3, ?4, ?5) RETURNING {DELIVERY_COLUMNS}"
+                ),
+                params![
+                    webhook_id,
+                    event.as_str(),
+                    payload.to_string(),
+                    DeliveryStatus::Pending.as_str(),
+                    Utc::now()
+                ],
+                delivery_from_row,
+            )?;
+            Ok(delivery)
+        })
+        .await
+    }
+
+    async fn record_attempt(
+        &self,
+        id: u32,
+        attempt: Attempt,
+    ) -> Result<Option<Delivery>, StoreError> {
+        self.run(move |conn| {
+            let delivery = conn
+                .query_row(
+                    &format!(
+                        "UPDATE webhook_deliveries SET status = ?2, attempts = attempts + 1,
+                             response_status = ?3, error = ?4, last_attempt_at = ?5
+                         WHERE id = ?1 RETURNING {DELIVERY_COLUMNS}"
+                    ),
+                    params![
+                        id,
+                        attempt.status.as_str(),
+                        attempt.response_status,
+                        attempt.error,
+                        Utc::now()
+                    ],
+                    delivery_from_row,
+                )
+                .optional()?;
+            Ok(delivery)
+        })
+        .await
+    }
+
+    async fn delivery(&self, id: u32) -> Result<Option<Delivery>, StoreError> {
+        self.run(move |conn| {
+            let delivery = conn
+                .query_row(
+                    &format!("SELECT {DELIVERY_COLUMNS} FROM webhook_deliveries WHERE id = ?1"),
+                    params![id],
+                    delivery_from_row,
+                )
+                .optional()?;
+            Ok(delivery)
+        })
+        .await
+    }
+
+    async fn deliveries(&self, webhook_id: u32) -> Result<Vec<Delivery>, StoreError> {
+        self.run(move |conn| {
+            let mut stmt = conn.prepare(&format!(
+                "SELECT {DELIVERY_COLUMNS} FROM webhook_deliveries WHERE webhook_id = ?1
+                 ORDER BY id DESC"
+            ))?;
+            let deliveries = stmt
+                .query_map(params![webhook_id], delivery_from_row)?
+                .collect::<Result<_, _>>()?;
+            Ok(deliveries)
+        })
+        .await
+    }
+
+    async fn pending_deliveries(&self) -> Result<Vec<Delivery>, StoreError> {
+        self.run(move |conn| {
+            let mut stmt = conn.prepare(&format!(
+                "SELECT {DELIVERY_COLUMNS} FROM webhook_deliveries WHERE status = ?1 ORDER BY id"
+            ))?;
+            let deliveries = stmt
+                .query_map(params![DeliveryStatus::Pending.as_str()], delivery_from_row)?
+                .collect::<Result<_, _>>()?;
+            Ok(deliveries)
+        })
+        .await
+    }
+
+    async fn flush(&self) -> Result<(), StoreError> {
+        self.run(move |conn| {
+            conn.cache_flush()?;
+            Ok(())
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::Scope;
+
+    #[tokio::test]
+    async fn round_trip() {
+        let store = SqliteStore::open_in_memory().unwrap();
+        let input = NoteInput::new("title".into(), "body".into());
+        let created = store.create(1, input).await.unwrap();
+        assert_eq!(created.created_at, created.updated_at);
+        assert_eq!(store.get(created.id).await.unwrap(), Some(created.clone()));
+
+        let input = NoteInput::new("title".into(), "updated".into());
+        let updated = store
+            .update(created.id, input.clone(), Some(created.revision))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(updated.revision, created.revision + 1);
+        assert_eq!(updated.note, "updated");
+        assert_eq!(updated.created_at, created.created_at);
+        assert!(updated.updated_at >= created.updated_at);
+        assert!(matches!(
+            store
+                .update(created.id, input.clone(), Some(created.revision))
+                .await,
+            Err(StoreError::Conflict)
+        ));
+        assert_eq!(
+            store.update(created.id + 1, input, None).await.unwrap(),
+            None
+        );
+        assert_eq!(store.list(1).await.unwrap(), vec![updated.clone()]);
+        assert_eq!(
+            store.revisions(created.id).await.unwrap(),
+            vec![Revision::from(&created), Revision::from(&updated)]
+        );
+        assert_eq!(
+            store.revision(created.id, 1).await.unwrap(),
+            Some(Revision::from(&created))
+        );
+
+        assert!(matches!(
+            store.delete(created.id, Some(created.revision)).await,
+            Err(StoreError::Conflict)
+        ));
+        assert!(store
+            .delete(created.id, Some(updated.revision))
+            .await
+            .unwrap());
+        assert!(!store.delete(created.id, None).await.unwrap());
+        assert_eq!(store.get(created.id).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn trash() {
+        let store = SqliteStore::open_in_memory().unwrap();
+        let input = NoteInput::new("title".into(), "body".into());
+        let note = store.create(1, input).await.unwrap();
+
+        assert!(store.delete(note.id, None).await.unwrap());
+        assert_eq!(store.list(1).await.unwrap(), vec![]);
+        assert_eq!(store.revisions(note.id).await.unwrap(), vec![]);
+        let trashed = store.list_trash(1).await.unwrap();
+        assert_eq!(trashed.len(), 1);
+        assert!(trashed[0].deleted_at.is_some());
+
+        let restored = store.restore(note.id).await.unwrap().unwrap();
+        assert_eq!(restored, note);
+        assert_eq!(store.restore(note.id).await.unwrap(), None);
+
+        store.delete(note.id, None).await.unwrap();
+        let cutoff = Utc::now() + chrono::Duration::seconds(1);
+        assert_eq!(store.purge_trashed_before(cutoff).await.unwrap(), 1);
+        assert_eq!(store.list_trash(1).await.unwrap(), vec![]);
+        assert!(!store.purge(note.id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn tags() {
+        let store = SqliteStore::open_in_memory().unwrap();
+        let input = NoteInput::new("title".into(), "body".into());
+        let first = store.create(1, input.clone()).await.unwrap();
+        let second = store.create(1, input).await.unwrap();
+        let tags = |tags: &[&str]| -> BTreeSet<String> {
+            tags.iter().map(|tag| tag.to_string()).collect()
+        };
+
+        let tagged = store
+            .update_tags(first.id, &tags(&["a", "b"]), &tags(&[]), Some(1))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(tagged.tags, tags(&["a", "b"]));
+        assert_eq!(tagged.revision, 2);
+        assert_eq!(store.revisions(first.id).await.unwrap().len(), 2);
+        let unchanged = store
+            .update_tags(first.id, &tags(&["a"]), &tags(&[]), None)
+            .await
+            .unwrap();
+        assert_eq!(unchanged, Some(tagged));
+        store
+            .update_tags(second.id, &tags(&["c"]), &tags(&[]), None)
+            .await
+            .unwrap();
+
+        assert_eq!(store.rename_tag(1, "c", "a").await.unwrap(), 1);
+        assert_eq!(
+            store.tag_counts(1).await.unwrap(),
+            vec![
+                TagCount {
+                    tag: "a".into(),
+                    count: 2
+                },
+                TagCount {
+                    tag: "b".into(),
+                    count: 1
+                },
+            ]
+        );
+        let removed = store
+            .update_tags(first.id, &tags(&[]), &tags(&["a"]), None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(removed.tags, tags(&["b"]));
+        assert_eq!(store.get(first.id).await.unwrap(), Some(removed));
+    }
+
+    #[tokio::test]
+    async fn notebooks() {
+        let store = SqliteStore::open_in_memory().unwrap();
+        let work = store
+            .create_notebook(1, NotebookInput::new("work".into(), None))
+            .await
+            .unwrap();
+        let project = store
+            .create_notebook(1, NotebookInput::new("project".into(), Some(work.id)))
+            .await
+            .unwrap();
+        assert!(matches!(
+            store
+                .update_notebook(work.id, NotebookInput::new("work".into(), Some(project.id)))
+                .await,
+            Err(StoreError::NotebookCycle)
+        ));
+        assert!(matches!(
+            store
+                .create_notebook(1, NotebookInput::new("orphan".into(), Some(99)))
+                .await,
+            Err(StoreError::NotebookNotFound(99))
+        ));
+
+        let note = store
+            .create(1, NoteInput::new("title".into(), "body".into()))
+            .await
+            .unwrap();
+        let moved = store
+            .move_note(note.id, Some(project.id), Some(note.revision))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(moved.notebook_id, Some(project.id));
+        assert_eq!(moved.revision, 2);
+        assert_eq!(store.get(note.id).await.unwrap(), Some(moved));
+
+        assert!(matches!(
+            store.delete_notebook(work.id, false).await,
+            Err(StoreError::NotebookNotEmpty)
+        ));
+        assert!(store.delete_notebook(work.id, true).await.unwrap());
+        assert_eq!(store.list_notebooks(1).await.unwrap(), vec![]);
+        let trashed = store.list_trash(1).await.unwrap();
+        assert_eq!(trashed[0].notebook_id, None);
+        assert!(!store.delete_notebook(work.id, true).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn users() {
+        let store = SqliteStore::open_in_memory().unwrap();
+        let alice = store.create_user("alice", "hash", true).await.unwrap();
+        assert!(matches!(
+            store.create_user("alice", "other", false).await,
+            Err(StoreError::UsernameTaken)
+        ));
+        assert_eq!(store.get_user(alice.id).await.unwrap(), Some(alice.clone()));
+        assert_eq!(
+            store.user_credentials("alice").await.unwrap(),
+            Some((alice.clone(), "hash".into()))
+        );
+        assert_eq!(store.user_credentials("bob").await.unwrap(), None);
+
+        assert!(alice.is_admin);
+        let bob = store.create_user("bob", "hash", false).await.unwrap();
+        assert!(!bob.is_admin);
+        let bob = store.set_admin(bob.id, true).await.unwrap().unwrap();
+        assert!(bob.is_admin);
+        assert_eq!(store.list_users().await.unwrap(), vec![alice, bob]);
+        assert_eq!(store.set_admin(9, true).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn api_keys() {
+        let store = SqliteStore::open_in_memory().unwrap();
+        let input = ApiKeyInput {
+            name: "backup".into(),
+            scopes: BTreeSet::from([Scope::Read, Scope::Write]),
+        };
+        let key = store
+            .create_api_key(1, input, "nk_abcdefg", "hash")
+            .await
+            .unwrap();
+        assert_eq!(key.scopes, BTreeSet::from([Scope::Read, Scope::Write]));
+        assert_eq!(key.last_used_at, None);
+        assert_eq!(store.list_api_keys(1).await.unwrap(), vec![key.clone()]);
+        assert_eq!(store.list_api_keys(2).await.unwrap(), vec![]);
+
+        let used = store.use_api_key("hash").await.unwrap().unwrap();
+        assert_eq!(used.id, key.id);
+        assert!(used.last_used_at.is_some());
+        assert_eq!(store.use_api_key("other").await.unwrap(), None);
+
+        assert!(store.revoke_api_key(key.id).await.unwrap());
+        assert!(!store.revoke_api_key(key.id).await.unwrap());
+        assert_eq!(store.use_api_key("hash").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn sharing() {
+        let store = SqliteStore::open_in_memory().unwrap();
+        let alice = store.create_user("alice", "hash", false).await.unwrap();
+        let bob = store.create_user("bob", "hash", false).await.unwrap();
+        let note = store
+            .create(alice.id, NoteInput::new("t".into(), "n".into()))
+            .await
+            .unwrap();
+
+        store.grant(note.id, bob.id, Role::Viewer).await.unwrap();
+        store.grant(note.id, bob.id, Role::Editor).await.unwrap();
+        let grant = Grant {
+            user_id: bob.id,
+            username: "bob".into(),
+            role: Role::Editor,
+        };
+        assert_eq!(store.grants(note.id).await.unwrap(), vec![grant]);
+        assert_eq!(
+            store.shared_with(bob.id).await.unwrap(),
+            vec![(note.clone(), Role::Editor)]
+        );
+        assert_eq!(store.shared_with(alice.id).await.unwrap(), vec![]);
+
+        let link = store
+            .create_share_link(note.id, "token", None)
+            .await
+            .unwrap();
+        assert_eq!(store.share_link("token").await.unwrap(), Some(link.clone()));
+        assert_eq!(store.share_links(note.id).await.unwrap(), vec![link]);
+
+        store.delete(note.id, None).await.unwrap();
+        assert_eq!(store.shared_with(bob.id).await.unwrap(), vec![]);
+        store.purge(note.id).await.unwrap();
+        assert_eq!(store.grants(note.id).await.unwrap(), vec![]);
+        assert_eq!(store.share_link("token").await.unwrap(), None);
+        assert!(!store.revoke_grant(note.id, bob.id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn webhooks() {
+        let store = SqliteStore::open_in_memory().unwrap();
+        let input = WebhookInput {
+            url: "http://localhost/hook".into(),
+            events: BTreeSet::from([EventKind::Created, EventKind::Deleted]),
+            secret: "secret".into(),
+        };
+        let webhook = store.create_webhook(1, input).await.unwrap();
+        assert_eq!(webhook.secret, "secret");
+        assert_eq!(store.list_webhooks(1).await.unwrap(), vec![webhook.clone()]);
+        let users = BTreeSet::from([1, 2]);
+        assert_eq!(
+            store
+                .webhooks_for(&users, EventKind::Deleted)
+                .await
+                .unwrap(),
+            vec![webhook.clone()]
+        );
+        assert_eq!(
+            store
+                .webhooks_for(&users, EventKind::Updated)
+                .await
+                .unwrap(),
+            vec![]
+        );
+        assert_eq!(
+            store
+                .webhooks_for(&BTreeSet::from([2]), EventKind::Deleted)
+                .await
+                .unwrap(),
+            vec![]
+        );
+        assert_eq!(store.list_webhooks(2).await.unwrap(), vec![]);
+
+        let payload = serde_json::json!({"id": 1});
+        let first = store
+            .create_delivery(webhook.id, EventKind::Created, &payload)
+            .await
+            .unwrap();
+        assert_eq!(first.status, DeliveryStatus::Pending);
+        let failed = Attempt {
+            status: DeliveryStatus::Failed,
+            response_status: Some(500),
+            error: Some("boom".into()),
+        };
+        let first = store
+            .record_attempt(first.id, failed)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(first.attempts, 1);
+        assert_eq!(first.response_status, Some(500));
+        assert!(first.last_attempt_at.is_some());
+        assert_eq!(store.delivery(first.id).await.unwrap(), Some(first.clone()));
+        let second = store
+            .create_delivery(webhook.id, EventKind::Deleted, &payload)
+            .await
+            .unwrap();
+        assert_eq!(
+            store.deliveries(webhook.id).await.unwrap(),
+            vec![second.clone(), first.clone()]
+        );
+        assert_eq!(store.pending_deliveries().await.unwrap(), vec![second]);
+
+        assert!(store.delete_webhook(webhook.id).await.unwrap());
+        assert_eq!(store.delivery(first.id).await.unwrap(), None);
+        assert!(!store.delete_webhook(webhook.id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn workspaces() {
+        let dir = std::env::temp_dir().join(format!("notes-workspaces-{}", std::process::id()));
+        let workspaces = SqliteWorkspaces::new(&dir).unwrap();
+        assert!(workspaces.open("team", false).unwrap().is_none());
+        let store = workspaces.open("team", true).unwrap().unwrap();
+        store.create_user("alice", "hash", false).await.unwrap();
+        store.flush().await.unwrap();
+        drop(store);
+
+        assert_eq!(workspaces.names().unwrap(), vec!["team".to_owned()]);
+        let store = workspaces.open("team", false).unwrap().unwrap();
+        assert!(store.user_credentials("alice").await.unwrap().is_some());
+        drop(store);
+
+        workspaces.remove("team").unwrap();
+        assert!(workspaces.names().unwrap().is_empty());
+        assert!(workspaces.open("team", false).unwrap().is_none());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn migrates_legacy_schema() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(MIGRATIONS[0]).unwrap();
+        conn.execute("INSERT INTO notes (title, note) VALUES ('old', 'note')", [])
+            .unwrap();
+
+        let store = SqliteStore::init(conn).unwrap();
+        let note = store.get(1).await.unwrap().unwrap();
+        assert_eq!(note.title, "old");
+        assert_eq!(note.owner_id, NO_OWNER);
+        let user = store.create_user("first", "hash", false).await.unwrap();
+        assert!(!user.is_admin);
+        assert!(store.list(user.id).await.unwrap().is_empty());
+        assert_eq!(store.claim_unowned(user.id).await.unwrap(), 1);
+        assert_eq!(store.list(user.id).await.unwrap().len(), 1);
+        let note = store.get(1).await.unwrap().unwrap();
+        assert_eq!(note.created_at, note.updated_at);
+        assert_eq!(
+            store.revisions(1).await.unwrap(),
+            vec![Revision::from(&note)]
+        );
+    }
+
+    #[tokio::test]
+    async fn hashes_legacy_share_tokens() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let tx = conn.transaction().unwrap();
+        for migration in &MIGRATIONS[..HASH_SHARE_TOKENS] {
+            tx.execute_batch(migration).unwrap();
+        }
+        tx.pragma_update(None, "user_version", HASH_SHARE_TOKENS)
+            .unwrap();
+        tx.execute(
+            "INSERT INTO share_links (note_id, token, created_at) VALUES (1, 'secret', ?1)",
+            params![Utc::now()],
+        )
+        .unwrap();
+        tx.commit().unwrap();
+
+        let store = SqliteStore::init(conn).unwrap();
+        assert_eq!(store.share_link("secret").await.unwrap(), None);
+        let link = store
+            .share_link(&hash_key("secret"))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(link.note_id, 1);
+    }
+}
@@ -0,0 +1,326 @@
+//! Tags on notes: adding and removing them, counting them and renaming or
+//! merging them across every note.
+
+use std::collections::BTreeSet;
+
+use axum::{extract::State, response::Response};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{
+    auth::AuthUser,
+    error::ApiError,
+    etag::IfMatch,
+    events::EventKind,
+    extract::{Json, Path},
+    keys::Scope,
+    notes::{expected_revision, precondition, tagged},
+    sharing::Role,
+    AppState,
+};
+
+pub const MAX_TAG_LEN: usize = 64;
+
+/// Normalizes a tag to lowercase. Tags are 1 to [`MAX_TAG_LEN`] letters,
+/// digits, `-` or `_`; anything else is `None`.
+pub fn normalize(tag: &str) -> Option<String> {
+    let tag = tag.trim().to_lowercase();
+    let valid = !tag.is_empty()
+        && tag.chars().count() <= MAX_TAG_LEN
+        && tag
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_');
+    valid.then_some(tag)
+}
+
+fn invalid_tag(tag: &str) -> String {
+    format!(
+        "Invalid tag `{}`: tags are 1 to {} letters, digits, `-` or `_`",
+        tag, MAX_TAG_LEN
+    )
+}
+
+/// Parses a comma separated list of tags, as used in query strings.
+pub fn parse_list(tags: &str) -> Result<BTreeSet<String>, ApiError> {
+    tags.split(',')
+        .map(|tag| normalize(tag).ok_or_else(|| ApiError::BadRequest(invalid_tag(tag))))
+        .collect()
+}
+
+fn path_tag(tag: &str) -> Result<String, ApiError> {
+    normalize(tag).ok_or_else(|| ApiError::BadRequest(invalid_tag(tag)))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct TagCount {
+    pub tag: String,
+    /// Number of live notes carrying the tag.
+    pub count: usize,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct TagsInput {
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct RenameInput {
+    pub to: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct TagRename {
+    pub from: String,
+    pub to: String,
+    /// Number of notes that carried `from`.
+    pub notes: usize,
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/notes/{id}/tags",
+    tag = "tags",
+    params(("id" = u32, Path, description = "Note id"), ("If-Match" = Option<String>, Header, description = "Only write if the note is still at this `ETag`")),
+    request_body = TagsInput,
+    responses(
+        (status = 200, description = "The tagged note", body = Note, headers(("ETag" = String))),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Not allowed for the caller", body = ErrorBody),
+        (status = 404, description = "Note not found", body = ErrorBody),
+        (status = 412, description = "`If-Match` does not match the current revision", body = ErrorBody),
+        (status = 422, description = "Invalid input", body = ErrorBody),
+    ),
+)]
+pub async fn add_tags(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Path(id): Path<u32>,
+    if_match: IfMatch,
+    Json(payload): Json<TagsInput>,
+) -> Result<Response, ApiError> {
+    user.require(Scope::Write)?;
+    let add = payload
+        .tags
+        .iter()
+        .map(|tag| normalize(tag).ok_or_else(|| ApiError::Unprocessable(invalid_tag(tag))))
+        .collect::<Result<_, _>>()?;
+    let expected = expected_revision(&state, &user, id, Role::Editor, &if_match).await?;
+    let events = state.events.publisher().await;
+    let note = state
+        .store
+        .update_tags(id, &add, &BTreeSet::new(), expected)
+        .await
+        .map_err(precondition)?
+        .ok_or_else(|| ApiError::note_not_found(id))?;
+    tracing::info!(
+        user_id = user.id,
+        note_id = id,
+        tags = ?add,
+        revision = note.revision,
+        "tags added"
+    );
+    events.publish(EventKind::Updated, &note);
+    Ok(tagged(note))
+}
+
+/// Removing a tag the note does not carry leaves the note unchanged.
+#[utoipa::path(
+    delete,
+    path = "/v1/notes/{id}/tags/{tag}",
+    tag = "tags",
+    params(("id" = u32, Path, description = "Note id"), ("tag" = String, Path, description = "Tag to remove"), ("If-Match" = Option<String>, Header, description = "Only write if the note is still at this `ETag`")),
+    responses(
+        (status = 200, description = "The note without the tag", body = Note, headers(("ETag" = String))),
+        (status = 400, description = "Malformed request", body = ErrorBody),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Not allowed for the caller", body = ErrorBody),
+        (status = 404, description = "Note not found", body = ErrorBody),
+        (status = 412, description = "`If-Match` does not match the current revision", body = ErrorBody),
+    ),
+)]
+pub async fn remove_tag(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Path((id, tag)): Path<(u32, String)>,
+    if_match: IfMatch,
+) -> Result<Response, ApiError> {
+    user.require(Scope::Write)?;
+    let remove = BTreeSet::from([path_tag(&tag)?]);
+    let expected = expected_revision(&state, &user, id, Role::Editor, &if_match).await?;
+    let events = state.events.publisher().await;
+    let note = state
+        .store
+        .update_tags(id, &BTreeSet::new(), &remove, expected)
+        .await
+        .map_err(precondition)?
+        .ok_or_else(|| ApiError::note_not_found(id))?;
+    tracing::info!(
+        user_id = user.id,
+        note_id = id,
+        tags = ?remove,
+        revision = note.revision,
+        "tags removed"
+    );
+    events.publish(EventKind::Updated, &note);
+    Ok(tagged(note))
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/tags",
+    tag = "tags",
+    responses(
+        (status = 200, description = "Tags in use with their note counts", body = [TagCount]),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Not allowed for the caller", body = ErrorBody),
+    ),
+)]
+pub async fn list_tags(
+    State(state): State<AppState>,
+    user: AuthUser,
+) -> Result<Json<Vec<TagCount>>, ApiError> {
+    user.require(Scope::Read)?;
+    Ok(Json(state.store.tag_counts(user.id).await?))
+}
+
+/// Renames a tag on every note of the caller. Renaming to a tag that is
+/// already in use merges the two.
+#[utoipa::path(
+    post,
+    path = "/v1/tags/{tag}/rename",
+    tag = "tags",
+    params(("tag" = String, Path, description = "Tag to rename")),
+    request_body = RenameInput,
+    responses(
+        (status = 200, description = "How many notes were changed", body = TagRename),
+        (status = 400, description = "Malformed request", body = ErrorBody),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Not allowed for the caller", body = ErrorBody),
+        (status = 422, description = "Invalid input", body = ErrorBody),
+    ),
+)]
+pub async fn rename_tag(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Path(from): Path<String>,
+    Json(payload): Json<RenameInput>,
+) -> Result<Json<TagRename>, ApiError> {
+    user.require(Scope::Write)?;
+    let from = path_tag(&from)?;
+    let to =
+        normalize(&payload.to).ok_or_else(|| ApiError::Unprocessable(invalid_tag(&payload.to)))?;
+    let events = state.events.publisher().await;
+    let carrying: Vec<u32> = state
+        .store
+        .list(user.id)
+        .await?
+        .into_iter()
+        .filter(|note| note.tags.contains(&from))
+        .map(|note| note.id)
+        .collect();
+    let notes = state.store.rename_tag(user.id, &from, &to).await?;
+    if notes == 0 {
+        return Err(ApiError::NotFound(format!("Tag {} not found", from)));
+    }
+    tracing::info!(user_id = user.id, from = %from, to = %to, notes, "tag renamed");
+    for id in carrying {
+        if let Some(note) = state.store.get(id).await? {
+            events.publish(EventKind::Updated, &note);
+        }
+    }
+    Ok(Json(TagRename { from, to, notes }))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{header, StatusCode};
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        store::NoteStore,
+        test_util::{json_body, request, seeded_store, test_app},
+        NoteInput,
+    };
+
+    #[test]
+    fn normalizes_tags() {
+        assert_eq!(normalize(" Work-Items "), Some("work-items".into()));
+        assert_eq!(normalize(""), None);
+        assert_eq!(normalize("a,b"), None);
+        assert_eq!(normalize(&"x".repeat(MAX_TAG_LEN + 1)), None);
+        assert!(matches!(parse_list("a,b c"), Err(ApiError::BadRequest(_))));
+    }
+
+    #[tokio::test]
+    async fn tags() {
+        let store = seeded_store().await;
+        store
+            .create(1, NoteInput::new("second".into(), "body".into()))
+            .await
+            .unwrap();
+        let app = test_app(store);
+        for (id, tags) in [(1, json!(["Work", "urgent"])), (2, json!(["home"]))] {
+            let response = app
+                .clone()
+                .oneshot(request(
+                    "POST",
+                    &format!("/v1/notes/{}/tags", id),
+                    Some(json!({ "tags": tags })),
+                ))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        let response = app
+            .clone()
+            .oneshot(request("DELETE", "/v1/notes/1/tags/urgent", None))
+            .await
+            .unwrap();
+        assert_eq!(response.headers()[header::ETAG], "\"3\"");
+        assert_eq!(json_body(response).await["tags"], json!(["work"]));
+
+        let response = app
+            .clone()
+            .oneshot(request(
+                "POST",
+                "/v1/tags/home/rename",
+                Some(json!({"to": "work"})),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(json_body(response).await["notes"], 1);
+
+        let response = app
+            .clone()
+            .oneshot(request("GET", "/v1/tags", None))
+            .await
+            .unwrap();
+        assert_eq!(
+            json_body(response).await,
+            json!([{"tag": "work", "count": 2}])
+        );
+
+        let response = app
+            .clone()
+            .oneshot(request("GET", "/v1/notes?tags_all=work", None))
+            .await
+            .unwrap();
+        assert_eq!(
+            json_body(response).await["items"].as_array().unwrap().len(),
+            2
+        );
+
+        let response = app
+            .oneshot(request(
+                "POST",
+                "/v1/notes/1/tags",
+                Some(json!({"tags": ["no spaces"]})),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+}
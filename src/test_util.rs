@@ -0,0 +1,105 @@
+use std::{sync::Arc, time::Duration};
+
+use axum::{
+    body::Body,
+    extract::Request,
+    http::{header, HeaderName, HeaderValue},
+    response::Response,
+    Router,
+};
+use chrono::Utc;
+
+use crate::{
+    app,
+    auth::{Auth, User},
+    config::Config,
+    store::{MemoryStore, MemoryWorkspaces, NoteStore},
+    webhooks::{RetryPolicy, WebhookClient},
+    workspaces::Workspaces,
+    NoteInput,
+};
+
+/// Id of the user that [`request`] authenticates as.
+pub const ALICE: u32 = 1;
+
+/// A fresh store holding the admin `alice` with id 1 and a single note of
+/// hers with id 1.
+pub async fn seeded_store() -> Arc<MemoryStore> {
+    let store = Arc::new(MemoryStore::new());
+    store
+        .create_user("alice", "not a hash", true)
+        .await
+        .unwrap();
+    let note = NoteInput::new("test_title".into(), "test".into());
+    store.create(ALICE, note).await.unwrap();
+    store
+}
+
+pub fn auth() -> Auth {
+    Auth::new(b"test secret")
+}
+
+/// Retries webhook deliveries three times, quickly.
+pub fn retry_policy() -> RetryPolicy {
+    RetryPolicy {
+        max_attempts: 3,
+        base_delay: Duration::from_millis(10),
+        timeout: Duration::from_secs(1),
+    }
+}
+
+/// The application with `store` as its default workspace, accepting tokens
+/// from [`token`]. Webhooks may target the test's own receivers.
+pub fn test_app(store: Arc<MemoryStore>) -> Router {
+    let workspaces = Workspaces::new(store, Arc::new(MemoryWorkspaces));
+    app(
+        Arc::new(workspaces),
+        auth(),
+        WebhookClient::new(retry_policy()).with_private_targets(true),
+        &Config::default(),
+    )
+}
+
+pub fn token(id: u32, username: &str) -> String {
+    let user = User {
+        id,
+        username: username.into(),
+        created_at: Utc::now(),
+        is_admin: false,
+    };
+    auth().issue(&user).unwrap().access_token
+}
+
+/// Authenticates `request` as `alice`.
+pub fn authorized(request: Request) -> Request {
+    let bearer = format!("Bearer {}", token(ALICE, "alice"));
+    with_header(request, header::AUTHORIZATION, &bearer)
+}
+
+/// Builds a request authenticated as `alice`, sending `body` as JSON when
+/// given.
+pub fn request(method: &str, uri: &str, body: Option<serde_json::Value>) -> Request {
+    let builder = Request::builder().method(method).uri(uri);
+    let request = match body {
+        Some(body) => builder
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap(),
+        None => builder.body(Body::empty()).unwrap(),
+    };
+    authorized(request)
+}
+
+pub fn with_header(mut request: Request, name: HeaderName, value: &str) -> Request {
+    request
+        .headers_mut()
+        .insert(name, HeaderValue::from_str(value).unwrap());
+    request
+}
+
+pub async fn json_body(response: Response) -> serde_json::Value {
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    serde_json::from_slice(&bytes).unwrap()
+}
@@ -0,0 +1,221 @@
+//! Trashed notes: listing, restoring and purging them, plus the background
+//! task that purges them once the retention period is over.
+
+use std::{sync::Arc, time::Duration as StdDuration};
+
+use axum::{extract::State, http::StatusCode, response::Response};
+use chrono::{Duration, Utc};
+use tokio::task::JoinHandle;
+
+use crate::{
+    auth::AuthUser,
+    error::ApiError,
+    events::EventKind,
+    extract::{Json, Path},
+    keys::Scope,
+    notes::tagged,
+    store::{NoteStore, StoreError},
+    workspaces::Workspaces,
+    AppState, Note,
+};
+
+/// How often the purger looks for expired notes.
+pub const PURGE_INTERVAL: StdDuration = StdDuration::from_secs(60 * 60);
+
+fn not_in_trash(id: u32) -> ApiError {
+    ApiError::NotFound(format!("Note {} is not in the trash", id))
+}
+
+/// Checks that `id` is one of `user`'s trashed notes.
+async fn check_trashed(state: &AppState, user: &AuthUser, id: u32) -> Result<(), ApiError> {
+    let trash = state.store.list_trash(user.id).await?;
+    if !trash.iter().any(|note| note.id == id) {
+        return Err(not_in_trash(id));
+    }
+    Ok(())
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/trash",
+    tag = "trash",
+    responses(
+        (status = 200, description = "The caller's trashed notes", body = [Note]),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Not allowed for the caller", body = ErrorBody),
+    ),
+)]
+pub async fn list_trash(
+    State(state): State<AppState>,
+    user: AuthUser,
+) -> Result<Json<Vec<Note>>, ApiError> {
+    user.require(Scope::Read)?;
+    Ok(Json(state.store.list_trash(user.id).await?))
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/trash/{id}/restore",
+    tag = "trash",
+    params(("id" = u32, Path, description = "Note id")),
+    responses(
+        (status = 200, description = "The restored note", body = Note, headers(("ETag" = String))),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Not allowed for the caller", body = ErrorBody),
+        (status = 404, description = "Trashed note not found", body = ErrorBody),
+    ),
+)]
+pub async fn restore_note(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Path(id): Path<u32>,
+) -> Result<Response, ApiError> {
+    user.require(Scope::Write)?;
+    check_trashed(&state, &user, id).await?;
+    let events = state.events.publisher().await;
+    let note = state
+        .store
+        .restore(id)
+        .await?
+        .ok_or_else(|| not_in_trash(id))?;
+    tracing::info!(
+        user_id = user.id,
+        note_id = id,
+        "note restored from the trash"
+    );
+    events.publish(EventKind::Restored, &note);
+    Ok(tagged(note))
+}
+
+/// Permanently deletes a trashed note without waiting for the purger.
+#[utoipa::path(
+    delete,
+    path = "/v1/trash/{id}",
+    tag = "trash",
+    params(("id" = u32, Path, description = "Note id")),
+    responses(
+        (status = 204, description = "Note purged"),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Not allowed for the caller", body = ErrorBody),
+        (status = 404, description = "Trashed note not found", body = ErrorBody),
+    ),
+)]
+pub async fn purge_note(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Path(id): Path<u32>,
+) -> Result<StatusCode, ApiError> {
+    user.require(Scope::Delete)?;
+    check_trashed(&state, &user, id).await?;
+    if !state.store.purge(id).await? {
+        return Err(not_in_trash(id));
+    }
+    tracing::info!(user_id = user.id, note_id = id, "note purged");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Purges every note that has been in the trash for longer than
+/// `retention`.
+pub async fn purge_expired(
+    store: &dyn NoteStore,
+    retention: Duration,
+) -> Result<usize, StoreError> {
+    match Utc::now().checked_sub_signed(retention) {
+        Some(cutoff) => store.purge_trashed_before(cutoff).await,
+        // No note was trashed that long ago.
+        None => Ok(0),
+    }
+}
+
+/// Runs [`purge_expired`] on every workspace every [`PURGE_INTERVAL`] until
+/// the returned task is aborted.
+pub fn spawn_purger(workspaces: Arc<Workspaces>, retention: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(PURGE_INTERVAL);
+        loop {
+            interval.tick().await;
+            let stores = match workspaces.stores().await {
+                Ok(stores) => stores,
+                Err(e) => {
+                    tracing::error!(error = %e, "failed to open workspaces");
+                    continue;
+                }
+            };
+            for store in stores {
+                match purge_expired(store.as_ref(), retention).await {
+                    Ok(0) => {}
+                    Ok(purged) => tracing::info!(purged, "purged expired notes from the trash"),
+                    Err(e) => tracing::error!(error = %e, "failed to purge trash"),
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::test_util::{json_body, request, seeded_store, test_app};
+
+    #[tokio::test]
+    async fn purges_only_expired_notes() {
+        let store = seeded_store().await;
+        store.delete(1, None).await.unwrap();
+
+        assert_eq!(
+            purge_expired(store.as_ref(), Duration::days(1))
+                .await
+                .unwrap(),
+            0
+        );
+        assert_eq!(store.list_trash(1).await.unwrap().len(), 1);
+        assert_eq!(
+            purge_expired(store.as_ref(), Duration::MAX).await.unwrap(),
+            0
+        );
+
+        assert_eq!(
+            purge_expired(store.as_ref(), Duration::zero())
+                .await
+                .unwrap(),
+            1
+        );
+        assert!(store.list_trash(1).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn trash_and_restore() {
+        let store = seeded_store().await;
+        let app = test_app(store.clone());
+        app.clone()
+            .oneshot(request("DELETE", "/v1/notes/1", None))
+            .await
+            .unwrap();
+
+        let response = app
+            .clone()
+            .oneshot(request("GET", "/v1/trash", None))
+            .await
+            .unwrap();
+        let trash = json_body(response).await;
+        assert_eq!(trash[0]["id"], 1);
+        assert!(trash[0]["deleted_at"].is_string());
+
+        let response = app
+            .clone()
+            .oneshot(request("POST", "/v1/trash/1/restore", None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(store.get(1).await.unwrap().is_some());
+
+        let response = app
+            .oneshot(request("DELETE", "/v1/trash/1", None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,907 @@
+//! Outgoing webhooks: every note event of the change feed is POSTed as JSON
+//! to the URLs users registered for it.
+//!
+//! A webhook only receives events for notes its owner has a role on, just
+//! like a feed subscriber. Each delivery is signed with the webhook's secret
+//! and retried with exponential backoff until the receiver answers with a
+//! 2xx status or the attempts run out. Every delivery is logged with its
+//! outcome and can be sent again by hand.
+//!
+//! Webhooks cannot target loopback or private addresses unless the
+//! configuration allows it, so that users cannot reach the server's own
+//! network through them. The addresses are checked when a webhook is
+//! created and again before each attempt, since a name can come to point
+//! elsewhere.
+//!
+//! On shutdown no more retries are started, and the deliveries being sent
+//! are given a while to finish. Deliveries left pending are resumed on the
+//! next start.
+
+use std::{collections::BTreeSet, fmt, net::IpAddr, str::FromStr, sync::Arc, time::Duration};
+
+use axum::{extract::State, http::StatusCode, response::IntoResponse};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tokio::sync::broadcast::error::{RecvError, TryRecvError};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use utoipa::ToSchema;
+
+use crate::{
+    auth::AuthUser,
+    error::ApiError,
+    events::{EventKind, NoteEvent},
+    extract::{Json, Path},
+    keys::Scope,
+    store::{NoteStore, StoreError},
+    workspaces::Workspaces,
+    AppState,
+};
+
+pub const SIGNATURE_HEADER: &str = "x-notes-signature";
+pub const EVENT_HEADER: &str = "x-notes-event";
+pub const DELIVERY_HEADER: &str = "x-notes-delivery";
+pub const MAX_SECRET_LEN: usize = 200;
+
+/// A URL notified of note events.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Webhook {
+    pub id: u32,
+    pub user_id: u32,
+    pub url: String,
+    /// The events sent; all of them when empty.
+    pub events: BTreeSet<EventKind>,
+    /// Key of the delivery signatures. Never shown again after the webhook
+    /// is created.
+    #[serde(skip_serializing, default)]
+    pub secret: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Webhook {
+    pub fn wants(&self, kind: EventKind) -> bool {
+        self.events.is_empty() || self.events.contains(&kind)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookInput {
+    pub url: String,
+    #[serde(default)]
+    pub events: BTreeSet<EventKind>,
+    pub secret: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryStatus {
+    /// Not sent successfully yet, with attempts left.
+    Pending,
+    Succeeded,
+    /// Every attempt failed.
+    Failed,
+}
+
+impl DeliveryStatus {
+    pub const ALL: [DeliveryStatus; 3] = [
+        DeliveryStatus::Pending,
+        DeliveryStatus::Succeeded,
+        DeliveryStatus::Failed,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DeliveryStatus::Pending => "pending",
+            DeliveryStatus::Succeeded => "succeeded",
+            DeliveryStatus::Failed => "failed",
+        }
+    }
+}
+
+impl fmt::Display for DeliveryStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for DeliveryStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        DeliveryStatus::ALL
+            .into_iter()
+            .find(|status| status.as_str() == s)
+            .ok_or_else(|| format!("unknown delivery status `{}`", s))
+    }
+}
+
+/// One event sent, or to be sent, to a webhook.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Delivery {
+    pub id: u32,
+    pub webhook_id: u32,
+    pub event: EventKind,
+    /// The body POSTed to the webhook.
+    #[schema(value_type = Object)]
+    pub payload: serde_json::Value,
+    pub status: DeliveryStatus,
+    pub attempts: u32,
+    /// The status code of the last response, if there was one.
+    pub response_status: Option<u16>,
+    /// Why the last attempt failed.
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_attempt_at: Option<DateTime<Utc>>,
+}
+
+/// The outcome of one attempt at a delivery.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attempt {
+    pub status: DeliveryStatus,
+    pub response_status: Option<u16>,
+    pub error: Option<String>,
+}
+
+/// How often and how fast failed deliveries are retried. The delay doubles
+/// after every attempt.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    /// The delay after the first attempt.
+    pub base_delay: Duration,
+    /// How long the receiver has to answer each attempt.
+    pub timeout: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            base_delay: Duration::from_secs(1),
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// The delay after attempt number `attempt`, counting from 1.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        self.base_delay * 2u32.saturating_pow(attempt.saturating_sub(1))
+    }
+}
+
+/// Sends deliveries, shared by every workspace. Clones share the deliveries
+/// being sent.
+#[derive(Clone)]
+pub struct WebhookClient {
+    http: reqwest::Client,
+    retry: RetryPolicy,
+    /// The deliveries being sent and the event dispatchers.
+    tasks: TaskTracker,
+    stopping: CancellationToken,
+    allow_private_targets: bool,
+}
+
+impl Default for WebhookClient {
+    fn default() -> Self {
+        Self::new(RetryPolicy::default())
+    }
+}
+
+impl WebhookClient {
+    pub fn new(retry: RetryPolicy) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            retry,
+            tasks: TaskTracker::new(),
+            stopping: CancellationToken::new(),
+            allow_private_targets: false,
+        }
+    }
+
+    /// Lets webhooks target loopback and private addresses.
+    pub fn with_private_targets(mut self, allow: bool) -> Self {
+        self.allow_private_targets = allow;
+        self
+    }
+
+    /// Checks that `url` may be sent deliveries, resolving its host.
+    pub async fn check_target(&self, url: &Url) -> Result<(), String> {
+        if self.allow_private_targets {
+            return Ok(());
+        }
+        let port = url.port_or_known_default().unwrap_or(80);
+        let host = url.host_str().unwrap_or_default();
+        // IPv6 hosts come in brackets.
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let addrs: Vec<IpAddr> = match host.parse() {
+            Ok(ip) => vec![ip],
+            Err(_) => tokio::net::lookup_host((host, port))
+                .await
+                .map_err(|e| format!("cannot be resolved: {e}"))?
+                .map(|addr| addr.ip())
+                .collect(),
+        };
+        if addrs.is_empty() || !addrs.into_iter().all(is_public) {
+            return Err("must not point to a loopback or private address".into());
+        }
+        Ok(())
+    }
+
+    /// Sends the deliveries left pending when the server last stopped again.
+    /// Returns how many there were.
+    pub async fn resume(&self, workspaces: &Workspaces) -> Result<usize, StoreError> {
+        let mut resumed = 0;
+        for store in workspaces.stores().await? {
+            for delivery in store.pending_deliveries().await? {
+                if let Some(webhook) = store.get_webhook(delivery.webhook_id).await? {
+                    self.spawn(store.clone(), webhook, delivery);
+                    resumed += 1;
+                }
+            }
+        }
+        Ok(resumed)
+    }
+
+    /// Stops retrying and dispatching, and waits up to `timeout` for the
+    /// attempts in flight. Returns whether they all finished.
+    pub async fn drain(&self, timeout: Duration) -> bool {
+        self.stopping.cancel();
+        self.tasks.close();
+        tokio::time::timeout(timeout, self.tasks.wait())
+            .await
+            .is_ok()
+    }
+
+    /// Makes one attempt at a delivery, `attempt` counting from 1.
+    async fn attempt(&self, webhook: &Webhook, delivery: &Delivery, attempt: u32) -> Attempt {
+        let target = match Url::parse(&webhook.url) {
+            Ok(url) => self.check_target(&url).await,
+            Err(e) => Err(format!("is invalid: {e}")),
+        };
+        if let Err(e) = target {
+            return Attempt {
+                status: DeliveryStatus::Failed,
+                response_status: None,
+                error: Some(format!("Webhook URL {e}")),
+            };
+        }
+        let body = delivery.payload.to_string();
+        let sent = self
+            .http
+            .post(&webhook.url)
+            .timeout(self.retry.timeout)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(EVENT_HEADER, delivery.event.as_str())
+            .header(DELIVERY_HEADER, delivery.id.to_string())
+            .header(SIGNATURE_HEADER, sign(&webhook.secret, body.as_bytes()))
+            .body(body)
+            .send()
+            .await;
+        let (ok, response_status, error) = match sent {
+            Ok(response) if response.status().is_success() => {
+                (true, Some(response.status().as_u16()), None)
+            }
+            Ok(response) => (
+                false,
+                Some(response.status().as_u16()),
+                Some(format!("Webhook answered {}", response.status())),
+            ),
+            Err(e) => (false, None, Some(e.to_string())),
+        };
+        let status = match (ok, attempt < self.retry.max_attempts) {
+            (true, _) => DeliveryStatus::Succeeded,
+            (false, true) => DeliveryStatus::Pending,
+            (false, false) => DeliveryStatus::Failed,
+        };
+        Attempt {
+            status,
+            response_status,
+            error,
+        }
+    }
+
+    /// Sends a delivery, retrying until it succeeds, the attempts run out, the
+    /// client is drained or the delivery is deleted along with its webhook,
+    /// and records every attempt.
+    pub async fn deliver(
+        &self,
+        store: &dyn NoteStore,
+        webhook: &Webhook,
+        delivery: &Delivery,
+    ) -> Result<(), StoreError> {
+        // A resumed delivery carries on from its last attempt.
+        for attempt in delivery.attempts + 1..=self.retry.max_attempts {
+            if store.delivery(delivery.id).await?.is_none() {
+                break;
+            }
+            let outcome = self.attempt(webhook, delivery, attempt).await;
+            let pending = outcome.status == DeliveryStatus::Pending;
+            tracing::debug!(
+                webhook_id = webhook.id,
+                delivery_id = delivery.id,
+                attempt,
+                status = outcome.status.as_str(),
+                response_status = outcome.response_status,
+                "webhook delivery attempted"
+            );
+            let recorded = store.record_attempt(delivery.id, outcome).await?;
+            if !pending || recorded.is_none() {
+                break;
+            }
+            tokio::select! {
+                () = tokio::time::sleep(self.retry.delay(attempt)) => {}
+                () = self.stopping.cancelled() => break,
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends a delivery in the background.
+    fn spawn(&self, store: Arc<dyn NoteStore>, webhook: Webhook, delivery: Delivery) {
+        let client = self.clone();
+        self.tasks.spawn(async move {
+            if let Err(e) = client.deliver(store.as_ref(), &webhook, &delivery).await {
+                tracing::error!(
+                    delivery_id = delivery.id,
+                    error = %e,
+                    "failed to record webhook delivery"
+                );
+            }
+        });
+    }
+}
+
+/// Whether `ip` is out on the internet, rather than on the server itself or
+/// a private network.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            // 100.64.0.0/10 is shared by carrier-grade NATs.
+            let shared = ip.octets()[0] == 100 && ip.octets()[1] & 0xc0 == 64;
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || shared)
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(ip.into()),
+            None => {
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local())
+            }
+        },
+    }
+}
+
+/// The signature of `body`: `sha256=` followed by the hex encoded
+/// HMAC-SHA256 of it, keyed with the webhook's secret.
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Turns the events of one workspace into deliveries for as long as the
+/// workspace is served. Once the client is drained, the events already
+/// published are still dispatched.
+pub(crate) fn spawn_dispatcher(state: AppState) {
+    let (_, mut receiver) = state.events.subscribe(None);
+    let webhooks = state.webhooks.clone();
+    webhooks.tasks.spawn(async move {
+        loop {
+            let received = tokio::select! {
+                received = receiver.recv() => received,
+                () = state.webhooks.stopping.cancelled() => match receiver.try_recv() {
+                    Ok(event) => Ok(event),
+                    Err(TryRecvError::Lagged(missed)) => Err(RecvError::Lagged(missed)),
+                    Err(TryRecvError::Empty | TryRecvError::Closed) => break,
+                },
+            };
+            match received {
+                Ok(event) => {
+                    if let Err(e) = dispatch(&state, &event).await {
+                        tracing::error!(event_id = event.id, error = %e, "failed to dispatch event");
+                    }
+                }
+                Err(RecvError::Lagged(missed)) => {
+                    tracing::warn!(missed, "webhooks missed events");
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    });
+}
+
+async fn dispatch(state: &AppState, event: &NoteEvent) -> Result<(), StoreError> {
+    let payload = serde_json::to_value(event).expect("events serialize");
+    // Only users with a role on the note hear about it.
+    let mut users: BTreeSet<u32> = state
+        .store
+        .grants(event.note.id)
+        .await?
+        .into_iter()
+        .map(|grant| grant.user_id)
+        .collect();
+    users.insert(event.note.owner_id);
+    for webhook in state.store.webhooks_for(&users, event.kind).await? {
+        let delivery = state
+            .store
+            .create_delivery(webhook.id, event.kind, &payload)
+            .await?;
+        state.webhooks.spawn(state.store.clone(), webhook, delivery);
+    }
+    Ok(())
+}
+
+/// Fetches a webhook of `user`, reporting other users' as missing.
+async fn find_webhook(state: &AppState, user: &AuthUser, id: u32) -> Result<Webhook, ApiError> {
+    state
+        .store
+        .get_webhook(id)
+        .await?
+        .filter(|webhook| webhook.user_id == user.id)
+        .ok_or_else(|| ApiError::NotFound(format!("Webhook {} not found", id)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/webhooks",
+    tag = "webhooks",
+    request_body = WebhookInput,
+    responses(
+        (status = 201, description = "The new webhook", body = Webhook),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Not allowed for the caller", body = ErrorBody),
+        (status = 422, description = "Invalid input", body = ErrorBody),
+    ),
+)]
+pub async fn create_webhook(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Json(mut payload): Json<WebhookInput>,
+) -> Result<impl IntoResponse, ApiError> {
+    user.require(Scope::Admin)?;
+    payload.url = payload.url.trim().to_owned();
+    let url = Url::parse(&payload.url)
+        .ok()
+        .filter(|url| matches!(url.scheme(), "http" | "https") && url.has_host());
+    let Some(url) = url else {
+        return Err(ApiError::Unprocessable(
+            "`url` must be an http or https URL".into(),
+        ));
+    };
+    if let Err(e) = state.webhooks.check_target(&url).await {
+        return Err(ApiError::Unprocessable(format!("`url` {e}")));
+    }
+    if payload.secret.is_empty() || payload.secret.len() > MAX_SECRET_LEN {
+        return Err(ApiError::Unprocessable(format!(
+            "`secret` must be 1 to {} bytes",
+            MAX_SECRET_LEN
+        )));
+    }
+    let webhook = state.store.create_webhook(user.id, payload).await?;
+    Ok((StatusCode::CREATED, Json(webhook)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/webhooks",
+    tag = "webhooks",
+    responses(
+        (status = 200, description = "The caller's webhooks", body = [Webhook]),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Not allowed for the caller", body = ErrorBody),
+    ),
+)]
+pub async fn list_webhooks(
+    State(state): State<AppState>,
+    user: AuthUser,
+) -> Result<Json<Vec<Webhook>>, ApiError> {
+    user.require(Scope::Admin)?;
+    Ok(Json(state.store.list_webhooks(user.id).await?))
+}
+
+/// Deleting a webhook stops its deliveries and drops their log.
+#[utoipa::path(
+    delete,
+    path = "/v1/webhooks/{id}",
+    tag = "webhooks",
+    params(("id" = u32, Path, description = "Webhook id")),
+    responses(
+        (status = 204, description = "Webhook deleted"),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Not allowed for the caller", body = ErrorBody),
+        (status = 404, description = "Webhook not found", body = ErrorBody),
+    ),
+)]
+pub async fn delete_webhook(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Path(id): Path<u32>,
+) -> Result<StatusCode, ApiError> {
+    user.require(Scope::Admin)?;
+    find_webhook(&state, &user, id).await?;
+    if !state.store.delete_webhook(id).await? {
+        return Err(ApiError::NotFound(format!("Webhook {} not found", id)));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Lists the deliveries of a webhook, latest first.
+#[utoipa::path(
+    get,
+    path = "/v1/webhooks/{id}/deliveries",
+    tag = "webhooks",
+    params(("id" = u32, Path, description = "Webhook id")),
+    responses(
+        (status = 200, description = "Deliveries of the webhook, latest first", body = [Delivery]),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Not allowed for the caller", body = ErrorBody),
+        (status = 404, description = "Webhook not found", body = ErrorBody),
+    ),
+)]
+pub async fn list_deliveries(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Path(id): Path<u32>,
+) -> Result<Json<Vec<Delivery>>, ApiError> {
+    user.require(Scope::Admin)?;
+    find_webhook(&state, &user, id).await?;
+    Ok(Json(state.store.deliveries(id).await?))
+}
+
+/// Sends the payload of an earlier delivery again, as a new delivery with
+/// attempts of its own.
+#[utoipa::path(
+    post,
+    path = "/v1/webhooks/{id}/deliveries/{delivery_id}/redeliver",
+    tag = "webhooks",
+    params(("id" = u32, Path, description = "Webhook id"), ("delivery_id" = u32, Path, description = "Delivery to send again")),
+    responses(
+        (status = 202, description = "The new delivery, sent in the background", body = Delivery),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Not allowed for the caller", body = ErrorBody),
+        (status = 404, description = "Webhook or delivery not found", body = ErrorBody),
+    ),
+)]
+pub async fn redeliver(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Path((id, delivery_id)): Path<(u32, u32)>,
+) -> Result<impl IntoResponse, ApiError> {
+    user.require(Scope::Admin)?;
+    let webhook = find_webhook(&state, &user, id).await?;
+    let original = state
+        .store
+        .delivery(delivery_id)
+        .await?
+        .filter(|delivery| delivery.webhook_id == id)
+        .ok_or_else(|| {
+            ApiError::NotFound(format!(
+                "Delivery {} of webhook {} not found",
+                delivery_id, id
+            ))
+        })?;
+    let delivery = state
+        .store
+        .create_delivery(id, original.event, &original.payload)
+        .await?;
+    state
+        .webhooks
+        .spawn(state.store.clone(), webhook, delivery.clone());
+    Ok((StatusCode::ACCEPTED, Json(delivery)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    };
+
+    use axum::{
+        http::{header, HeaderMap},
+        routing::post,
+        Router,
+    };
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        store::{MemoryStore, MemoryWorkspaces},
+        test_util::{json_body, request, retry_policy, seeded_store, test_app, token, with_header},
+    };
+
+    #[test]
+    fn backoff_doubles() {
+        let retry = RetryPolicy {
+            base_delay: Duration::from_millis(100),
+            ..RetryPolicy::default()
+        };
+        assert_eq!(retry.delay(1), Duration::from_millis(100));
+        assert_eq!(retry.delay(2), Duration::from_millis(200));
+        assert_eq!(retry.delay(4), Duration::from_millis(800));
+    }
+
+    #[test]
+    fn signatures() {
+        // From the HMAC-SHA256 test vectors of RFC 4231.
+        assert_eq!(
+            sign("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[tokio::test]
+    async fn drain_stops_retrying() {
+        let store: Arc<dyn NoteStore> = Arc::new(MemoryStore::new());
+        let input = WebhookInput {
+            // Nothing listens on the discard port.
+            url: "http://127.0.0.1:9/hook".into(),
+            events: BTreeSet::new(),
+            secret: "secret".into(),
+        };
+        let webhook = store.create_webhook(1, input).await.unwrap();
+        let payload = serde_json::json!({});
+        let delivery = store
+            .create_delivery(webhook.id, EventKind::Created, &payload)
+            .await
+            .unwrap();
+        let client = Arc::new(
+            WebhookClient::new(RetryPolicy {
+                max_attempts: 5,
+                base_delay: Duration::from_secs(60),
+                timeout: Duration::from_secs(1),
+            })
+            .with_private_targets(true),
+        );
+        client.spawn(store.clone(), webhook, delivery.clone());
+
+        assert!(client.drain(Duration::from_secs(5)).await);
+        let delivery = store.delivery(delivery.id).await.unwrap().unwrap();
+        assert_eq!(delivery.status, DeliveryStatus::Pending);
+        assert_eq!(delivery.attempts, 1);
+    }
+
+    #[tokio::test]
+    async fn deleting_the_webhook_stops_retries() {
+        let store: Arc<dyn NoteStore> = Arc::new(MemoryStore::new());
+        let hits = Arc::new(AtomicUsize::new(0));
+        // Fails every request, deleting the webhook on the first one.
+        let hook = {
+            let (store, hits) = (store.clone(), hits.clone());
+            axum::routing::post(move || async move {
+                hits.fetch_add(1, Ordering::SeqCst);
+                store.delete_webhook(1).await.unwrap();
+                StatusCode::INTERNAL_SERVER_ERROR
+            })
+        };
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let receiver = axum::Router::new().route("/hook", hook);
+        tokio::spawn(async move { axum::serve(listener, receiver).await.unwrap() });
+
+        let input = WebhookInput {
+            url,
+            events: BTreeSet::new(),
+            secret: "secret".into(),
+        };
+        let webhook = store.create_webhook(1, input).await.unwrap();
+        let payload = serde_json::json!({});
+        let delivery = store
+            .create_delivery(webhook.id, EventKind::Created, &payload)
+            .await
+            .unwrap();
+        let client = WebhookClient::new(retry_policy()).with_private_targets(true);
+        client
+            .deliver(store.as_ref(), &webhook, &delivery)
+            .await
+            .unwrap();
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+        assert_eq!(store.delivery(delivery.id).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn resumes_pending_deliveries() {
+        let store = Arc::new(MemoryStore::new());
+        let input = WebhookInput {
+            url: "http://127.0.0.1:9/hook".into(),
+            events: BTreeSet::new(),
+            secret: "secret".into(),
+        };
+        let webhook = store.create_webhook(1, input).await.unwrap();
+        let payload = serde_json::json!({});
+        let delivery = store
+            .create_delivery(webhook.id, EventKind::Created, &payload)
+            .await
+            .unwrap();
+        let attempt = Attempt {
+            status: DeliveryStatus::Pending,
+            response_status: None,
+            error: Some("stopped".into()),
+        };
+        store.record_attempt(delivery.id, attempt).await.unwrap();
+        let workspaces = Workspaces::new(store.clone(), Arc::new(MemoryWorkspaces));
+        let client = WebhookClient::new(RetryPolicy {
+            max_attempts: 2,
+            ..retry_policy()
+        })
+        .with_private_targets(true);
+
+        assert_eq!(client.resume(&workspaces).await.unwrap(), 1);
+        assert!(client.drain(Duration::from_secs(5)).await);
+        let delivery = store.delivery(delivery.id).await.unwrap().unwrap();
+        assert_eq!(delivery.status, DeliveryStatus::Failed);
+        assert_eq!(delivery.attempts, 2);
+    }
+
+    #[tokio::test]
+    async fn private_targets() {
+        let client = WebhookClient::new(retry_policy());
+        for url in [
+            "http://localhost:3000/hook",
+            "http://127.0.0.1/hook",
+            "http://10.1.2.3/hook",
+            "http://169.254.169.254/latest/meta-data",
+            "http://[::1]/hook",
+            "http://[::ffff:192.168.0.1]/hook",
+        ] {
+            let url = Url::parse(url).unwrap();
+            assert!(client.check_target(&url).await.is_err(), "{url} allowed");
+            let client = client.clone().with_private_targets(true);
+            assert_eq!(client.check_target(&url).await, Ok(()));
+        }
+        let url = Url::parse("https://93.184.215.14/hook").unwrap();
+        assert_eq!(client.check_target(&url).await, Ok(()));
+    }
+
+    #[test]
+    fn event_filter() {
+        let mut webhook = Webhook {
+            id: 1,
+            user_id: 1,
+            url: "http://localhost/hook".into(),
+            events: BTreeSet::new(),
+            secret: "secret".into(),
+            created_at: Utc::now(),
+        };
+        assert!(webhook.wants(EventKind::Deleted));
+        webhook.events.insert(EventKind::Created);
+        assert!(webhook.wants(EventKind::Created));
+        assert!(!webhook.wants(EventKind::Deleted));
+        webhook.events.insert("restored".parse().unwrap());
+        assert!(webhook.wants(EventKind::Restored));
+        assert!(!serde_json::to_string(&webhook).unwrap().contains("secret"));
+    }
+
+    /// Requests a webhook receiver got, with their headers and body.
+    type Received = Arc<Mutex<Vec<(HeaderMap, String)>>>;
+
+    /// Serves a webhook receiver on a free port that fails the first request
+    /// it gets and accepts the others. Returns its URL.
+    async fn webhook_receiver() -> (String, Received) {
+        let received = Received::default();
+        let log = received.clone();
+        let hook = post(move |headers: HeaderMap, body: String| {
+            let log = log.clone();
+            async move {
+                let mut log = log.lock().unwrap();
+                log.push((headers, body));
+                if log.len() == 1 {
+                    StatusCode::INTERNAL_SERVER_ERROR
+                } else {
+                    StatusCode::NO_CONTENT
+                }
+            }
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let receiver = Router::new().route("/hook", hook);
+        tokio::spawn(async move { axum::serve(listener, receiver).await.unwrap() });
+        (url, received)
+    }
+
+    /// Waits until webhook 1 has `count` deliveries and none of them is
+    /// pending, and returns them.
+    async fn settled_deliveries(app: &Router, count: usize) -> serde_json::Value {
+        for _ in 0..100 {
+            let response = app
+                .clone()
+                .oneshot(request("GET", "/v1/webhooks/1/deliveries", None))
+                .await
+                .unwrap();
+            let deliveries = json_body(response).await;
+            let list = deliveries.as_array().unwrap();
+            if list.len() == count && list.iter().all(|d| d["status"] != "pending") {
+                return deliveries;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("deliveries did not settle");
+    }
+
+    #[tokio::test]
+    async fn webhooks() {
+        let app = test_app(seeded_store().await);
+        let (url, received) = webhook_receiver().await;
+
+        let invalid = json!({"url": "ftp://localhost/hook", "secret": "s3cret"});
+        let response = app
+            .clone()
+            .oneshot(request("POST", "/v1/webhooks", Some(invalid)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let webhook = json!({"url": url, "events": ["created", "deleted"], "secret": "s3cret"});
+        let response = app
+            .clone()
+            .oneshot(request("POST", "/v1/webhooks", Some(webhook)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let webhook = json_body(response).await;
+        assert_eq!(webhook["id"], 1);
+        assert!(webhook.get("secret").is_none());
+
+        // Updates are filtered out, so only the creation is delivered.
+        let created = json!({"title": "hooked", "note": "n"});
+        app.clone()
+            .oneshot(request("POST", "/v1/notes", Some(created)))
+            .await
+            .unwrap();
+        let updated = json!({"title": "test_title", "note": "changed"});
+        app.clone()
+            .oneshot(request("PUT", "/v1/notes/1", Some(updated)))
+            .await
+            .unwrap();
+        let deliveries = settled_deliveries(&app, 1).await;
+        let delivery = &deliveries[0];
+        assert_eq!(delivery["status"], "succeeded");
+        assert_eq!(delivery["attempts"], 2);
+        assert_eq!(delivery["response_status"], 204);
+        assert_eq!(delivery["event"], "created");
+
+        let (headers, body) = received.lock().unwrap().last().cloned().unwrap();
+        assert_eq!(received.lock().unwrap().len(), 2);
+        assert_eq!(headers[EVENT_HEADER], "created");
+        assert_eq!(headers[DELIVERY_HEADER], delivery["id"].to_string());
+        assert_eq!(headers[SIGNATURE_HEADER], sign("s3cret", body.as_bytes()));
+        let payload: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(payload["kind"], "created");
+        assert_eq!(payload["note"]["title"], "hooked");
+
+        let uri = format!("/v1/webhooks/1/deliveries/{}/redeliver", delivery["id"]);
+        let response = app
+            .clone()
+            .oneshot(request("POST", &uri, None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let deliveries = settled_deliveries(&app, 2).await;
+        assert_eq!(deliveries[0]["status"], "succeeded");
+        assert_eq!(deliveries[0]["attempts"], 1);
+        assert_eq!(deliveries[0]["payload"], payload);
+        assert_eq!(received.lock().unwrap().len(), 3);
+
+        // Other users cannot see the webhook.
+        let response = app
+            .oneshot(with_header(
+                request("GET", "/v1/webhooks/1/deliveries", None),
+                header::AUTHORIZATION,
+                &format!("Bearer {}", token(2, "bob")),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,693 @@
+//! Workspaces: tenants sharing one instance without seeing each other's
+//! data.
+//!
+//! Every workspace has a store of its own, so notes, ids, tags, users and
+//! API keys are all scoped to it and lists and searches cannot reach across.
+//! A request picks its workspace with a `/w/{name}` path prefix or the
+//! `X-Workspace` header and goes to the default workspace otherwise. The
+//! admins of the default workspace create the other ones.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::Arc,
+};
+
+use axum::{
+    extract::{Request, State},
+    http::{HeaderName, StatusCode, Uri},
+    response::{IntoResponse, Response},
+    Router,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tower::ServiceExt;
+use utoipa::ToSchema;
+
+use crate::{
+    auth::{hash_credentials, Auth, AuthUser, Credentials, User},
+    config::{Features, Limits},
+    error::ApiError,
+    events::EventFeed,
+    extract::{Json, Path},
+    keys::Scope,
+    search::IndexedStore,
+    store::{NoteStore, StoreError, WorkspaceStores},
+    webhooks::{self, WebhookClient},
+    AppState,
+};
+
+pub const DEFAULT_WORKSPACE: &str = "default";
+pub const WORKSPACE_HEADER: HeaderName = HeaderName::from_static("x-workspace");
+const PATH_PREFIX: &str = "/w/";
+
+/// Workspace names are 3 to 32 lowercase letters, digits, `-` or `_`;
+/// anything else is `None`.
+pub fn normalize_name(name: &str) -> Option<String> {
+    let name = name.trim().to_lowercase();
+    let valid = (3..=32).contains(&name.len())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    valid.then_some(name)
+}
+
+/// The stores of every workspace, opened on first use.
+pub struct Workspaces {
+    stores: Arc<dyn WorkspaceStores>,
+    /// Every workspace opened so far, the default one included.
+    open: Mutex<HashMap<String, Arc<IndexedStore>>>,
+}
+
+impl Workspaces {
+    pub fn new(default: Arc<dyn NoteStore>, stores: Arc<dyn WorkspaceStores>) -> Self {
+        let default = Arc::new(IndexedStore::new(default));
+        Self {
+            stores,
+            open: Mutex::new(HashMap::from([(DEFAULT_WORKSPACE.to_owned(), default)])),
+        }
+    }
+
+    /// Returns the store of the workspace `name`, or `None` if there is no
+    /// such workspace.
+    pub async fn get(&self, name: &str) -> Result<Option<Arc<IndexedStore>>, StoreError> {
+        let mut open = self.open.lock().await;
+        if let Some(store) = open.get(name) {
+            return Ok(Some(store.clone()));
+        }
+        let Some(store) = self.stores.open(name, false)? else {
+            return Ok(None);
+        };
+        let store = Arc::new(IndexedStore::new(store));
+        open.insert(name.to_owned(), store.clone());
+        Ok(Some(store))
+    }
+
+    /// The store of the default workspace, which is always open.
+    pub async fn default_store(&self) -> Arc<IndexedStore> {
+        self.open.lock().await[DEFAULT_WORKSPACE].clone()
+    }
+
+    /// Creates the workspace `name` with an empty store and `username` as its
+    /// admin, returning both. Fails with [`StoreError::WorkspaceExists`] if
+    /// there already is one. A workspace whose admin cannot be created is
+    /// removed again, so that it is not left without one.
+    pub async fn create(
+        &self,
+        name: &str,
+        username: &str,
+        password_hash: &str,
+    ) -> Result<(Arc<IndexedStore>, User), StoreError> {
+        let mut open = self.open.lock().await;
+        if open.contains_key(name) || self.stores.names()?.iter().any(|n| n == name) {
+            return Err(StoreError::WorkspaceExists);
+        }
+        let store = self.stores.open(name, true)?.ok_or_else(|| {
+            StoreError::Io(std::io::Error::other(format!(
+                "workspace {name} was created but cannot be opened"
+            )))
+        })?;
+        let admin = match store.create_user(username, password_hash, true).await {
+            Ok(admin) => admin,
+            Err(e) => {
+                drop(store);
+                if let Err(removal) = self.stores.remove(name) {
+                    tracing::error!(
+                        workspace = name,
+                        error = %removal,
+                        "failed to remove a workspace left without an admin"
+                    );
+                }
+                return Err(e);
+            }
+        };
+        let store = Arc::new(IndexedStore::new(store));
+        open.insert(name.to_owned(), store.clone());
+        Ok((store, admin))
+    }
+
+    /// Returns the names of every workspace, the default one included, in
+    /// order.
+    pub async fn names(&self) -> Result<Vec<String>, StoreError> {
+        let open = self.open.lock().await;
+        let mut names: BTreeSet<String> = self.stores.names()?.into_iter().collect();
+        names.extend(open.keys().cloned());
+        Ok(names.into_iter().collect())
+    }
+
+    /// Opens the store of every workspace.
+    pub async fn stores(&self) -> Result<Vec<Arc<IndexedStore>>, StoreError> {
+        let mut stores = Vec::new();
+        for name in self.names().await? {
+            stores.extend(self.get(&name).await?);
+        }
+        Ok(stores)
+    }
+
+    /// The stores of the workspaces opened so far.
+    pub async fn opened(&self) -> Vec<Arc<IndexedStore>> {
+        self.open.lock().await.values().cloned().collect()
+    }
+
+    /// Flushes the store of every workspace opened so far. All of them are
+    /// flushed even if one fails, and the first error is returned.
+    pub async fn flush(&self) -> Result<(), StoreError> {
+        let mut result = Ok(());
+        for store in self.opened().await {
+            if let Err(e) = store.flush().await {
+                result = result.and(Err(e));
+            }
+        }
+        result
+    }
+}
+
+/// Hands every request to the router of its workspace, built on first use.
+struct Dispatcher {
+    workspaces: Arc<Workspaces>,
+    auth: Auth,
+    webhooks: Arc<WebhookClient>,
+    features: Features,
+    limits: Limits,
+    routers: Mutex<HashMap<String, Router>>,
+}
+
+impl Dispatcher {
+    async fn router(&self, name: &str) -> Result<Router, ApiError> {
+        let mut routers = self.routers.lock().await;
+        if let Some(router) = routers.get(name) {
+            return Ok(router.clone());
+        }
+        let store = self
+            .workspaces
+            .get(name)
+            .await?
+            .ok_or_else(|| ApiError::NotFound(format!("Workspace {} not found", name)))?;
+        let state = AppState {
+            store: store.clone(),
+            search: store,
+            auth: Arc::new(self.auth.for_workspace(name)),
+            events: Arc::new(if self.features.events {
+                EventFeed::new()
+            } else {
+                EventFeed::unordered()
+            }),
+            webhooks: self.webhooks.clone(),
+            workspace: name.to_owned(),
+            workspaces: self.workspaces.clone(),
+        };
+        if self.features.webhooks {
+            webhooks::spawn_dispatcher(state.clone());
+        }
+        let router = crate::router(state, self.features, self.limits);
+        routers.insert(name.to_owned(), router.clone());
+        Ok(router)
+    }
+}
+
+/// The router dispatching to every workspace. Tokens are signed with the
+/// keys of `auth`, and webhooks of every workspace are sent by `webhooks`.
+/// Every workspace serves the same `features` within the same `limits`.
+pub(crate) fn dispatcher(
+    workspaces: Arc<Workspaces>,
+    auth: Auth,
+    webhooks: Arc<WebhookClient>,
+    features: Features,
+    limits: Limits,
+) -> Router {
+    Router::new()
+        .fallback(dispatch)
+        .with_state(Arc::new(Dispatcher {
+            workspaces,
+            auth,
+            webhooks,
+            features,
+            limits,
+            routers: Mutex::new(HashMap::new()),
+        }))
+}
+
+async fn dispatch(
+    State(dispatcher): State<Arc<Dispatcher>>,
+    mut request: Request,
+) -> Result<Response, ApiError> {
+    let name = select(&mut request)?;
+    tracing::Span::current().record("workspace", name.as_str());
+    let router = dispatcher.router(&name).await?;
+    Ok(router
+        .oneshot(request)
+        .await
+        .unwrap_or_else(|never| match never {}))
+}
+
+/// Works out which workspace `request` is for, taking the `/w/{name}`
+/// prefix off its path.
+fn select(request: &mut Request) -> Result<String, ApiError> {
+    let name = if let Some(rest) = request.uri().path().strip_prefix(PATH_PREFIX) {
+        let (name, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+        let name = name.to_owned();
+        let path_and_query = match request.uri().query() {
+            Some(query) => format!("/{}?{}", path.trim_start_matches('/'), query),
+            None => format!("/{}", path.trim_start_matches('/')),
+        };
+        let mut parts = request.uri().clone().into_parts();
+        parts.path_and_query = Some(
+            path_and_query
+                .parse()
+                .map_err(|_| ApiError::BadRequest("Invalid path".into()))?,
+        );
+        *request.uri_mut() =
+            Uri::from_parts(parts).map_err(|_| ApiError::BadRequest("Invalid path".into()))?;
+        name
+    } else if let Some(value) = request.headers().get(WORKSPACE_HEADER) {
+        String::from_utf8_lossy(value.as_bytes()).into_owned()
+    } else {
+        return Ok(DEFAULT_WORKSPACE.into());
+    };
+    normalize_name(&name)
+        .ok_or_else(|| ApiError::BadRequest(format!("Invalid workspace name `{}`", name)))
+}
+
+/// Checks that `user` is an admin of the workspace the request is for.
+async fn require_admin(state: &AppState, user: &AuthUser) -> Result<(), ApiError> {
+    user.require(Scope::Admin)?;
+    let is_admin = state
+        .store
+        .get_user(user.id)
+        .await?
+        .is_some_and(|user| user.is_admin);
+    if !is_admin {
+        return Err(ApiError::Forbidden(
+            "Only workspace admins can do this".into(),
+        ));
+    }
+    Ok(())
+}
+
+fn require_default(state: &AppState) -> Result<(), ApiError> {
+    if state.workspace != DEFAULT_WORKSPACE {
+        return Err(ApiError::Forbidden(
+            "Workspaces are managed from the default workspace".into(),
+        ));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct WorkspaceInput {
+    pub name: String,
+    /// The first user of the workspace, who becomes its admin.
+    pub admin: Credentials,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct NewWorkspace {
+    pub name: String,
+    pub admin: User,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct AdminInput {
+    pub admin: bool,
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/workspaces",
+    tag = "workspaces",
+    responses(
+        (status = 200, description = "Names of every workspace", body = [String]),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Not allowed for the caller", body = ErrorBody),
+    ),
+)]
+pub async fn list_workspaces(
+    State(state): State<AppState>,
+    user: AuthUser,
+) -> Result<Json<Vec<String>>, ApiError> {
+    require_default(&state)?;
+    require_admin(&state, &user).await?;
+    Ok(Json(state.workspaces.names().await?))
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/workspaces",
+    tag = "workspaces",
+    request_body = WorkspaceInput,
+    responses(
+        (status = 201, description = "The new workspace and its admin", body = NewWorkspace),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Not allowed for the caller", body = ErrorBody),
+        (status = 409, description = "Workspace exists", body = ErrorBody),
+        (status = 422, description = "Invalid input", body = ErrorBody),
+    ),
+)]
+pub async fn create_workspace(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Json(payload): Json<WorkspaceInput>,
+) -> Result<impl IntoResponse, ApiError> {
+    require_default(&state)?;
+    require_admin(&state, &user).await?;
+    let name = normalize_name(&payload.name).ok_or_else(|| {
+        ApiError::Unprocessable("Workspace name must be 3 to 32 letters, digits, `-` or `_`".into())
+    })?;
+    let (username, hash) = hash_credentials(payload.admin).await?;
+    let (_, admin) = state.workspaces.create(&name, &username, &hash).await?;
+    tracing::info!(
+        user_id = user.id,
+        workspace = %name,
+        admin_id = admin.id,
+        "workspace created"
+    );
+    Ok((StatusCode::CREATED, Json(NewWorkspace { name, admin })))
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/users",
+    tag = "workspaces",
+    responses(
+        (status = 200, description = "Users of the workspace", body = [User]),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Not allowed for the caller", body = ErrorBody),
+    ),
+)]
+pub async fn list_users(
+    State(state): State<AppState>,
+    user: AuthUser,
+) -> Result<Json<Vec<User>>, ApiError> {
+    require_admin(&state, &user).await?;
+    Ok(Json(state.store.list_users().await?))
+}
+
+/// Makes a user of the workspace an admin or takes that away. Admins cannot
+/// demote themselves, so a workspace always keeps one.
+#[utoipa::path(
+    put,
+    path = "/v1/users/{id}/admin",
+    tag = "workspaces",
+    params(("id" = u32, Path, description = "User id")),
+    request_body = AdminInput,
+    responses(
+        (status = 200, description = "The updated user", body = User),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Not allowed for the caller", body = ErrorBody),
+        (status = 404, description = "User not found", body = ErrorBody),
+        (status = 422, description = "Invalid input", body = ErrorBody),
+    ),
+)]
+pub async fn set_admin(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Path(id): Path<u32>,
+    Json(payload): Json<AdminInput>,
+) -> Result<Json<User>, ApiError> {
+    require_admin(&state, &user).await?;
+    if id == user.id && !payload.admin {
+        return Err(ApiError::Unprocessable(
+            "Admins cannot demote themselves".into(),
+        ));
+    }
+    let updated = state
+        .store
+        .set_admin(id, payload.admin)
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("User {} not found", id)))?;
+    tracing::info!(
+        user_id = user.id,
+        target_id = id,
+        admin = payload.admin,
+        "admin role changed"
+    );
+    Ok(Json(updated))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{header, StatusCode};
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        store::{MemoryStore, MemoryWorkspaces},
+        test_util::{json_body, request, seeded_store, test_app, token, with_header},
+    };
+
+    #[test]
+    fn names() {
+        assert_eq!(normalize_name(" Team-1 ").as_deref(), Some("team-1"));
+        assert_eq!(normalize_name("ab"), None);
+        assert_eq!(normalize_name("a/b/c"), None);
+    }
+
+    #[test]
+    fn selects_workspace() {
+        let mut request = Request::get("/w/Team/v1/notes?limit=2")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        assert_eq!(select(&mut request).unwrap(), "team");
+        assert_eq!(request.uri(), "/v1/notes?limit=2");
+
+        let mut request = Request::get("/w/team")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        assert_eq!(select(&mut request).unwrap(), "team");
+        assert_eq!(request.uri(), "/");
+
+        let mut request = Request::get("/v1/notes")
+            .header(WORKSPACE_HEADER, "team")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        assert_eq!(select(&mut request).unwrap(), "team");
+        assert_eq!(request.uri(), "/v1/notes");
+
+        let mut request = Request::get("/v1/notes")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        assert_eq!(select(&mut request).unwrap(), DEFAULT_WORKSPACE);
+    }
+
+    /// Stores that cannot be created.
+    struct Broken;
+
+    impl WorkspaceStores for Broken {
+        fn open(&self, _: &str, _: bool) -> Result<Option<Arc<dyn NoteStore>>, StoreError> {
+            Ok(None)
+        }
+
+        fn names(&self) -> Result<Vec<String>, StoreError> {
+            Ok(Vec::new())
+        }
+
+        fn remove(&self, _: &str) -> Result<(), StoreError> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn create_fails_without_panicking() {
+        let workspaces = Workspaces::new(Arc::new(MemoryStore::new()), Arc::new(Broken));
+        assert!(matches!(
+            workspaces.create("team", "admin", "hash").await,
+            Err(StoreError::Io(_))
+        ));
+    }
+
+    /// Stores that all share one store, where the username `taken` is.
+    #[derive(Default)]
+    struct Shared {
+        store: Arc<MemoryStore>,
+        removed: std::sync::Mutex<Vec<String>>,
+    }
+
+    impl WorkspaceStores for Shared {
+        fn open(&self, _: &str, _: bool) -> Result<Option<Arc<dyn NoteStore>>, StoreError> {
+            Ok(Some(self.store.clone()))
+        }
+
+        fn names(&self) -> Result<Vec<String>, StoreError> {
+            Ok(Vec::new())
+        }
+
+        fn remove(&self, name: &str) -> Result<(), StoreError> {
+            self.removed.lock().unwrap().push(name.to_owned());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn create_removes_workspace_without_admin() {
+        let stores = Arc::new(Shared::default());
+        stores
+            .store
+            .create_user("taken", "hash", false)
+            .await
+            .unwrap();
+        let workspaces = Workspaces::new(Arc::new(MemoryStore::new()), stores.clone());
+        assert!(matches!(
+            workspaces.create("team", "taken", "hash").await,
+            Err(StoreError::UsernameTaken)
+        ));
+        assert_eq!(*stores.removed.lock().unwrap(), vec!["team"]);
+        assert_eq!(workspaces.opened().await.len(), 1);
+        let (_, admin) = workspaces.create("other", "admin", "hash").await.unwrap();
+        assert!(admin.is_admin);
+    }
+
+    #[tokio::test]
+    async fn registry() {
+        let workspaces = Workspaces::new(Arc::new(MemoryStore::new()), Arc::new(MemoryWorkspaces));
+        assert!(workspaces.get("team").await.unwrap().is_none());
+        workspaces.create("team", "admin", "hash").await.unwrap();
+        assert!(matches!(
+            workspaces.create("team", "admin", "hash").await,
+            Err(StoreError::WorkspaceExists)
+        ));
+        assert!(workspaces.get("team").await.unwrap().is_some());
+        assert_eq!(workspaces.names().await.unwrap(), vec!["default", "team"]);
+        assert_eq!(workspaces.stores().await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn workspaces() {
+        let app = test_app(seeded_store().await);
+        let admin = json!({"username": "carol", "password": "correct horse"});
+        let response = app
+            .clone()
+            .oneshot(request(
+                "POST",
+                "/v1/workspaces",
+                Some(json!({"name": "Team", "admin": admin})),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let created = json_body(response).await;
+        assert_eq!(created["name"], "team");
+        assert_eq!(created["admin"]["id"], 1);
+        assert_eq!(created["admin"]["is_admin"], true);
+
+        // Alice's token is only good in the default workspace.
+        let response = app
+            .clone()
+            .oneshot(request("GET", "/w/team/v1/notes", None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = app
+            .clone()
+            .oneshot(request("POST", "/w/team/v1/auth/login", Some(admin)))
+            .await
+            .unwrap();
+        let bearer = format!(
+            "Bearer {}",
+            json_body(response).await["access_token"].as_str().unwrap()
+        );
+        let as_carol = |req| {
+            let req = with_header(req, header::AUTHORIZATION, &bearer);
+            with_header(req, WORKSPACE_HEADER, "team")
+        };
+        let response = app
+            .clone()
+            .oneshot(as_carol(request(
+                "POST",
+                "/v1/notes",
+                Some(json!({"title": "team note", "note": "n"})),
+            )))
+            .await
+            .unwrap();
+        assert_eq!(json_body(response).await["id"], 1);
+        let response = app
+            .clone()
+            .oneshot(as_carol(request("GET", "/v1/search?q=note", None)))
+            .await
+            .unwrap();
+        assert_eq!(json_body(response).await["total"], 1);
+        let response = app
+            .clone()
+            .oneshot(request("GET", "/v1/notes/1", None))
+            .await
+            .unwrap();
+        assert_eq!(json_body(response).await["title"], "test_title");
+        let response = app
+            .clone()
+            .oneshot(request("GET", "/v1/search?q=team", None))
+            .await
+            .unwrap();
+        assert_eq!(json_body(response).await["total"], 0);
+
+        let response = app
+            .clone()
+            .oneshot(as_carol(request("GET", "/v1/users", None)))
+            .await
+            .unwrap();
+        assert_eq!(json_body(response).await[0]["username"], "carol");
+        let response = app
+            .clone()
+            .oneshot(as_carol(request("GET", "/v1/workspaces", None)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = app
+            .clone()
+            .oneshot(request("GET", "/v1/workspaces", None))
+            .await
+            .unwrap();
+        assert_eq!(json_body(response).await, json!(["default", "team"]));
+        let response = app
+            .oneshot(request("GET", "/w/nope/v1/notes", None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn workspace_admins() {
+        let store = seeded_store().await;
+        store.create_user("bob", "not a hash", false).await.unwrap();
+        let app = test_app(store);
+        let as_bob = |req| {
+            with_header(
+                req,
+                header::AUTHORIZATION,
+                &format!("Bearer {}", token(2, "bob")),
+            )
+        };
+        let response = app
+            .clone()
+            .oneshot(as_bob(request("GET", "/v1/users", None)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = app
+            .clone()
+            .oneshot(request(
+                "PUT",
+                "/v1/users/2/admin",
+                Some(json!({"admin": true})),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(json_body(response).await["is_admin"], true);
+        let response = app
+            .clone()
+            .oneshot(as_bob(request("GET", "/v1/users", None)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = app
+            .oneshot(request(
+                "PUT",
+                "/v1/users/1/admin",
+                Some(json!({"admin": false})),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+}